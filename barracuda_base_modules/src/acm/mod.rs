@@ -4,9 +4,12 @@ This module contains the basic data for generic ACM modules
 
 */
 
-#[derive(Clone)]
+use serde::{Serialize, Deserialize};
+use barracuda_core::core::sud::Sud;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct WhitelistAccessRequest
 {
     pub identity_token_number: Vec<u8>,
-    pub access_point_id: u32
+    pub access_point_id: Sud
 }
\ No newline at end of file
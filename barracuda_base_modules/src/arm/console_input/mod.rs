@@ -1,21 +1,32 @@
 use std::{io, thread};
 
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager}, trace::trace_helper};
+use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop, handle_system_message}, dependencies::Dependencies, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, module_thread::spawn_module, sud::Sud}, trace::trace_helper};
 
-use crate::{acm::WhitelistAccessRequest, modcaps::{ModuleCapability, ModuleCapabilityAdvertisement}};
+use crate::{acm::WhitelistAccessRequest, modcaps::{ModuleCapability, ModuleCapabilityAdvertisement, ACCESS_POINTS}};
 
 
 const MODULE_ID: u32 = 0x04000000;
 
 pub fn launch(chm: &mut ChannelManager)
 {    
-    let tracer = trace_helper::TraceHelper::new("ARM/ConsoleInput".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("ARM/ConsoleInput".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut wl = ConsoleInput::new(tracer, chm);
-    thread::spawn(move || {  
+    spawn_module(MODULE_ID, "ARM/ConsoleInput", chm, move || {  
         wl.init();   
+
+        // Reading stdin blocks, so this is done by a thread of its
+        // own, leaving this thread free to answer the system channel.
+        let request_tx = wl.access_request_tx.clone();
+        thread::spawn(move || {
+            loop
+            {
+                ConsoleInput::do_request(&request_tx);
+            }
+        });
+
         loop 
         {
-            if !wl.do_request()
+            if !wl.do_system_event()
             {
                 break;
             }
@@ -53,12 +64,12 @@ impl ConsoleInput
         let hlicb= Some(move|| {
             let m = ModuleCapabilityAdvertisement {
                 caps: vec![ModuleCapability::AccessPoints(1)],
-                module_id: MODULE_ID
+                module_id: Sud::from(MODULE_ID)
             };
             modcaps_tx_clone.send(m);            
         });
 
-        boot(MODULE_ID, Some(boot_noop), hlicb, 
+        boot(MODULE_ID, Dependencies::new(&[ACCESS_POINTS], &[]), Some(boot_noop), hlicb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
@@ -66,7 +77,13 @@ impl ConsoleInput
         // crate::core::bootstage_helper::plain_boot(MODULE_ID, self.system_events_tx.clone(), self.system_events_rx.clone(), &self.tracer);        
     }
 
-    pub fn do_request(&mut self) -> bool
+    pub fn do_system_event(&self) -> bool
+    {
+        let msg = self.system_events_rx.receive();
+        handle_system_message(MODULE_ID, msg, &self.system_events_tx)
+    }
+
+    pub fn do_request(access_request_tx: &GenericSender<WhitelistAccessRequest>)
     {
 
         let mut input = String::new();
@@ -74,13 +91,12 @@ impl ConsoleInput
             Ok(_) => {
                 let req = WhitelistAccessRequest
                 {
-                    access_point_id: MODULE_ID,      // Access point 1, i.e. index 0
+                    access_point_id: Sud::from(MODULE_ID),      // Access point 1, i.e. index 0
                     identity_token_number: input.into_bytes()
                 };
-                self.access_request_tx.send(req);
+                access_request_tx.send(req);
             }
             Err(error) => println!("error: {}", error),
         }
-        true
     }
 }
\ No newline at end of file
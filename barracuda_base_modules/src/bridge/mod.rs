@@ -0,0 +1,643 @@
+/*
+
+The bridge exposes channels of the bus to other processes by
+means of a Unix domain socket, so tools and modules that are
+not compiled into the binary (and not even written in Rust)
+can publish messages or subscribe to them.
+
+*/
+
+use std::{collections::{HashMap, HashSet}, fs, io::{self, BufRead, BufReader, Write}, net::Shutdown, os::unix::{fs::PermissionsExt, net::{UnixListener, UnixStream}}, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread, time::Duration};
+use serde::{Serialize, Deserialize};
+
+use barracuda_core::{core::{bootstage_helper::boot_noop, dependencies::Dependencies, broadcast_channel::{ChannelImpl, ChannelSettings, GenericReceiver, GenericSender, OverflowPolicy, make_receiver, make_sender}, channel_manager::ChannelManager, event::DataEvent, module_thread::spawn_module}, trace::trace_helper::{self, TraceHelper}};
+
+use crate::{bus::{SerializedChannel, open_channel}, modulebase::ModuleBase};
+
+const MODULE_ID: u32 = 0x10000000;
+
+/// Time a client has to accept a frame, clients that
+/// do not keep up are disconnected.
+const WRITE_TIMEOUT: u64 = 1000;     // in ms!
+
+/// Max. number of frames queued for a client, a client whose
+/// queue is full is disconnected.
+const CLIENT_QUEUE_CAPACITY: usize = 256;
+
+/// Types that operate doors or outputs, or grant access, have
+/// to be bridged explicitly, clients would bypass the access
+/// control otherwise. By default clients may only subscribe to
+/// events.
+#[derive(Clone, Debug)]
+pub struct BridgeSettings
+{
+    pub socket_path: String,
+    pub message_types: Vec<String>,     // Bridged types, see bus::MESSAGE_TYPES
+    pub publishable_types: Vec<String>  // Bridged types clients may publish as well
+}
+
+impl Default for BridgeSettings
+{
+    fn default() -> Self {
+        BridgeSettings
+        {
+            socket_path: "./barracuda.sock".to_string(),
+            message_types: vec!["InputEvent".to_string(), "LogEvent".to_string()],
+            publishable_types: Vec::new()
+        }
+    }
+}
+
+/// # A frame exchanged with a client
+/// Each frame is sent as a single line of JSON, e.g.
+/// {"Subscribe":"LogEvent"}
+/// {"Publish":["WhitelistAccessRequest",{"identity_token_number":[1,2,3],"access_point_id":1}]}
+/// The messages are encoded just like in the log of the recorder.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Frame
+{
+    Subscribe(String),                  // Client -> bridge: type name, the client receives all messages of the type afterwards
+    Unsubscribe(String),                // Client -> bridge: type name
+    Publish(String, serde_json::Value), // Client -> bridge: type name and message, the message is sent on the bus
+    Message(String, serde_json::Value), // Bridge -> client: type name and message of a subscribed type
+    Error(String)                       // Bridge -> client: describes a frame the bridge rejected
+}
+
+impl Frame
+{
+    pub fn to_line(&self) -> String
+    {
+        format!("{}\n", serde_json::to_string(self).unwrap())
+    }
+
+    /// Yields the line of a Message frame. The message was serialized
+    /// by its channel already, so it is written as is instead of
+    /// being parsed and serialized again.
+    pub fn message_line(type_name: &str, message: &str) -> String
+    {
+        format!("{{\"Message\":[{},{}]}}\n", serde_json::to_string(type_name).unwrap(), message)
+    }
+
+    pub fn parse(line: &str) -> Result<Frame, String>
+    {
+        serde_json::from_str(line.trim_end()).map_err(|e| format!("Invalid frame: {}", e))
+    }
+}
+
+pub fn launch(chm: &mut ChannelManager)
+{
+    launch_with_settings(chm, BridgeSettings::default());
+}
+
+pub fn launch_with_settings(chm: &mut ChannelManager, settings: BridgeSettings)
+{
+    let tracer = trace_helper::TraceHelper::new("DIAG/Bridge".to_string(), chm);
+    let mut bridge = Bridge::new(tracer, chm, settings);
+    spawn_module(MODULE_ID, "DIAG/Bridge", chm, move || {
+        bridge.init();
+        loop
+        {
+            if !bridge.run()
+            {
+                break;
+            }
+        }
+    });
+}
+
+/// Sent by the threads serving the socket to the bridge, which
+/// owns the state of all clients.
+#[derive(Clone)]
+enum ClientEvent
+{
+    Connected(u32, Arc<UnixStream>),
+    Received(u32, Frame),
+    Invalid(u32, String),       // Client ID and the reason the line was rejected
+    Disconnected(u32)
+}
+
+struct Client
+{
+    stream: Arc<UnixStream>,
+    writer: GenericSender<Option<Arc<String>>>,    // Lines to send, None stops the writer
+    subscriptions: HashSet<String>
+}
+
+impl Client
+{
+    fn new(stream: Arc<UnixStream>) -> Self
+    {
+        Client { writer: spawn_writer(stream.clone()), stream, subscriptions: HashSet::new() }
+    }
+
+    /// Queues the line for the writer of the client, fails
+    /// if the client does not keep up.
+    fn send(&self, line: Arc<String>) -> bool
+    {
+        self.writer.try_send(Some(line)).is_ok()
+    }
+
+    fn close(&self)
+    {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        // The writer fails on its next write if the queue is full.
+        let _ = self.writer.try_send(None);
+    }
+}
+
+/// Accepts clients until the bridge is stopped, each
+/// client is served by a thread of its own.
+fn listen(listener: UnixListener, client_events_tx: GenericSender<ClientEvent>, stopped: Arc<AtomicBool>)
+{
+    thread::spawn(move || {
+        let mut next_id = 0;
+        for stream in listener.incoming()
+        {
+            if stopped.load(Ordering::SeqCst)
+            {
+                break;
+            }
+            if let Ok(stream) = stream
+            {
+                next_id += 1;
+                serve(next_id, stream, client_events_tx.clone());
+            }
+        }
+    });
+}
+
+/// Reads the frames sent by a client, writing to the
+/// client is up to its writer, see spawn_writer.
+fn serve(id: u32, stream: UnixStream, client_events_tx: GenericSender<ClientEvent>)
+{
+    let _ = stream.set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT)));
+    let stream = Arc::new(stream);
+    client_events_tx.send(ClientEvent::Connected(id, stream.clone()));
+    thread::spawn(move || {
+        for line in BufReader::new(&*stream).lines()
+        {
+            match line
+            {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => match Frame::parse(&line)
+                {
+                    Ok(frame) => client_events_tx.send(ClientEvent::Received(id, frame)),
+                    Err(reason) => client_events_tx.send(ClientEvent::Invalid(id, reason))
+                },
+                Err(_) => break
+            }
+        }
+        client_events_tx.send(ClientEvent::Disconnected(id));
+    });
+}
+
+/// Writes the lines queued for a client on a thread of its own,
+/// so a client that stalls does not delay the bridge or other
+/// clients. A write that fails closes the stream, the reading
+/// thread reports the client as disconnected then.
+fn spawn_writer(stream: Arc<UnixStream>) -> GenericSender<Option<Arc<String>>>
+{
+    let queue = Arc::new(ChannelImpl::<Option<Arc<String>>>::with_settings(ChannelSettings::bounded(CLIENT_QUEUE_CAPACITY, OverflowPolicy::Error)));
+    let lines_rx = make_receiver(&queue);
+    thread::spawn(move || {
+        while let Some(line) = lines_rx.receive()
+        {
+            let mut writer = &*stream;
+            if writer.write_all(line.as_bytes()).is_err()
+            {
+                let _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+        }
+    });
+    make_sender(&queue)
+}
+
+struct Bridge
+{
+    module              : ModuleBase,
+    socket_path         : String,
+    channels            : Vec<Box<dyn SerializedChannel>>,
+    publishable_types   : HashSet<String>,
+    clients             : HashMap<u32, Client>,
+    client_events_rx    : GenericReceiver<ClientEvent>,
+    client_events_tx    : GenericSender<ClientEvent>,
+    stopped             : Arc<AtomicBool>
+}
+
+impl Bridge
+{
+    fn new(tracer: TraceHelper, chm: &mut ChannelManager, settings: BridgeSettings) -> Self
+    {
+        let mut channels = Vec::new();
+        for type_name in settings.message_types.iter()
+        {
+            // Channels receive only while clients subscribe to them.
+            match open_channel(type_name, false, chm)
+            {
+                Some(channel) => channels.push(channel),
+                None => tracer.trace(format!("Cannot bridge unknown message type {}", type_name))
+            }
+        }
+
+        // Each bridge uses the socket path as topic, so
+        // multiple bridges don't see each other's clients.
+        Bridge
+        {
+            module              : ModuleBase::new(MODULE_ID, Dependencies::new(&[], &[]), tracer, chm),
+            channels,
+            publishable_types   : settings.publishable_types.iter().cloned().collect(),
+            clients             : HashMap::new(),
+            client_events_rx    : chm.get_topic_receiver(&settings.socket_path),
+            client_events_tx    : chm.get_topic_sender(&settings.socket_path),
+            socket_path         : settings.socket_path,
+            stopped             : Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    fn init(&mut self)
+    {
+        let lli_cb = Some(|| {
+            if let Err(e) = self.start_listening()
+            {
+                self.module.tracer().trace(format!("Cannot listen on {}: {}", self.socket_path, e));
+            }
+        });
+        self.module.boot(lli_cb, Some(boot_noop));
+    }
+
+    /// Binds the socket, a socket file that was left over
+    /// by a previous run is replaced. Only the user running
+    /// the device may connect.
+    fn start_listening(&self) -> io::Result<()>
+    {
+        let _ = fs::remove_file(&self.socket_path);
+        let listener = UnixListener::bind(&self.socket_path)?;
+        fs::set_permissions(&self.socket_path, fs::Permissions::from_mode(0o600))?;
+        listen(listener, self.client_events_tx.clone(), self.stopped.clone());
+        Ok(())
+    }
+
+    fn run(&mut self) -> bool
+    {
+        // The channels hide their message types, so a Select
+        // cannot be used here, see Recorder.
+        let evt = Arc::new(DataEvent::<u32>::new("Bridge".to_string()));
+        for (index, channel) in self.channels.iter().enumerate()
+        {
+            channel.set_data_trigger(evt.clone(), index as u32);
+        }
+        let num_channels = self.channels.len() as u32;
+        self.client_events_rx.set_data_trigger(evt.clone(), num_channels);
+        self.module.system_events_rx.set_data_trigger(evt.clone(), num_channels + 1);
+        evt.wait();
+
+        while let Some(event) = self.client_events_rx.receive_with_timeout(0)
+        {
+            self.handle_client_event(event);
+        }
+
+        self.forward_pending();
+
+        if let Some(msg) = self.module.system_events_rx.receive_with_timeout(0)
+        {
+            if self.module.is_stop_request(&msg)
+            {
+                self.stop();
+            }
+            return self.module.handle_system_event(msg);
+        }
+        true
+    }
+
+    fn handle_client_event(&mut self, event: ClientEvent)
+    {
+        match event
+        {
+            ClientEvent::Connected(id, stream) => { self.clients.insert(id, Client::new(stream)); },
+            ClientEvent::Received(id, frame) => self.handle_frame(id, frame),
+            ClientEvent::Invalid(id, reason) => self.reply(id, Frame::Error(reason)),
+            ClientEvent::Disconnected(id) => { self.clients.remove(&id); }
+        }
+        self.update_receivers();
+    }
+
+    /// Opens the channels clients subscribed to and closes all
+    /// others, so messages nobody subscribed to are neither
+    /// queued nor serialized.
+    fn update_receivers(&mut self)
+    {
+        for channel in self.channels.iter_mut()
+        {
+            let subscribed = self.clients.values().any(|x| x.subscriptions.contains(channel.type_name()));
+            channel.set_receiving(subscribed);
+        }
+    }
+
+    fn handle_frame(&mut self, id: u32, frame: Frame)
+    {
+        let type_name = match &frame
+        {
+            Frame::Subscribe(type_name) | Frame::Unsubscribe(type_name) | Frame::Publish(type_name, _) => type_name.clone(),
+            _ => return self.reply(id, Frame::Error("Clients may only subscribe, unsubscribe and publish".to_string()))
+        };
+        let channel = match self.channels.iter().find(|x| x.type_name() == type_name)
+        {
+            Some(channel) => channel,
+            None => return self.reply(id, Frame::Error(format!("Message type {} is not bridged", type_name)))
+        };
+
+        if let Frame::Publish(_, payload) = frame
+        {
+            if !self.publishable_types.contains(&type_name)
+            {
+                return self.reply(id, Frame::Error(format!("Message type {} may not be published", type_name)));
+            }
+            if !channel.inject(&payload.to_string())
+            {
+                self.reply(id, Frame::Error(format!("Invalid {}: {}", type_name, payload)));
+            }
+            return;
+        }
+
+        if let Some(client) = self.clients.get_mut(&id)
+        {
+            if let Frame::Subscribe(_) = frame
+            {
+                client.subscriptions.insert(type_name);
+            }
+            else
+            {
+                client.subscriptions.remove(&type_name);
+            }
+        }
+    }
+
+    /// Sends a frame to a single client, a client that does
+    /// not keep up is disconnected.
+    fn reply(&mut self, id: u32, frame: Frame)
+    {
+        let failed = match self.clients.get(&id)
+        {
+            Some(client) => !client.send(Arc::new(frame.to_line())),
+            None => false
+        };
+        if failed
+        {
+            self.disconnect(id);
+        }
+    }
+
+    /// Sends all pending messages of the bridged channels to their
+    /// subscribers, see update_receivers.
+    fn forward_pending(&mut self)
+    {
+        let mut failed = Vec::new();
+        for channel in self.channels.iter()
+        {
+            while let Some(payload) = channel.take()
+            {
+                let line = Arc::new(Frame::message_line(channel.type_name(), &payload));
+                for (id, client) in self.clients.iter().filter(|(_, x)| x.subscriptions.contains(channel.type_name()))
+                {
+                    if !client.send(line.clone())
+                    {
+                        failed.push(*id);
+                    }
+                }
+            }
+        }
+
+        for id in failed
+        {
+            self.disconnect(id);
+        }
+        self.update_receivers();
+    }
+
+    fn disconnect(&mut self, id: u32)
+    {
+        if let Some(client) = self.clients.remove(&id)
+        {
+            self.module.tracer().trace(format!("Client {} does not accept frames, disconnected", id));
+            client.close();
+        }
+    }
+
+    /// Disconnects all clients and stops listening, the socket
+    /// file is removed.
+    fn stop(&mut self)
+    {
+        self.stopped.store(true, Ordering::SeqCst);
+        for (_, client) in self.clients.drain()
+        {
+            client.close();
+        }
+        // Wakes the listener, so it notices that the bridge was stopped.
+        let _ = UnixStream::connect(&self.socket_path);
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use barracuda_core::core::{SystemMessage, sud::Sud};
+    use crate::{acm::WhitelistAccessRequest, events::LogEvent};
+
+    struct TestClient
+    {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream
+    }
+
+    impl TestClient
+    {
+        fn connect(socket_path: &str) -> Self
+        {
+            let stream = UnixStream::connect(socket_path).unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(2000))).unwrap();
+            TestClient { writer: stream.try_clone().unwrap(), reader: BufReader::new(stream) }
+        }
+
+        fn send(&mut self, line: &str)
+        {
+            self.writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+        }
+
+        fn receive(&mut self) -> Frame
+        {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            Frame::parse(&line).unwrap()
+        }
+    }
+
+    fn start_bridge(chm: &mut ChannelManager, name: &str) -> (String, thread::JoinHandle<()>)
+    {
+        let socket_path = std::env::temp_dir().join(format!("barracuda-{}-{}.sock", name, std::process::id()))
+                                              .to_string_lossy()
+                                              .to_string();
+        let settings = BridgeSettings { socket_path: socket_path.clone(),
+                                        message_types: vec!["WhitelistAccessRequest".to_string(), "LogEvent".to_string()],
+                                        publishable_types: vec!["WhitelistAccessRequest".to_string()] };
+        let mut bridge = Bridge::new(TraceHelper::new("Test".to_string(), chm), chm, settings);
+        bridge.start_listening().unwrap();
+        let handle = thread::spawn(move || {
+            while bridge.run() {}
+        });
+        (socket_path, handle)
+    }
+
+    fn stop_bridge(chm: &mut ChannelManager, socket_path: &str, handle: thread::JoinHandle<()>)
+    {
+        chm.get_sender::<SystemMessage>().send(SystemMessage::ShutdownModule(MODULE_ID));
+        handle.join().unwrap();
+        assert!(!Path::new(socket_path).exists());
+    }
+
+    #[test]
+    fn frames_can_be_parsed()
+    {
+        let frame = Frame::parse(r#"{"Publish":["WhitelistAccessRequest",{"identity_token_number":[1,2],"access_point_id":7}]}"#).unwrap();
+        assert_eq!(Frame::Publish("WhitelistAccessRequest".to_string(), serde_json::json!({"identity_token_number": [1, 2], "access_point_id": 7})), frame);
+        assert_eq!(frame, Frame::parse(&frame.to_line()).unwrap());
+        assert!(Frame::parse("{\"Publish\":42}").is_err());
+    }
+
+    #[test]
+    fn message_lines_contain_the_message_as_is()
+    {
+        let line = Frame::message_line("LogEvent", r#"{"DoorBlocked":3}"#);
+        assert_eq!("{\"Message\":[\"LogEvent\",{\"DoorBlocked\":3}]}\n", line);
+        assert_eq!(Frame::Message("LogEvent".to_string(), serde_json::json!({"DoorBlocked": 3})), Frame::parse(&line).unwrap());
+    }
+
+    #[test]
+    fn clients_can_publish_and_subscribe()
+    {
+        let mut chm = ChannelManager::new();
+        let requests_rx = chm.get_receiver::<WhitelistAccessRequest>();
+        let (socket_path, handle) = start_bridge(&mut chm, "pubsub");
+
+        let mut client = TestClient::connect(&socket_path);
+        client.send(r#"{"Subscribe":"LogEvent"}"#);
+        client.send(r#"{"Publish":["WhitelistAccessRequest",{"identity_token_number":[1,2],"access_point_id":7}]}"#);
+
+        // Frames are handled in order, so the subscription is
+        // in place once the request arrived.
+        let request = requests_rx.receive_with_timeout(2000).unwrap();
+        assert_eq!((vec![1, 2], Sud::from(7)), (request.identity_token_number, request.access_point_id));
+
+        chm.get_sender::<LogEvent>().send(LogEvent::DoorBlocked(3));
+        assert_eq!(Frame::Message("LogEvent".to_string(), serde_json::json!({"DoorBlocked": 3})), client.receive());
+
+        stop_bridge(&mut chm, &socket_path, handle);
+    }
+
+    #[test]
+    fn actuator_and_access_types_are_not_bridged_by_default()
+    {
+        let settings = BridgeSettings::default();
+        for type_name in ["OutputSwitch", "DoorOpenRequest", "WhitelistAccessRequest", "PassagewayCommand"].iter()
+        {
+            assert!(!settings.message_types.contains(&type_name.to_string()));
+        }
+        assert!(settings.publishable_types.is_empty());
+    }
+
+    #[test]
+    fn only_the_owner_may_connect()
+    {
+        let mut chm = ChannelManager::new();
+        let (socket_path, handle) = start_bridge(&mut chm, "mode");
+        assert_eq!(0o600, fs::metadata(&socket_path).unwrap().permissions().mode() & 0o777);
+        stop_bridge(&mut chm, &socket_path, handle);
+    }
+
+    #[test]
+    fn channels_receive_only_while_subscribed()
+    {
+        let mut chm = ChannelManager::new();
+        let log_tx = chm.get_sender::<LogEvent>();
+        let (socket_path, handle) = start_bridge(&mut chm, "receivers");
+        assert_eq!(0, log_tx.num_receivers());
+
+        let mut client = TestClient::connect(&socket_path);
+        client.send(r#"{"Subscribe":"LogEvent"}"#);
+        // The bridge answers invalid frames only, so this one
+        // tells when the subscription was handled.
+        client.send("sync");
+        assert!(matches!(client.receive(), Frame::Error(_)));
+        assert_eq!(1, log_tx.num_receivers());
+
+        client.send(r#"{"Unsubscribe":"LogEvent"}"#);
+        client.send("sync");
+        assert!(matches!(client.receive(), Frame::Error(_)));
+        assert_eq!(0, log_tx.num_receivers());
+
+        stop_bridge(&mut chm, &socket_path, handle);
+    }
+
+    #[test]
+    fn stalled_client_does_not_delay_others()
+    {
+        // Far more than fits into the socket buffer and the queue of a client.
+        const NUM_EVENTS: usize = 10000;
+        const CHUNK: usize = 100;
+        let mut chm = ChannelManager::new();
+        let (socket_path, handle) = start_bridge(&mut chm, "stalled");
+
+        let mut stalled = TestClient::connect(&socket_path);
+        let mut client = TestClient::connect(&socket_path);
+        for c in [&mut stalled, &mut client].iter_mut()
+        {
+            c.send(r#"{"Subscribe":"LogEvent"}"#);
+            c.send("sync");
+            assert!(matches!(c.receive(), Frame::Error(_)));
+        }
+
+        // The client keeps up, each chunk is received before the next one is sent.
+        let started = std::time::Instant::now();
+        let log_tx = chm.get_sender::<LogEvent>();
+        for _ in 0..NUM_EVENTS / CHUNK
+        {
+            (0..CHUNK).for_each(|_| log_tx.send(LogEvent::DoorBlocked(3)));
+            for _ in 0..CHUNK
+            {
+                assert_eq!(Frame::Message("LogEvent".to_string(), serde_json::json!({"DoorBlocked": 3})), client.receive());
+            }
+        }
+        assert!(started.elapsed() < Duration::from_millis(WRITE_TIMEOUT));
+
+        // The stalled client was disconnected, it gets the frames
+        // queued so far and then the end of the stream.
+        let mut line = String::new();
+        while stalled.reader.read_line(&mut line).unwrap() > 0
+        {
+            line.clear();
+        }
+
+        stop_bridge(&mut chm, &socket_path, handle);
+    }
+
+    #[test]
+    fn invalid_frames_are_rejected()
+    {
+        let mut chm = ChannelManager::new();
+        let (socket_path, handle) = start_bridge(&mut chm, "invalid");
+
+        let mut client = TestClient::connect(&socket_path);
+        client.send("no json");
+        assert!(matches!(client.receive(), Frame::Error(_)));
+        client.send(r#"{"Subscribe":"InputEvent"}"#);
+        assert_eq!(Frame::Error("Message type InputEvent is not bridged".to_string()), client.receive());
+        client.send(r#"{"Publish":["WhitelistAccessRequest",42]}"#);
+        assert_eq!(Frame::Error("Invalid WhitelistAccessRequest: 42".to_string()), client.receive());
+        client.send(r#"{"Publish":["LogEvent",{"DoorBlocked":3}]}"#);
+        assert_eq!(Frame::Error("Message type LogEvent may not be published".to_string()), client.receive());
+
+        stop_bridge(&mut chm, &socket_path, handle);
+    }
+}
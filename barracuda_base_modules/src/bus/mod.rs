@@ -0,0 +1,106 @@
+/*
+
+Access to the channels of all message types that can be
+serialized, without knowing the actual message type. Used by
+modules that move messages off the bus and back onto it, i.e.
+the recorder and the bridge.
+
+*/
+
+use std::sync::Arc;
+use serde::{Serialize, de::DeserializeOwned};
+
+use barracuda_core::core::{broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, event::DataEvent};
+
+use crate::{acm::WhitelistAccessRequest, dcm::{DoorOpenRequest, PassagewayCommand}, events::LogEvent, io::{InputEvent, OutputSwitch}, sig::SigCommand};
+
+/// Names of all message types that can be opened using open_channel.
+pub const MESSAGE_TYPES: [&str; 7] = ["InputEvent", "OutputSwitch", "DoorOpenRequest", "PassagewayCommand", "SigCommand", "WhitelistAccessRequest", "LogEvent"];
+
+/// A channel of a serializable message type, hides the
+/// actual message type from its user.
+pub trait SerializedChannel: Send
+{
+    fn type_name(&self) -> &'static str;
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32);
+    /// Yields the next message as json, None if there is none
+    /// or the channel was opened for sending only.
+    fn take(&self) -> Option<String>;
+    /// Sends the message given as json, yields false if the
+    /// json does not contain a message of the channel's type.
+    fn inject(&self, payload: &str) -> bool;
+    /// Opens or closes the receiver of the channel, messages
+    /// sent while it is closed are not queued.
+    fn set_receiving(&mut self, receive: bool);
+}
+
+struct Channel<T: Clone>
+{
+    type_name: &'static str,
+    rx: Option<GenericReceiver<T>>,
+    tx: GenericSender<T>
+}
+
+impl <T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static> SerializedChannel for Channel<T>
+{
+    fn type_name(&self) -> &'static str
+    {
+        self.type_name
+    }
+
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32)
+    {
+        if let Some(rx) = self.rx.as_ref()
+        {
+            rx.set_data_trigger(evt, trigger_data)
+        }
+    }
+
+    fn take(&self) -> Option<String>
+    {
+        self.rx.as_ref()
+               .and_then(|rx| rx.receive_shared_with_timeout(0))
+               .and_then(|msg| serde_json::to_string(&*msg).ok())
+    }
+
+    fn inject(&self, payload: &str) -> bool
+    {
+        match serde_json::from_str::<T>(payload)
+        {
+            Ok(msg) => { self.tx.send(msg); true },
+            Err(_) => false
+        }
+    }
+
+    fn set_receiving(&mut self, receive: bool)
+    {
+        if receive != self.rx.is_some()
+        {
+            self.rx = if receive { Some(self.tx.create_receiver()) } else { None };
+        }
+    }
+}
+
+/// Opens the channel for the given type name (see MESSAGE_TYPES).
+/// Only channels that are opened for receiving queue the messages
+/// sent on them.
+pub fn open_channel(type_name: &str, receive: bool, chm: &mut ChannelManager) -> Option<Box<dyn SerializedChannel>>
+{
+    fn make<T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>(type_name: &'static str, receive: bool, chm: &mut ChannelManager) -> Option<Box<dyn SerializedChannel>>
+    {
+        let rx = if receive { Some(chm.get_receiver()) } else { None };
+        Some(Box::new(Channel::<T> { type_name, rx, tx: chm.get_sender() }))
+    }
+
+    match type_name
+    {
+        "InputEvent" => make::<InputEvent>("InputEvent", receive, chm),
+        "OutputSwitch" => make::<OutputSwitch>("OutputSwitch", receive, chm),
+        "DoorOpenRequest" => make::<DoorOpenRequest>("DoorOpenRequest", receive, chm),
+        "PassagewayCommand" => make::<PassagewayCommand>("PassagewayCommand", receive, chm),
+        "SigCommand" => make::<SigCommand>("SigCommand", receive, chm),
+        "WhitelistAccessRequest" => make::<WhitelistAccessRequest>("WhitelistAccessRequest", receive, chm),
+        "LogEvent" => make::<LogEvent>("LogEvent", receive, chm),
+        _ => None
+    }
+}
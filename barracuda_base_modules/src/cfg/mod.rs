@@ -19,6 +19,10 @@ value
 */
 pub mod cfgholder;
 
+// Name used to declare a dependency on the CfgHolder published by
+// the configuration module during its HLI (see core::dependencies).
+pub const CFG_HOLDER: &str = "CfgHolder";
+
 #[derive(Clone)]
 pub enum ConfigMessage
 {
@@ -1,4 +1,4 @@
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, shareable::Shareable}, trace::trace_helper};
+use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop, handle_system_message}, dependencies::Dependencies, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, module_thread::spawn_module, registry::ModuleRegistry, shareable::Shareable, rpc::Requester, sud::Sud}, trace::{TraceMessage, TraceQuery, filter::TraceLevelSetting, trace_helper}};
 use rouille::*;
 
 
@@ -7,15 +7,17 @@ use std::{thread};
 use std::io::Read;
 
 use crate::cfg::cfgholder::*;
+use crate::{Handler, ReadDataHandler, cfg};
 
 
 const MODULE_ID: u32 = 0x06000000;
+const TRACE_QUERY_TIMEOUT: u64 = 500;      // in ms!
 
 pub fn launch(chm: &mut ChannelManager)
 {        
-    let tracer = trace_helper::TraceHelper::new("CFG/Rest".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("CFG/Rest".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut cr = ConfigRest::new(tracer, chm);      
-    thread::spawn(move|| {
+    spawn_module(MODULE_ID, "CFG/Rest", chm, move|| {
         cr.init();  
         cr.run();         
     });
@@ -56,20 +58,27 @@ struct ConfigRest
     system_events_tx: GenericSender<SystemMessage>,
     cfg_publish_tx: GenericSender<crate::cfg::ConfigMessage>,
     cfg: Shareable<crate::cfg::cfgholder::CfgHolder>,
-    
+    registry_rx: GenericReceiver<Shareable<ModuleRegistry>>,
+    chm: ChannelManager
+
 }
 
 impl ConfigRest
 {
     fn new(trace: trace_helper::TraceHelper, chm: &mut ChannelManager) -> Self
     {
+        // Modules that are launched or rebooted after HLI still
+        // get the handlers to register their routes with.
+        chm.retain_messages::<crate::cfg::ConfigMessage>(1);
         ConfigRest
         {
             tracer: trace,            
             system_events_rx: chm.get_receiver(),
             system_events_tx: chm.get_sender(),
             cfg_publish_tx: chm.get_sender(),
-            cfg: Shareable::new(CfgHolder::new())
+            cfg: Shareable::new(CfgHolder::new()),
+            registry_rx: chm.get_receiver(),
+            chm: chm.clone()
         }
 
     }
@@ -78,11 +87,47 @@ impl ConfigRest
     {
         let the_sender = self.cfg_publish_tx.clone();
         let the_cfg = self.cfg.clone();
+        let registry_rx = &self.registry_rx;
+        let reboot_tx = self.system_events_tx.clone();
+        let shutdown_tx = self.system_events_tx.clone();
+        let trace_level_tx = self.chm.get_sender::<TraceLevelSetting>();
+        let bus = self.chm.clone();
+        let trace_requester = Requester::<TraceQuery, Vec<TraceMessage>>::new(&mut self.chm);
         let hlicb = Some(move|| {
+            the_cfg.lock().register_handler(FunctionType::Put, "reboot".to_string(), Handler!(|module_id: u32|
+                {
+                    reboot_tx.send(SystemMessage::Reboot(module_id));
+                }));
+            the_cfg.lock().register_handler(FunctionType::Put, "shutdown".to_string(), move |_| 
+                {
+                    shutdown_tx.send(SystemMessage::Shutdown);
+                });
+            the_cfg.lock().register_handler(FunctionType::Put, "trace/level".to_string(), Handler!(|setting: TraceLevelSetting|
+                {
+                    trace_level_tx.send(setting);
+                }));
+            // The supervisor publishes the module registry right after
+            // all modules synced, so it is available by now.
+            if let Some(registry) = registry_rx.receive_with_timeout(0)
+            {
+                the_cfg.lock().register_read_handler("modules".to_string(), ReadDataHandler!(||
+                    {
+                        registry.lock().modules()
+                    }));
+            }
+            the_cfg.lock().register_read_handler("bus/metrics".to_string(), ReadDataHandler!(||
+                {
+                    bus.metrics()
+                }));
+            the_cfg.lock().register_read_handler("trace/records".to_string(), ReadDataHandler!(||
+                {
+                    // Empty if SYS/Trace does not keep records
+                    trace_requester.request(TraceQuery, TRACE_QUERY_TIMEOUT).unwrap_or_default()
+                }));
             the_sender.send(super::ConfigMessage::RegisterHandlers(the_cfg))
         });
 
-        boot(MODULE_ID, Some(boot_noop), hlicb, 
+        boot(MODULE_ID, Dependencies::new(&[cfg::CFG_HOLDER], &[]), Some(boot_noop), hlicb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
@@ -138,6 +183,27 @@ impl ConfigRest
 
     pub fn run(self) -> bool
     {
+        // The server never returns, so the system channel is served
+        // by a thread of its own.
+        let system_events_rx = self.system_events_rx.clone_receiver();
+        let system_events_tx = self.system_events_tx.clone();
+        thread::spawn(move || {
+            loop
+            {
+                let msg = system_events_rx.receive();
+                match msg
+                {
+                    // The server cannot be stopped, so there is no way to
+                    // launch a second instance of this module.
+                    SystemMessage::Reboot(id) if id == MODULE_ID => system_events_tx.send(SystemMessage::RebootRefused(MODULE_ID)),
+                    _ => if !handle_system_message(MODULE_ID, msg, &system_events_tx)
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+
         // The `start_server` starts listening forever on the given address.
         rouille::start_server("localhost:8000", move |request| {        
         router!(request,
@@ -163,6 +229,10 @@ impl ConfigRest
                 self.do_get(&request, module)
             },
 
+            (GET) (/api/{module: String}/{submodule: String}) => {
+                self.do_get(&request, format!("{}/{}", module, submodule))
+            },
+
             (DELETE) (/api/{module: String}) => {
                 self.do_delete(&request, module)
             },
@@ -1,16 +1,21 @@
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DoorOpenRequest
 {
     pub access_point_id: u32,
     pub identification_token: Vec<u8>
 }
 
-// pub enum DoorControlCommands
-// {
-//     DoorOpenRequest,
-//     BarDoorRequest,
-//     PermanentReleaseRequest
-// }
+/// Remote control of a passageway, e.g. by a building
+/// automation system. Contains the passageway ID.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PassagewayCommand
+{
+    ReleaseOnce(u32),
+    Block(u32),
+    ReleasePermanently(u32),
+    NormalOperation(u32)        // Ends a block or a permanent release
+}
 
 pub mod trivial;
\ No newline at end of file
@@ -1,6 +1,5 @@
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{self}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager}, trace::trace_helper};
+use barracuda_core::{core::{SystemMessage, bootstage_helper::{self}, dependencies::Dependencies, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, module_thread::spawn_module, select::Selector, sud::Sud}, trace::trace_helper};
 
-use std::thread;
 
 use crate::io;
 
@@ -8,9 +7,9 @@ const MODULE_ID: u32 = 0x08000000;
 
 pub fn launch(chm: &mut ChannelManager)
 {    
-    let tracer = trace_helper::TraceHelper::new("DCM/Trivial".to_string(), chm);
-    let tdc = TrivialDoorControl::new(tracer, chm);
-    thread::spawn(move || {  
+    let tracer = trace_helper::TraceHelper::new("DCM/Trivial".to_string(), chm).with_sud(Sud::from(MODULE_ID));
+    let mut tdc = TrivialDoorControl::new(tracer, chm);
+    spawn_module(MODULE_ID, "DCM/Trivial", chm, move || {  
         tdc.init();   
         loop 
         {
@@ -35,7 +34,14 @@ pub struct TrivialDoorControl
     system_events_rx: GenericReceiver<SystemMessage>,
     system_events_tx: GenericSender<SystemMessage>,
     door_requests_rx: GenericReceiver<crate::dcm::DoorOpenRequest>,
-    output_cmd_tx: GenericSender<io::OutputSwitch>
+    output_cmd_tx: GenericSender<io::OutputSwitch>,
+    selector: Selector
+}
+
+enum Work
+{
+    DoorRequest(crate::dcm::DoorOpenRequest),
+    System(SystemMessage)
 }
 
 impl TrivialDoorControl
@@ -48,18 +54,27 @@ impl TrivialDoorControl
             system_events_rx    : chm.get_receiver(),
             system_events_tx    : chm.get_sender(),
             door_requests_rx    : chm.get_receiver(),
-            output_cmd_tx       : chm.get_sender()
+            output_cmd_tx       : chm.get_sender(),
+            selector            : Selector::new("DCM/Trivial")
         }
     }
 
     pub fn init(&self)
     {
-        bootstage_helper::plain_boot(MODULE_ID, &self.system_events_tx, &self.system_events_rx, &self.tracer)
+        bootstage_helper::plain_boot(MODULE_ID, Dependencies::none(), &self.system_events_tx, &self.system_events_rx, &self.tracer)
     }
 
-    pub fn do_request(&self) -> bool
+    pub fn do_request(&mut self) -> bool
     {
-        let request = self.door_requests_rx.receive();
+        let work = self.selector.select()
+                                .recv(&self.door_requests_rx, Work::DoorRequest)
+                                .recv(&self.system_events_rx, Work::System)
+                                .wait();
+        let request = match work
+        {
+            Work::DoorRequest(request) => request,
+            Work::System(msg) => return bootstage_helper::handle_system_message(MODULE_ID, msg, &self.system_events_tx)
+        };
 
         self.tracer.trace(format!("Open door {}", request.access_point_id));
 
@@ -1,12 +1,10 @@
-use std::thread;
-use std::{sync::Arc};
-use serde::{Serialize};
+use serde::{Deserialize, Serialize};
 
-use barracuda_core::{core::{bootstage_helper::boot_noop, broadcast_channel::GenericReceiver, channel_manager::ChannelManager, event::DataEvent, shareable::Shareable}, select_chan, trace::trace_helper::{self, TraceHelper}, wait_for};
+use barracuda_core::{core::{ModuleFault, SystemMessage, bootstage_helper::boot_noop, dependencies::Dependencies, broadcast_channel::{GenericReceiver, ChannelSettings, OverflowPolicy}, channel_manager::ChannelManager, module_thread::spawn_module, select::Selector, shareable::Shareable}, trace::trace_helper::{self, TraceHelper}, util::{JsonStorage, ObjectStorage}};
 
-use crate::{cfg::{self, ConfigMessage}, modulebase::ModuleBase};
+use crate::{cfg::{self, ConfigMessage, CFG_HOLDER}, modulebase::ModuleBase};
 
-#[derive( Clone, Debug, PartialEq, Serialize)]
+#[derive( Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LogEvent
 {
     AccessGranted(u32, Vec<u8>, u32),                  // pwayid, token, ap id
@@ -22,17 +20,29 @@ pub enum LogEvent
     DoorForcedOpen(u32),    // pwayid
     DoorOpenTooLong(u32),   // pwayid
     DoorClosedAgain(u32),   // pwayid
+
+    ModuleFault(ModuleFault),   // e.g. an optional module that failed to boot
 }
 
 const MODULE_ID: u32 = 0x0E000000;
 
+/// Max. number of events queued for each receiver, the oldest
+/// events are dropped if a receiver does not keep up.
+const EVENT_QUEUE_CAPACITY: usize = 256;
+
 pub fn launch(chm: &mut ChannelManager)
 {        
     let tracer = trace_helper::TraceHelper::new("FDB/Events".to_string(), chm);
-    let cr = EventStore::new(tracer, chm);      
-    thread::spawn(move|| {
+    let mut cr = EventStore::new(tracer, chm);      
+    spawn_module(MODULE_ID, "FDB/Events", chm, move|| {
         cr.init();  
-        cr.run();         
+        loop
+        {
+            if !cr.run()
+            {
+                break;
+            }
+        }
     });
 }
 
@@ -40,18 +50,35 @@ pub struct EventStore
 {
     module   : ModuleBase,
     event_rx : GenericReceiver<LogEvent>,
+    fault_rx : GenericReceiver<ModuleFault>,
     cfg_rx   : GenericReceiver<ConfigMessage>,
-    event_buffer: Shareable<Vec<LogEvent>>
+    event_buffer: Shareable<Vec<LogEvent>>,
+    storage  : Shareable<JsonStorage<LogEvent>>,
+    selector : Selector
+}
+
+enum Work
+{
+    Event(LogEvent),
+    System(SystemMessage)
 }
 
 impl EventStore {
     pub fn new(tracer: TraceHelper, chm: &mut ChannelManager) -> Self { 
+        // Events that were not fetched before the last shutdown
+        // are still available.
+        let storage = JsonStorage::new("./events.txt".to_string());
+        let events = storage.iter().cloned().collect();
+        chm.configure_channel::<LogEvent>(ChannelSettings::bounded(EVENT_QUEUE_CAPACITY, OverflowPolicy::DropOldest));
         Self 
         {
-         module          : ModuleBase::new(MODULE_ID, tracer, chm),
+         module          : ModuleBase::new(MODULE_ID, Dependencies::new(&[], &[CFG_HOLDER]), tracer, chm),
          event_rx        : chm.get_receiver(),
+         fault_rx        : chm.get_receiver(),
          cfg_rx          : chm.get_receiver(),
-         event_buffer    : Shareable::new(Vec::new())
+         event_buffer    : Shareable::new(events),
+         storage         : Shareable::new(storage),
+         selector        : Selector::new("FDB/Events")
         } 
     }
 
@@ -86,13 +113,56 @@ impl EventStore {
         self.module.boot(Some(boot_noop), hli_cb);
     }
 
-    pub fn run(&self)
+    pub fn run(&mut self) -> bool
+    {
+        let work = self.selector.select()
+                                .recv(&self.event_rx, Work::Event)
+                                .recv(&self.module.system_events_rx, Work::System)
+                                .recv(&self.fault_rx, |fault| Work::Event(LogEvent::ModuleFault(fault)))
+                                .wait();
+        match work
+        {
+            Work::System(msg) =>
+            {
+                if self.module.is_stop_request(&msg)
+                {
+                    self.persist_events();
+                }
+                self.module.handle_system_event(msg)
+            }
+            Work::Event(event) =>
+            {
+                self.event_buffer.lock().push(event);
+                self.collect_events();
+                true
+            }
+        }
+    }
+
+    fn collect_events(&self)
     {
-        let _ = select_chan!(self.event_rx);
         let mut data_access = self.event_buffer.lock();
         while self.event_rx.has_data()
         {
             data_access.push(self.event_rx.receive())
         }
+        while self.fault_rx.has_data()
+        {
+            data_access.push(LogEvent::ModuleFault(self.fault_rx.receive()))
+        }
+    }
+
+    fn persist_events(&self)
+    {
+        self.collect_events();
+        let data_access = self.event_buffer.lock();
+
+        let mut storage = self.storage.lock();
+        storage.delete_entry(|_| true);
+        for event in data_access.iter()
+        {
+            storage.put_entry(event.clone());
+        }
+        storage.update_storage();
     }
 }
@@ -1,13 +1,14 @@
 
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{self}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, event::DataEvent, shareable::Shareable, timer::Timer}, trace::trace_helper};
-use std::{sync::Arc, thread};
+use barracuda_core::{core::{SystemMessage, bootstage_helper::{self}, dependencies::Dependencies, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, module_thread::spawn_module, select::Selector, sud::Sud, shareable::Shareable, timer::Timer}, trace::trace_helper};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
 
 use crate::modcaps::*;
 
 
 extern crate chrono;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum InputState
 {
     _Unknown,
@@ -23,7 +24,7 @@ pub enum InputState
 #[derive(Clone)]
 pub struct RawInputEvent
 {
-    input_id: u32,      // SUD!
+    input_id: Sud,
     state: InputState
 }
 
@@ -31,7 +32,7 @@ pub struct RawInputEvent
 // Interface of the IO Module to the rest of the
 // system. Logical Input states, which have been
 // debounce appropiately are propagated this way
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct InputEvent
 {
     pub input_id: u32,      // Logical!
@@ -59,7 +60,7 @@ pub struct InputEvent
 //     debounce_off: u64
 // }
 
-#[derive(Copy,Clone, PartialEq, Debug)]
+#[derive(Copy,Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum OutputState
 {
     Low,
@@ -79,7 +80,7 @@ pub struct RawOutputSwitch
     target_state: OutputState   // physical!
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OutputSwitch
 {
     pub output_id: u32,
@@ -92,9 +93,9 @@ const MODULE_ID: u32 = 0x07000000;
 
 pub fn launch(chm: &mut ChannelManager)
 {
-    let tracer = trace_helper::TraceHelper::new("IO/IoManager".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("IO/IoManager".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut ioman = IoManager::new(tracer, chm);
-    thread::spawn(move || {
+    spawn_module(MODULE_ID, "IO/IoManager", chm, move || {
         ioman.init();
         loop
         {
@@ -142,21 +143,22 @@ pub struct IoManager
     timer: Arc<Timer>,
     input_list: ModCapAggregator,
     output_list: Shareable<Vec<OutputEntry>>,
-    dataevent: Arc<DataEvent<u32>>
-
+    selector: Selector
 }
 
-impl Drop for IoManager
+enum Work
 {
-    fn drop(&mut self) {
-        self.timer.stop();
-    }
+    ModCaps(ModuleCapabilityAdvertisement),
+    RawInput(RawInputEvent),
+    OutputCommand(OutputSwitch),
+    System(SystemMessage)
 }
 
 impl IoManager
 {
     pub fn new(trace: trace_helper::TraceHelper, chm: &mut ChannelManager) -> Self
     {
+        retain_advertisements(chm);
         IoManager{
             system_events_rx    : chm.get_receiver(),
             system_events_tx    : chm.get_sender(),
@@ -166,26 +168,39 @@ impl IoManager
             output_commands     : chm.get_receiver(),
             raw_output_commands : chm.get_sender(),
             tracer              : trace,
-            timer               : Timer::new(),
+            timer               : chm.timer(),
             input_list          : ModCapAggregator::new(),
             output_list         : Shareable::new(Vec::new()),
-            dataevent           : Arc::new(DataEvent::new("IOWait".to_string()))
+            selector            : Selector::new("IOWait")
         }
     }
 
     pub fn init(&self)
     {
-        self.modcaps_rx.set_data_trigger(self.dataevent.clone(), 0);
-        self.raw_input_events.set_data_trigger(self.dataevent.clone(), 1);
-        self.output_commands.set_data_trigger(self.dataevent.clone(), 2);
-
-        bootstage_helper::plain_boot(MODULE_ID, &self.system_events_tx, &self.system_events_rx, &self.tracer);
+        bootstage_helper::plain_boot(MODULE_ID, Dependencies::new(&[], &[INPUTS, OUTPUTS]), &self.system_events_tx, &self.system_events_rx, &self.tracer);
     }
 
-    fn do_all_modcap_messages(&mut self)
+    fn do_all_modcap_messages(&mut self, first: ModuleCapabilityAdvertisement)
     {
-        // called upon HLI, all I/O modules must have advertised by now.
+        let mut caps = vec![first];
         while let Some(cap) = self.modcaps_rx.receive_with_timeout(0)
+        {
+            caps.push(cap);
+        }
+
+        if self.input_list.is_built()
+        {
+            // A module was rebooted and advertised its capabilities again. The
+            // I/O map cannot change at runtime, so it keeps its old entries.
+            for cap in caps
+            {
+                self.tracer.trace(format!("Ignoring capabilities of module {}, I/O map is already built.", cap.module_id));
+            }
+            return;
+        }
+
+        // called upon HLI, all I/O modules must have advertised by now.
+        for cap in latest_per_module(caps)
         {
             self.process_modcaps_message(cap);
         }
@@ -211,31 +226,31 @@ impl IoManager
     pub fn run(&mut self) -> bool
     {
         self.tracer.trace_str("Waiting for commands");
-        self.modcaps_rx.set_data_trigger(self.dataevent.clone(), 0);
-        self.raw_input_events.set_data_trigger(self.dataevent.clone(), 1);
-        self.output_commands.set_data_trigger(self.dataevent.clone(), 2);
-        let chanid = self.dataevent.wait();
-
-        match chanid
+        let work = self.selector.select()
+                                .recv(&self.modcaps_rx, Work::ModCaps)
+                                .recv(&self.raw_input_events, Work::RawInput)
+                                .recv(&self.output_commands, Work::OutputCommand)
+                                .recv(&self.system_events_rx, Work::System)
+                                .wait();
+
+        match work
         {
-            0 => {
+            Work::ModCaps(cap) => {
                 // Note: This should actually be done during HLI, however, if the
                 // other modules advertise only during LLI this should work just as
                 // well.
-                self.do_all_modcap_messages();
+                self.do_all_modcap_messages(cap);
             },
-            1 => self.dispatch_raw_input_event(),
-            2 => self.dispatch_output_command(),
-            _ => return true
+            Work::RawInput(event) => self.dispatch_raw_input_event(event),
+            Work::OutputCommand(command) => self.dispatch_output_command(command),
+            Work::System(msg) => return self.dispatch_system_event(msg)
         }
 
         return true
     }
 
-    fn dispatch_output_command(&self)
+    fn dispatch_output_command(&self, command: OutputSwitch)
     {
-        
-        let command = self.output_commands.receive();
         self.tracer.trace(format!("Switching output {}", command.output_id));
         if let Ok(output) = self.input_list.logical_id_to_sud(command.output_id, ModuleCapabilityType::Outputs)        
         {
@@ -281,9 +296,34 @@ impl IoManager
 
     }
 
-    fn dispatch_raw_input_event(&self)
+    fn dispatch_system_event(&self, msg: SystemMessage) -> bool
+    {
+        if bootstage_helper::is_stop_request(MODULE_ID, &msg)
+        {
+            self.switch_outputs_to_safe_state();
+        }
+        return bootstage_helper::handle_system_message(MODULE_ID, msg, &self.system_events_tx);
+    }
+
+    /// Switches all outputs off and cancels all pending
+    /// switchbacks, so no relay stays energised once the
+    /// module has stopped.
+    fn switch_outputs_to_safe_state(&self)
+    {
+        self.tracer.trace_str("Switching all outputs to safe state.");
+        let mut output_access = self.output_list.lock();
+        for (output_id, output_entry) in output_access.iter_mut().enumerate()
+        {
+            output_entry.timer_guard = None;
+            if let Ok(output) = self.input_list.logical_id_to_sud(output_id as u32, ModuleCapabilityType::Outputs)
+            {
+                self.raw_output_commands.send(RawOutputSwitch{output_id: output, target_state: OutputState::Low});
+            }
+        }
+    }
+
+    fn dispatch_raw_input_event(&self, event: RawInputEvent)
     {
-        let event = self.raw_input_events.receive();
         if let Ok(input_id) = self.input_list.sud_to_logical_id(event.input_id, ModuleCapabilityType::Inputs)
         {
             self.input_events.send(InputEvent {
@@ -318,26 +358,30 @@ mod tests {
     use barracuda_core::core::*;
     use crate::io::*;
     use crate::modcaps::{ModuleCapabilityAdvertisement, ModuleCapability};
+    use barracuda_core::core::clock::VirtualClock;
     use std::time::Duration;
 
 
     fn make_mod() -> (IoManager, GenericSender<crate::io::RawInputEvent>,
                       GenericReceiver<crate::io::InputEvent>,
-                      GenericSender<OutputSwitch>, GenericReceiver<crate::io::RawOutputSwitch>)
+                      GenericSender<OutputSwitch>, GenericReceiver<crate::io::RawOutputSwitch>,
+                      Arc<VirtualClock>)
     {
         let mut chm = ChannelManager::new();
+        let clock = Arc::new(VirtualClock::new());
+        chm.set_clock(clock.clone());
         let trace = trace_helper::TraceHelper::new("".to_string(), &mut chm);
         let sender = chm.get_sender::<crate::io::RawInputEvent>();
         let receiver = chm.get_receiver::<crate::io::InputEvent>();
         let output_sender = chm.get_sender::<crate::io::OutputSwitch>();
         let output_command_recv = chm.get_receiver::<crate::io::RawOutputSwitch>();
         let mut module = IoManager::new(trace, &mut chm);
-        let modcap = ModuleCapabilityAdvertisement {module_id : make_sud(10, 0, 0), caps : vec![ModuleCapability::Inputs(4), ModuleCapability::Outputs(4)] };
-        let modcap2 = ModuleCapabilityAdvertisement {module_id : make_sud(12, 0, 0), caps : vec![ModuleCapability::Inputs(2), ModuleCapability::Outputs(2)] };
+        let modcap = ModuleCapabilityAdvertisement {module_id : Sud::new(10, 0, 0), caps : vec![ModuleCapability::Inputs(4), ModuleCapability::Outputs(4)] };
+        let modcap2 = ModuleCapabilityAdvertisement {module_id : Sud::new(12, 0, 0), caps : vec![ModuleCapability::Inputs(2), ModuleCapability::Outputs(2)] };
         module.process_modcaps_message(modcap);
         module.process_modcaps_message(modcap2);
         module.modcaps_done();
-        return (module, sender, receiver, output_sender, output_command_recv)
+        return (module, sender, receiver, output_sender, output_command_recv, clock)
     }
 
     #[test]
@@ -345,7 +389,7 @@ mod tests {
     {
         let mut md = make_mod();
         let s = md.1;
-        let evt = RawInputEvent {input_id: make_sud(10, 0, 1), state: InputState::High};
+        let evt = RawInputEvent {input_id: Sud::new(10, 0, 1), state: InputState::High};
         s.send(evt);
         md.0.run();
         let recv = md.2.receive_with_timeout(1).unwrap();
@@ -358,7 +402,7 @@ mod tests {
     {
         let mut md = make_mod();
         let s = md.1;
-        let evt = RawInputEvent {input_id: make_sud(12, 0, 1), state: InputState::High};
+        let evt = RawInputEvent {input_id: Sud::new(12, 0, 1), state: InputState::High};
         s.send(evt);
         md.0.run();
         let recv = md.2.receive_with_timeout(1).unwrap();
@@ -371,7 +415,7 @@ mod tests {
     {
         let mut md = make_mod();
         let s = md.1;
-        let evt = RawInputEvent {input_id: make_sud(14, 0, 1), state: InputState::High};
+        let evt = RawInputEvent {input_id: Sud::new(14, 0, 1), state: InputState::High};
         s.send(evt);
         md.0.run();
         let recv = md.2.receive_with_timeout(1);
@@ -404,9 +448,13 @@ mod tests {
             md.0.run();
             let recv = md.4.receive_with_timeout(1).unwrap();
             assert_eq!(recv.output_id, make_sud(10, 0, 1));
-            // The internal timer will trigger the switchback after 100 ms, we'll wait
-            // some more time to avoid a volatile test.
-            thread::sleep(Duration::from_millis(100));
+
+            md.5.advance(Duration::from_millis(99));
+            md.0.timer.fire_due();
+            assert!(!md.0.output_commands.has_data());
+
+            md.5.advance(Duration::from_millis(1));
+            md.0.timer.fire_due();
             md.0.run();
             let recv = md.4.receive_with_timeout(1).unwrap();
             assert_eq!(recv.output_id, make_sud(10, 0, 1));
@@ -428,5 +476,25 @@ mod tests {
         assert!(recv.is_none());
     }
 
+    #[test]
+    pub fn safe_state_switches_all_outputs_low_and_cancels_switchback()
+    {
+        let mut md = make_mod();
+        let s = md.3;
+        let evt = OutputSwitch {output_id: 1, target_state: OutputState::High, switch_time: 100};
+        s.send(evt);
+        md.0.run();
+        let _ = md.4.receive_with_timeout(1).unwrap();
+
+        md.0.switch_outputs_to_safe_state();
+        for _ in 0..6
+        {
+            let recv = md.4.receive_with_timeout(1).unwrap();
+            assert!(OutputState::Low == recv.target_state)
+        }
 
+        md.5.advance(Duration::from_millis(150));
+        md.0.timer.fire_due();
+        assert!(!md.0.output_commands.has_data());
+    }
 }
\ No newline at end of file
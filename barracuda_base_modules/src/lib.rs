@@ -17,4 +17,8 @@ pub mod modcaps;
 pub mod io;
 pub mod profile;
 pub mod events;
+pub mod recorder;
+pub mod bus;
+#[cfg(unix)]
+pub mod bridge;
 pub mod modulebase;
\ No newline at end of file
@@ -1,5 +1,16 @@
-use barracuda_core::core::broadcast_channel::GenericReceiver;
+use barracuda_core::core::{broadcast_channel::GenericReceiver, channel_manager::ChannelManager, sud::Sud};
 
+// Names used to declare dependencies on modules advertising
+// the respective capabilities (see core::dependencies). Modules
+// depending on these will only be started after all modules
+// providing them have advertised their capabilities.
+pub const INPUTS: &str = "Inputs";
+pub const OUTPUTS: &str = "Outputs";
+pub const ACCESS_POINTS: &str = "AccessPoints";
+
+/// Max. number of modules whose advertisements are kept
+/// for modules that subscribe late, see retain_advertisements.
+pub const RETAINED_ADVERTISEMENTS: usize = 64;
 
 #[derive(Copy, Clone, Debug)]
 pub enum ModuleCapability
@@ -26,10 +37,38 @@ pub enum ModuleCapabilityType
 #[derive(Clone)]
 pub struct ModuleCapabilityAdvertisement
 {
-    pub module_id: u32,
+    pub module_id: Sud,
     pub caps: Vec<ModuleCapability>
 }
 
+/// Makes the advertisements available to receivers created after
+/// they were sent, e.g. by an aggregating module that was rebooted.
+/// Only the latest advertisement of each module is kept, so a module
+/// rebooted several times does not push out the others.
+/// Called by the aggregating modules when they are launched.
+pub fn retain_advertisements(chm: &mut ChannelManager)
+{
+    chm.retain_latest_by::<ModuleCapabilityAdvertisement, _>(RETAINED_ADVERTISEMENTS, |a, b| a.module_id == b.module_id);
+}
+
+/// A module that was rebooted advertises its capabilities again, so
+/// the advertisements received may contain several advertisements of
+/// the same module. Keeps the latest advertisement of each module, in
+/// the order the modules advertised first.
+pub fn latest_per_module(advertisements: Vec<ModuleCapabilityAdvertisement>) -> Vec<ModuleCapabilityAdvertisement>
+{
+    let mut result: Vec<ModuleCapabilityAdvertisement> = Vec::new();
+    for advertisement in advertisements
+    {
+        match result.iter_mut().find(|x| x.module_id == advertisement.module_id)
+        {
+            Some(existing) => *existing = advertisement,
+            None => result.push(advertisement)
+        }
+    }
+    result
+}
+
 pub struct ModCapAggregator
 {
     inputs: Vec<u32>,
@@ -54,7 +93,12 @@ impl ModCapAggregator
 
     pub fn aggregate(&mut self, message_receiver: &GenericReceiver<ModuleCapabilityAdvertisement>)
     {
+        let mut messages = Vec::new();
         while let Some(modcap_message) = message_receiver.receive_with_timeout(0)
+        {
+            messages.push(modcap_message);
+        }
+        for modcap_message in latest_per_module(messages)
         {
             self.add_message(modcap_message)
         }
@@ -93,9 +137,9 @@ impl ModCapAggregator
         }
     }
 
-    fn add_cap(dest: &mut Vec<u32>, module_id: u32, num_entries: u32)
+    fn add_cap(dest: &mut Vec<u32>, module_id: Sud, num_entries: u32)
     {
-        let first_index = module_id;
+        let first_index = u32::from(module_id);
         for index in first_index..first_index + num_entries
         {
             if dest.binary_search(&index).is_ok()
@@ -107,6 +151,11 @@ impl ModCapAggregator
         }
     }
 
+    pub fn is_built(&self) -> bool
+    {
+        self.locked
+    }
+
     pub fn build(&mut self) 
     {
         self.inputs.sort_unstable_by(|a,b| a.partial_cmp(b).unwrap());
@@ -117,7 +166,7 @@ impl ModCapAggregator
 
     }
 
-    pub fn sud_to_logical_id(&self, sud: u32, cap: ModuleCapabilityType) -> Result<u32, ()> 
+    pub fn sud_to_logical_id(&self, sud: Sud, cap: ModuleCapabilityType) -> Result<u32, ()> 
     {
         if !self.locked
         {
@@ -133,7 +182,7 @@ impl ModCapAggregator
             ModuleCapabilityType::_KeypadEntry => search_list = &self.inputs
         }
 
-        if let Ok(id) = search_list.binary_search(&sud)
+        if let Ok(id) = search_list.binary_search(&u32::from(sud))
         {
             return Ok(id as u32)
         }
@@ -172,7 +221,7 @@ mod tests {
 
     fn make_message(mod_id: u32, cap: ModuleCapability) -> ModuleCapabilityAdvertisement
     {
-        return ModuleCapabilityAdvertisement{module_id: mod_id, caps: vec![cap]};
+        return ModuleCapabilityAdvertisement{module_id: Sud::from(mod_id), caps: vec![cap]};
     }
 
     #[test]
@@ -194,6 +243,33 @@ mod tests {
         a.add_message(make_message(0x4721, ModuleCapability::Inputs(10)));
     }
 
+    #[test]
+    pub fn is_built_after_build()
+    {
+        let mut a = ModCapAggregator::new();
+        a.add_message(make_message(0x4711, ModuleCapability::Inputs(5)));
+        assert!(!a.is_built());
+        a.build();
+        assert!(a.is_built());
+    }
+
+    #[test]
+    pub fn reboots_do_not_push_out_advertisements_of_other_modules()
+    {
+        let mut chm = ChannelManager::new();
+        retain_advertisements(&mut chm);
+        let tx = chm.get_sender::<ModuleCapabilityAdvertisement>();
+        tx.send(make_message(0x04000000, ModuleCapability::Inputs(10)));
+        for _ in 0..RETAINED_ADVERTISEMENTS
+        {
+            tx.send(make_message(0x05000000, ModuleCapability::Inputs(5)));
+        }
+
+        let rx = chm.get_receiver::<ModuleCapabilityAdvertisement>();
+        let modules: Vec<Sud> = std::iter::from_fn(|| rx.receive_with_timeout(0)).map(|x| x.module_id).collect();
+        assert_eq!(vec![Sud::from(0x04000000), Sud::from(0x05000000)], modules);
+    }
+
     #[test]
     #[should_panic]
     pub fn will_panic_if_sees_same_id_twice()
@@ -209,7 +285,7 @@ mod tests {
     {
         let mut a = ModCapAggregator::new();
         a.add_message(make_message(0x4711, ModuleCapability::Inputs(10)));
-        let _ = a.sud_to_logical_id(0x47110011, ModuleCapabilityType::Inputs);
+        let _ = a.sud_to_logical_id(Sud::from(0x47110011), ModuleCapabilityType::Inputs);
     }
 
     #[test]
@@ -256,12 +332,25 @@ mod tests {
         a.add_message(make_message(0x05000000, ModuleCapability::Inputs(5)));
         a.build();
 
-        let result = a.sud_to_logical_id(0x04000003, ModuleCapabilityType::Inputs).unwrap();
+        let result = a.sud_to_logical_id(Sud::from(0x04000003), ModuleCapabilityType::Inputs).unwrap();
         assert_eq!(result, 3);
-        let result = a.sud_to_logical_id(0x05000003, ModuleCapabilityType::Inputs).unwrap();
+        let result = a.sud_to_logical_id(Sud::from(0x05000003), ModuleCapabilityType::Inputs).unwrap();
         assert_eq!(result, 13);
     }
 
+    #[test]
+    pub fn repeated_advertisement_replaces_earlier_one()
+    {
+        let messages = vec![make_message(0x04000000, ModuleCapability::Inputs(10)),
+                            make_message(0x05000000, ModuleCapability::Inputs(5)),
+                            make_message(0x04000000, ModuleCapability::Inputs(2))];
+        let latest = latest_per_module(messages);
+
+        let ids: Vec<Sud> = latest.iter().map(|x| x.module_id).collect();
+        assert_eq!(vec![Sud::from(0x04000000), Sud::from(0x05000000)], ids);
+        assert!(matches!(latest[0].caps[0], ModuleCapability::Inputs(2)));
+    }
+
     #[test]
     pub fn yields_err_on_bad_sud()
     {
@@ -270,7 +359,7 @@ mod tests {
         a.add_message(make_message(0x05000000, ModuleCapability::Inputs(5)));
         a.build();
 
-        let result = a.sud_to_logical_id(0x07000003, ModuleCapabilityType::Inputs);
+        let result = a.sud_to_logical_id(Sud::from(0x07000003), ModuleCapabilityType::Inputs);
         assert!(result.is_err());
     }
 
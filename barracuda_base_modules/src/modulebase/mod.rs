@@ -1,10 +1,11 @@
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, plain_boot}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager}, trace::trace_helper};
+use barracuda_core::{core::{SystemMessage, dependencies::Dependencies, bootstage_helper::{boot, plain_boot, handle_system_message, is_stop_request, poll_system_messages}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, sud::Sud}, trace::trace_helper};
 
 use crate::cfg::ConfigMessage;
 
 pub struct ModuleBase
 {
     module_id               : u32,
+    dependencies            : Dependencies,
     tracer                  : trace_helper::TraceHelper,
     pub cfg_rx              : GenericReceiver<ConfigMessage>,
     pub system_events_rx    : GenericReceiver<SystemMessage>,
@@ -13,12 +14,13 @@ pub struct ModuleBase
 
 impl ModuleBase
 {
-    pub fn new(module_id: u32, trace: trace_helper::TraceHelper, chm: &mut ChannelManager) -> Self
+    pub fn new(module_id: u32, dependencies: Dependencies, trace: trace_helper::TraceHelper, chm: &mut ChannelManager) -> Self
     {
         Self
         {
             module_id,
-            tracer              : trace,
+            dependencies,
+            tracer              : trace.with_sud(Sud::from(module_id)),
             cfg_rx              : chm.get_receiver(), 
             system_events_rx    : chm.get_receiver(),
             system_events_tx    : chm.get_sender(),
@@ -28,14 +30,41 @@ impl ModuleBase
     pub fn boot<LliCb, HliCb>(&self,  llicb: Option<LliCb>, hlicb: Option<HliCb>)
     where LliCb: FnOnce() -> (), HliCb: FnOnce() -> ()
     {
-        boot(self.module_id, llicb, hlicb, 
+        boot(self.module_id, self.dependencies.clone(), llicb, hlicb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);        
     }
 
+    pub fn tracer(&self) -> &trace_helper::TraceHelper
+    {
+        &self.tracer
+    }
+
     pub fn plain_boot(&self)
     {
-        plain_boot(self.module_id, &self.system_events_tx, &self.system_events_rx, &self.tracer)
+        plain_boot(self.module_id, self.dependencies.clone(), &self.system_events_tx, &self.system_events_rx, &self.tracer)
+    }
+
+    /// Answers pending system messages (i.e. heartbeats) without blocking.
+    /// Returns false if the module is supposed to terminate.
+    pub fn poll_system_events(&self) -> bool
+    {
+        poll_system_messages(self.module_id, &self.system_events_tx, &self.system_events_rx)
+    }
+
+    /// Handles a single system message that was already received from
+    /// system_events_rx. Returns false if the module is supposed to terminate.
+    pub fn handle_system_event(&self, msg: SystemMessage) -> bool
+    {
+        handle_system_message(self.module_id, msg, &self.system_events_tx)
+    }
+
+    /// Checks if the message asks this module to shut down
+    /// (or to reboot), i.e. the module should persist its data
+    /// before handling the message.
+    pub fn is_stop_request(&self, msg: &SystemMessage) -> bool
+    {
+        is_stop_request(self.module_id, msg)
     }
 }
\ No newline at end of file
@@ -1,11 +1,10 @@
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, shareable::Shareable}, trace::trace_helper};
+use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop, handle_system_message}, clock::Clock, dependencies::Dependencies, module_thread::spawn_module, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, shareable::Shareable, sud::Sud}, trace::trace_helper};
 
-use crate::{cfg::{cfgholder::FunctionType, ConfigMessage}};
+use crate::{cfg::{cfgholder::FunctionType, ConfigMessage, CFG_HOLDER}};
 use crate::{Handler, cfg::{self}};
-use std::{thread};
+use std::sync::Arc;
 mod profile_checker;
 
-use chrono::{Local};
 
 const MODULE_ID: u32 = 0x0C000000;
 
@@ -25,9 +24,9 @@ pub struct ProfileChangeEvent
 
 pub fn launch(chm: &mut ChannelManager)
 {    
-    let tracer = trace_helper::TraceHelper::new("ProfileControl".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("ProfileControl".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut wl = ProfileControl::new(tracer, chm);
-    thread::spawn(move || {  
+    spawn_module(MODULE_ID, "ProfileControl", chm, move || {  
         wl.init();   
         loop 
         {
@@ -47,7 +46,8 @@ struct ProfileControl
     system_events_tx: GenericSender<SystemMessage>,
     profile_state_tx: GenericSender<ProfileChangeEvent>,
     cfg_rx          : GenericReceiver<ConfigMessage>,
-    checker         : Shareable<profile_checker::ProfileChecker>
+    checker         : Shareable<profile_checker::ProfileChecker>,
+    clock           : Arc<dyn Clock>
 }
 
 impl ProfileControl
@@ -61,7 +61,8 @@ impl ProfileControl
             system_events_tx: chm.get_sender(),
             profile_state_tx: chm.get_sender(),
             cfg_rx:           chm.get_receiver(),
-            checker:          Shareable::new(profile_checker::ProfileChecker::new())
+            checker:          Shareable::new(profile_checker::ProfileChecker::new()),
+            clock:            chm.clock()
         }
     }
 
@@ -91,7 +92,7 @@ impl ProfileControl
                 }));            
         });
 
-        boot(MODULE_ID, Some(boot_noop), hli_cb, 
+        boot(MODULE_ID, Dependencies::new(&[], &[CFG_HOLDER]), Some(boot_noop), hli_cb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
@@ -102,18 +103,18 @@ impl ProfileControl
 
     pub fn run(&mut self) -> bool
     {
-        let mut last_date_time = Local::now();
+        let mut last_date_time = self.clock.local_time();
         loop 
         {
             if let Some(e) = self.system_events_rx.receive_with_timeout(5000)
             {
-                if e == SystemMessage::Shutdown
+                if !handle_system_message(MODULE_ID, e, &self.system_events_tx)
                 {
                     return false
                 }
             }
 
-            let current_time = Local::now();
+            let current_time = self.clock.local_time();
             let events = self.checker.lock().tick(current_time, last_date_time);
 
             last_date_time = current_time;
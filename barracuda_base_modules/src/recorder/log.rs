@@ -0,0 +1,118 @@
+use std::io;
+use barracuda_core::util::rotating_file::RotatingFile;
+
+/// # A single recorded message
+/// Stored as one line of text:
+/// <timestamp> <type name> <message as json>
+/// The timestamp is in µs since the recorder was started, as
+/// measured by the clock of the channel manager (see core::clock),
+/// i.e. it is monotonic within a log file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecord
+{
+    pub timestamp: u64,
+    pub type_name: String,
+    pub payload: String
+}
+
+impl LogRecord
+{
+    pub fn to_line(&self) -> String
+    {
+        format!("{} {} {}\n", self.timestamp, self.type_name, self.payload)
+    }
+
+    /// Yields None for lines that are not a valid record,
+    /// e.g. a line that was cut off by a power loss.
+    pub fn parse(line: &str) -> Option<LogRecord>
+    {
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let timestamp = parts.next()?.parse::<u64>().ok()?;
+        let type_name = parts.next()?.to_string();
+        let payload = parts.next()?.to_string();
+        Some(LogRecord { timestamp, type_name, payload })
+    }
+}
+
+/// # Writes records to a rotating set of files
+/// See RotatingFile for how the files are rotated.
+pub struct LogWriter
+{
+    file: RotatingFile
+}
+
+impl LogWriter
+{
+    pub fn new(file_name: String, max_file_size: u64, max_files: u32) -> Self
+    {
+        LogWriter
+        {
+            file: RotatingFile::new(file_name, max_file_size, max_files)
+        }
+    }
+
+    pub fn write(&mut self, record: &LogRecord) -> io::Result<()>
+    {
+        self.file.write_line(&record.to_line())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use barracuda_core::util::temp_dir::TempDir;
+
+    fn record(timestamp: u64) -> LogRecord
+    {
+        LogRecord { timestamp, type_name: "SigCommand".to_string(), payload: "{\"access_point_id\":1}".to_string() }
+    }
+
+    #[test]
+    fn record_survives_roundtrip()
+    {
+        let r = record(1234);
+        assert_eq!(Some(r.clone()), LogRecord::parse(&r.to_line()));
+    }
+
+    #[test]
+    fn truncated_line_is_rejected()
+    {
+        assert_eq!(None, LogRecord::parse("1234 SigCommand"));
+        assert_eq!(None, LogRecord::parse("12x SigCommand {}"));
+    }
+
+    #[test]
+    fn log_is_rotated_when_full()
+    {
+        let dir = TempDir::new("rotate");
+        let file_name = dir.file("buslog.txt");
+        let line_len = record(10).to_line().len() as u64;
+        let mut writer = LogWriter::new(file_name.clone(), 2 * line_len, 2);
+        for timestamp in 10..15
+        {
+            writer.write(&record(timestamp)).unwrap();
+        }
+
+        let current = fs::read_to_string(&file_name).unwrap();
+        let previous = fs::read_to_string(format!("{}.1", file_name)).unwrap();
+        let oldest = fs::read_to_string(format!("{}.2", file_name)).unwrap();
+        assert_eq!(record(14).to_line(), current);
+        assert_eq!(record(12).to_line() + &record(13).to_line(), previous);
+        assert_eq!(record(10).to_line() + &record(11).to_line(), oldest);
+        assert!(!Path::new(&format!("{}.3", file_name)).exists());
+    }
+
+    #[test]
+    fn existing_log_is_kept()
+    {
+        let dir = TempDir::new("existing");
+        let file_name = dir.file("buslog.txt");
+        fs::write(&file_name, record(1).to_line()).unwrap();
+        let mut writer = LogWriter::new(file_name.clone(), 1024, 1);
+        writer.write(&record(2)).unwrap();
+        assert_eq!(record(1).to_line(), fs::read_to_string(format!("{}.1", file_name)).unwrap());
+        assert_eq!(record(2).to_line(), fs::read_to_string(&file_name).unwrap());
+    }
+}
@@ -0,0 +1,217 @@
+use std::{sync::Arc, time::Instant};
+
+use barracuda_core::{core::{bootstage_helper::boot_noop, clock::Clock, dependencies::Dependencies, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, event::DataEvent, module_thread::spawn_module}, trace::trace_helper::{self, TraceHelper}};
+
+use crate::{bus::{SerializedChannel, open_channel}, cfg::{self, cfgholder::FunctionType, CFG_HOLDER}, modulebase::ModuleBase};
+
+pub mod log;
+pub mod replay;
+use log::{LogRecord, LogWriter};
+
+const MODULE_ID: u32 = 0x0F000000;
+
+/// Names of the message types the recorder subscribes to by
+/// default, these are also the type names used in the log.
+/// All types of bus::MESSAGE_TYPES can be recorded.
+pub const RECORDABLE_TYPES: [&str; 5] = ["InputEvent", "OutputSwitch", "DoorOpenRequest", "SigCommand", "WhitelistAccessRequest"];
+
+/// Switches the recorder on and off at runtime,
+/// e.g. PUT api/recorder with true or false.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecorderCommand
+{
+    Start,
+    Stop
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderSettings
+{
+    pub file_name: String,
+    pub max_file_size: u64,             // in bytes! The log is rotated once it would grow larger
+    pub max_files: u32,                 // Number of rotated logs that are kept
+    pub enabled: bool,                  // Initial state, see RecorderCommand
+    pub message_types: Vec<String>      // See bus::MESSAGE_TYPES, default: RECORDABLE_TYPES
+}
+
+impl Default for RecorderSettings
+{
+    fn default() -> Self {
+        RecorderSettings
+        {
+            file_name: "./buslog.txt".to_string(),
+            max_file_size: 1024 * 1024,
+            max_files: 3,
+            enabled: false,
+            message_types: RECORDABLE_TYPES.iter().map(|x| x.to_string()).collect()
+        }
+    }
+}
+
+pub fn launch(chm: &mut ChannelManager)
+{
+    launch_with_settings(chm, RecorderSettings::default());
+}
+
+pub fn launch_with_settings(chm: &mut ChannelManager, settings: RecorderSettings)
+{
+    let tracer = trace_helper::TraceHelper::new("DIAG/Recorder".to_string(), chm);
+    let mut rec = Recorder::new(tracer, chm, settings);
+    spawn_module(MODULE_ID, "DIAG/Recorder", chm, move || {
+        rec.init();
+        loop
+        {
+            if !rec.run()
+            {
+                break;
+            }
+        }
+    });
+}
+
+struct Recorder
+{
+    module      : ModuleBase,
+    channels    : Vec<Box<dyn SerializedChannel>>,
+    command_rx  : GenericReceiver<RecorderCommand>,
+    command_tx  : GenericSender<RecorderCommand>,
+    writer      : LogWriter,
+    enabled     : bool,
+    clock       : Arc<dyn Clock>,
+    started     : Instant       // Taken from the clock, i.e. on the bus time
+}
+
+impl Recorder
+{
+    fn new(tracer: TraceHelper, chm: &mut ChannelManager, settings: RecorderSettings) -> Self
+    {
+        let mut channels = Vec::new();
+        for type_name in settings.message_types.iter()
+        {
+            match open_channel(type_name, true, chm)
+            {
+                Some(channel) => channels.push(channel),
+                None => tracer.trace(format!("Cannot record unknown message type {}", type_name))
+            }
+        }
+
+        let clock = chm.clock();
+        let started = clock.now();
+        Recorder
+        {
+            module      : ModuleBase::new(MODULE_ID, Dependencies::new(&[], &[CFG_HOLDER]), tracer, chm),
+            channels,
+            command_rx  : chm.get_receiver(),
+            command_tx  : chm.get_sender(),
+            writer      : LogWriter::new(settings.file_name, settings.max_file_size, settings.max_files),
+            enabled     : settings.enabled,
+            clock,
+            started
+        }
+    }
+
+    fn init(&mut self)
+    {
+        let the_receiver = self.module.cfg_rx.clone_receiver();
+        let command_tx = self.command_tx.clone();
+        let hli_cb = Some(move || {
+            let res = the_receiver.receive();
+            let cfg::ConfigMessage::RegisterHandlers(cfg_holder) = res;
+            let mut holder = cfg_holder.lock();
+            holder.register_handler(FunctionType::Put, "recorder".to_string(), Handler!(|enabled: bool|
+                {
+                    command_tx.send(if enabled { RecorderCommand::Start } else { RecorderCommand::Stop });
+                }));
+        });
+        self.module.boot(Some(boot_noop), hli_cb);
+    }
+
+    fn run(&mut self) -> bool
+    {
+        // The channels hide their message types, so a Select
+        // cannot be used here. All channels are checked after
+        // each wakeup anyway.
+        let evt = Arc::new(DataEvent::<u32>::new("Recorder".to_string()));
+        for (index, channel) in self.channels.iter().enumerate()
+        {
+            channel.set_data_trigger(evt.clone(), index as u32);
+        }
+        let num_channels = self.channels.len() as u32;
+        self.command_rx.set_data_trigger(evt.clone(), num_channels);
+        self.module.system_events_rx.set_data_trigger(evt.clone(), num_channels + 1);
+        evt.wait();
+
+        while let Some(cmd) = self.command_rx.receive_with_timeout(0)
+        {
+            self.enabled = cmd == RecorderCommand::Start;
+            self.module.tracer().trace(format!("Recording {}", if self.enabled { "started" } else { "stopped" }));
+        }
+
+        self.record_pending();
+
+        if let Some(msg) = self.module.system_events_rx.receive_with_timeout(0)
+        {
+            return self.module.handle_system_event(msg);
+        }
+        true
+    }
+
+    /// Writes all pending messages to the log, messages are
+    /// discarded while the recorder is disabled.
+    fn record_pending(&mut self)
+    {
+        for channel in self.channels.iter()
+        {
+            while let Some(payload) = channel.take()
+            {
+                if !self.enabled
+                {
+                    continue;
+                }
+                let record = LogRecord
+                {
+                    timestamp: self.clock.now().saturating_duration_since(self.started).as_micros() as u64,
+                    type_name: channel.type_name().to_string(),
+                    payload
+                };
+                if let Err(e) = self.writer.write(&record)
+                {
+                    self.module.tracer().trace(format!("Failed to write log: {}, recording stopped", e));
+                    self.enabled = false;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use barracuda_core::{core::clock::VirtualClock, util::temp_dir::TempDir};
+    use crate::sig::{SigCommand, SigType};
+    use super::replay::read_log;
+
+    #[test]
+    fn timestamps_follow_the_clock_of_the_channel_manager()
+    {
+        let dir = TempDir::new("recorder_clock");
+        let clock = Arc::new(VirtualClock::new());
+        let mut chm = ChannelManager::new();
+        chm.set_clock(clock.clone());
+        let settings = RecorderSettings { file_name: dir.file("buslog.txt"), enabled: true, message_types: vec!["SigCommand".to_string()], ..RecorderSettings::default() };
+        let mut rec = Recorder::new(TraceHelper::new("Test".to_string(), &mut chm), &mut chm, settings);
+        let sig_tx = chm.get_sender::<SigCommand>();
+
+        clock.advance(Duration::from_millis(3));
+        sig_tx.send(SigCommand { access_point_id: 1, sig_type: SigType::AccessGranted, duration: 1000 });
+        rec.record_pending();
+        clock.advance(Duration::from_millis(250));
+        sig_tx.send(SigCommand { access_point_id: 1, sig_type: SigType::AccessDenied, duration: 1000 });
+        rec.record_pending();
+
+        let timestamps: Vec<u64> = read_log(&dir.file("buslog.txt")).unwrap().iter().map(|x| x.timestamp).collect();
+        assert_eq!(vec![3000, 253000], timestamps);
+    }
+}
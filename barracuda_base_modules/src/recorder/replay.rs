@@ -0,0 +1,297 @@
+use std::{fmt, fs, io, thread, time::{Duration, Instant}};
+
+use barracuda_core::core::{BootStage, SystemMessage, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, shareable::Shareable};
+
+use crate::cfg::{ConfigMessage, cfgholder::CfgHolder};
+use crate::bus::{SerializedChannel, open_channel};
+use super::log::LogRecord;
+
+/// Time a module has to complete each bootstage during a replay.
+const BOOT_TIMEOUT: u64 = 2500;
+
+/// Reads all records of a log written by the recorder,
+/// lines that are not a valid record are skipped.
+pub fn read_log(file_name: &str) -> io::Result<Vec<LogRecord>>
+{
+    let content = fs::read_to_string(file_name)?;
+    Ok(content.lines().filter_map(LogRecord::parse).collect())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayError
+{
+    UnknownType(String),        // name of a type that cannot be recorded, see bus::MESSAGE_TYPES
+    BootFailed(BootStage),      // stage the module did not complete in time
+    InvalidRecord(LogRecord)    // record whose payload does not match its type
+}
+
+impl fmt::Display for ReplayError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            ReplayError::UnknownType(name) => write!(f, "Unknown message type {}", name),
+            ReplayError::BootFailed(stage) => write!(f, "Module did not complete stage {:?}", stage),
+            ReplayError::InvalidRecord(record) => write!(f, "Cannot replay record: {}", record.to_line().trim_end())
+        }
+    }
+}
+
+/// Outputs of the original run and of the replay, in the order
+/// they were received.
+#[derive(Debug)]
+pub struct ReplayResult
+{
+    pub expected: Vec<LogRecord>,
+    pub actual: Vec<LogRecord>
+}
+
+impl ReplayResult
+{
+    /// Describes all differences between the expected and the actual
+    /// outputs. The outputs are compared per message type, ignoring the
+    /// timestamps, as the order of messages of different types that
+    /// were sent at (nearly) the same time is not deterministic.
+    pub fn differences(&self) -> Vec<String>
+    {
+        let mut types: Vec<&str> = self.expected.iter().chain(self.actual.iter()).map(|x| x.type_name.as_str()).collect();
+        types.sort();
+        types.dedup();
+
+        let mut result = Vec::new();
+        for type_name in types
+        {
+            let expected: Vec<&str> = self.expected.iter().filter(|x| x.type_name == type_name).map(|x| x.payload.as_str()).collect();
+            let actual: Vec<&str> = self.actual.iter().filter(|x| x.type_name == type_name).map(|x| x.payload.as_str()).collect();
+            for index in 0..expected.len().max(actual.len())
+            {
+                match (expected.get(index), actual.get(index))
+                {
+                    (Some(e), Some(a)) if e == a => continue,
+                    (e, a) => result.push(format!("{} #{}: expected {}, got {}", type_name, index, e.unwrap_or(&"nothing"), a.unwrap_or(&"nothing")))
+                }
+            }
+        }
+        result
+    }
+
+    pub fn matches(&self) -> bool
+    {
+        self.differences().is_empty()
+    }
+}
+
+/// # Replay of recorded bus traffic
+/// Runs a single module on a channel manager of its own and
+/// feeds it the recorded messages of the input types, in the
+/// order and with the delays they were recorded with. The
+/// messages the module sends on the output types are captured
+/// and compared with the recorded ones. This allows
+/// turning a log from the field into a regression test:
+/// ```ignore
+/// let mut replay = Replay::new(&["DoorOpenRequest"], &["OutputSwitch"])?;
+/// replay.boot(crate::dcm::trivial::launch)?;
+/// let result = replay.run(&read_log("buslog.txt")?)?;
+/// assert!(result.matches(), "{:?}", result.differences());
+/// ```
+/// The replay takes the place of the supervisor and of the
+/// configuration module, so modules depending on CfgHolder
+/// can be replayed; their configuration can be set using
+/// config() before running the replay.
+pub struct Replay
+{
+    chm: ChannelManager,
+    inputs: Vec<Box<dyn SerializedChannel>>,
+    outputs: Vec<Box<dyn SerializedChannel>>,
+    system_events_tx: GenericSender<SystemMessage>,
+    system_events_rx: GenericReceiver<SystemMessage>,
+    cfg: Shareable<CfgHolder>,
+    module_id: Option<u32>,
+    pub settle_time: u64            // in ms! Time to wait for outputs after the last input was sent.
+}
+
+impl Replay
+{
+    pub fn new(input_types: &[&str], output_types: &[&str]) -> Result<Self, ReplayError>
+    {
+        let mut chm = ChannelManager::new();
+        let mut open = |names: &[&str], receive: bool| -> Result<Vec<Box<dyn SerializedChannel>>, ReplayError> {
+            names.iter()
+                 .map(|x| open_channel(x, receive, &mut chm).ok_or_else(|| ReplayError::UnknownType(x.to_string())))
+                 .collect()
+        };
+        let inputs = open(input_types, false)?;
+        let outputs = open(output_types, true)?;
+
+        Ok(Replay
+        {
+            inputs,
+            outputs,
+            system_events_tx: chm.get_sender(),
+            system_events_rx: chm.get_receiver(),
+            cfg: Shareable::new(CfgHolder::new()),
+            module_id: None,
+            settle_time: 500,
+            chm
+        })
+    }
+
+    /// Launches the module and walks it through all bootstages,
+    /// yields once the module entered the application stage.
+    pub fn boot<T: Fn(&mut ChannelManager)>(&mut self, launcher: T) -> Result<(), ReplayError>
+    {
+        launcher(&mut self.chm);
+        let module_id = self.wait_for_stage(BootStage::Sync, None)?;
+        self.module_id = Some(module_id);
+
+        self.chm.get_sender::<ConfigMessage>().send(ConfigMessage::RegisterHandlers(self.cfg.clone()));
+        for stage in [BootStage::LowLevelInit, BootStage::HighLevelInit].iter()
+        {
+            self.system_events_tx.send(SystemMessage::RunStageFor(*stage, module_id));
+            self.wait_for_stage(*stage, Some(module_id))?;
+        }
+        self.system_events_tx.send(SystemMessage::RunStageFor(BootStage::Application, module_id));
+        Ok(())
+    }
+
+    /// The configuration the module registered its handlers with.
+    pub fn config(&self) -> Shareable<CfgHolder>
+    {
+        self.cfg.clone()
+    }
+
+    /// Sends all records of the input types to the module and
+    /// captures its outputs. Records of other types are ignored,
+    /// except for the output types, which are the expected outputs.
+    pub fn run(&mut self, records: &[LogRecord]) -> Result<ReplayResult, ReplayError>
+    {
+        let mut actual = Vec::new();
+        let expected = records.iter()
+                              .filter(|x| self.outputs.iter().any(|o| o.type_name() == x.type_name))
+                              .cloned()
+                              .collect();
+
+        let started = Instant::now();
+        let first_timestamp = records.first().map(|x| x.timestamp).unwrap_or(0);
+        for record in records.iter()
+        {
+            if let Some(input) = self.inputs.iter().find(|x| x.type_name() == record.type_name)
+            {
+                let due = started + Duration::from_micros(record.timestamp.saturating_sub(first_timestamp));
+                self.capture_until(due, started, &mut actual);
+                if !input.inject(&record.payload)
+                {
+                    return Err(ReplayError::InvalidRecord(record.clone()));
+                }
+            }
+        }
+
+        let settled = Instant::now() + Duration::from_millis(self.settle_time);
+        self.capture_until(settled, started, &mut actual);
+        Ok(ReplayResult { expected, actual })
+    }
+
+    fn capture_until(&self, deadline: Instant, started: Instant, captured: &mut Vec<LogRecord>)
+    {
+        loop
+        {
+            for output in self.outputs.iter()
+            {
+                while let Some(payload) = output.take()
+                {
+                    let timestamp = started.elapsed().as_micros() as u64;
+                    captured.push(LogRecord { timestamp, type_name: output.type_name().to_string(), payload });
+                }
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0
+            {
+                return;
+            }
+            thread::sleep(remaining.min(Duration::from_millis(1)));
+        }
+    }
+
+    fn wait_for_stage(&self, stage: BootStage, module_id: Option<u32>) -> Result<u32, ReplayError>
+    {
+        let deadline = Instant::now() + Duration::from_millis(BOOT_TIMEOUT);
+        loop
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0
+            {
+                return Err(ReplayError::BootFailed(stage));
+            }
+
+            match self.system_events_rx.receive_with_timeout(remaining.as_millis() as u64)
+            {
+                Some(SystemMessage::StageComplete(s, id)) if s == stage && (module_id.is_none() || module_id == Some(id)) => return Ok(id),
+                _ => continue
+            }
+        }
+    }
+}
+
+impl Drop for Replay
+{
+    fn drop(&mut self)
+    {
+        // Lets the module's thread terminate.
+        if let Some(module_id) = self.module_id
+        {
+            self.system_events_tx.send(SystemMessage::ShutdownModule(module_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dcm::trivial;
+
+    fn record(timestamp: u64, type_name: &str, payload: &str) -> LogRecord
+    {
+        LogRecord { timestamp, type_name: type_name.to_string(), payload: payload.to_string() }
+    }
+
+    fn field_log(opened_output: u32) -> Vec<LogRecord>
+    {
+        vec![record(1000, "DoorOpenRequest", "{\"access_point_id\":1,\"identification_token\":[49]}"),
+             record(1500, "OutputSwitch", &format!("{{\"output_id\":{},\"target_state\":\"High\",\"switch_time\":5000}}", opened_output)),
+             record(9000, "SigCommand", "{\"access_point_id\":1,\"sig_type\":\"AccessGranted\",\"duration\":1000}"),
+             record(20000, "DoorOpenRequest", "{\"access_point_id\":2,\"identification_token\":[50]}"),
+             record(20500, "OutputSwitch", "{\"output_id\":2,\"target_state\":\"High\",\"switch_time\":5000}")]
+    }
+
+    fn replay_trivial_dcm(records: &[LogRecord]) -> ReplayResult
+    {
+        let mut replay = Replay::new(&["DoorOpenRequest"], &["OutputSwitch"]).unwrap();
+        replay.settle_time = 100;
+        replay.boot(trivial::launch).unwrap();
+        replay.run(records).unwrap()
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_outputs()
+    {
+        let result = replay_trivial_dcm(&field_log(1));
+        assert_eq!(2, result.actual.len());
+        assert!(result.matches(), "{:?}", result.differences());
+    }
+
+    #[test]
+    fn replay_reports_differing_outputs()
+    {
+        let result = replay_trivial_dcm(&field_log(7));
+        let differences = result.differences();
+        assert_eq!(1, differences.len());
+        assert!(differences[0].starts_with("OutputSwitch #0"));
+    }
+
+    #[test]
+    fn unknown_types_are_rejected()
+    {
+        assert_eq!(Some(ReplayError::UnknownType("ConfigMessage".to_string())), Replay::new(&["ConfigMessage"], &[]).err());
+    }
+}
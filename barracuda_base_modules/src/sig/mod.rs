@@ -1,5 +1,6 @@
+use serde::{Serialize, Deserialize};
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum SigType
 {
     _Default,
@@ -7,7 +8,7 @@ pub enum SigType
     AccessDenied,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct SigCommand
 {
     pub access_point_id: u32,
@@ -1,16 +1,15 @@
-use std::thread;
-use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop}, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager}, trace::trace_helper};
+use barracuda_core::{core::{SystemMessage, bootstage_helper::{boot, boot_noop, handle_system_message}, dependencies::Dependencies, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, module_thread::spawn_module, sud::Sud}, trace::trace_helper};
 
 //use crate::cfg::ConfigMessage;
 
 
-const MODULE_ID: u32 = 0x0C000000;
+const MODULE_ID: u32 = 0x05000000;
 
 pub fn launch(chm: &mut ChannelManager)
 {    
-    let tracer = trace_helper::TraceHelper::new("ProfileControl".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("SIG/SigCtrl".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut wl = SignalControl::new(tracer, chm);
-    thread::spawn(move || {  
+    spawn_module(MODULE_ID, "SIG/SigCtrl", chm, move || {  
         wl.init();   
         loop 
         {
@@ -75,7 +74,7 @@ impl SignalControl
             //     }));            
         });
 
-        boot(MODULE_ID, Some(boot_noop), hli_cb, 
+        boot(MODULE_ID, Dependencies::none(), Some(boot_noop), hli_cb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
@@ -87,13 +86,11 @@ impl SignalControl
         //let mut last_date_time = Local::now();
         loop 
         {
-            // if let Some(e) = self.system_events_rx.receive_with_timeout(5000)
-            // {
-            //     if e == SystemMessage::Shutdown
-            //     {
-            //         return false
-            //     }
-            // }
+            let e = self.system_events_rx.receive();
+            if !handle_system_message(MODULE_ID, e, &self.system_events_tx)
+            {
+                return false
+            }
 
             // let current_time = Local::now();
             // let events = self.checker.lock().tick(current_time, last_date_time);
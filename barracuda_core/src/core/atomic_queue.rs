@@ -1,14 +1,21 @@
 
 
-use std::{collections::VecDeque, sync::{Arc, Mutex}, cell::Cell};
+use std::{collections::VecDeque, sync::{Arc, Mutex, Condvar, atomic::{AtomicBool, AtomicUsize, Ordering}}, cell::Cell, time::Instant};
 use super::event::{DataEvent, Event};
+#[cfg(feature = "async")]
+use std::task::Waker;
 
 
 pub struct AtomicQueue<T: Clone>
 {
     data: Mutex<Cell<VecDeque<T>>>,
     data_trigger: Mutex<Cell<(Option<Arc<DataEvent<u32>>>, u32)>>,
-    evt: Event
+    evt: Event,
+    space: Condvar,             // Notified whenever an element was popped
+    peak_len: AtomicUsize,      // Max. number of elements queued at any time
+    closed: AtomicBool,         // Nobody pops anymore, see close
+    #[cfg(feature = "async")]
+    waker: Mutex<Option<Waker>> // Task waiting for data, woken once by the next push
 }
 
 impl<T: Clone> AtomicQueue<T> {
@@ -19,6 +26,11 @@ impl<T: Clone> AtomicQueue<T> {
             data: Mutex::new(Cell::new(VecDeque::new())), 
             data_trigger: Mutex::new(Cell::new((None, 0))),
             evt: Event::new(),
+            space: Condvar::new(),
+            peak_len: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            #[cfg(feature = "async")]
+            waker: Mutex::new(None)
         } 
     }
 
@@ -26,10 +38,88 @@ impl<T: Clone> AtomicQueue<T> {
     {
         let mut d = self.data.lock().unwrap();
         d.get_mut().push_back(data);
+        self.record_len(d.get_mut().len());
         self.evt.trigger();
         self.do_data_trigger();
     }
 
+    /// Pushes the data if the queue holds less than capacity
+    /// elements, yields the data back otherwise.
+    pub fn try_push(&self, data: T, capacity: usize) -> Result<(), T>
+    {
+        let mut d = self.data.lock().unwrap();
+        if d.get_mut().len() >= capacity.max(1)
+        {
+            return Err(data);
+        }
+        d.get_mut().push_back(data);
+        self.record_len(d.get_mut().len());
+        self.evt.trigger();
+        self.do_data_trigger();
+        Ok(())
+    }
+
+    /// Pushes the data, dropping the oldest elements if the
+    /// queue is full. Yields the number of dropped elements.
+    pub fn push_dropping_oldest(&self, data: T, capacity: usize) -> usize
+    {
+        let mut d = self.data.lock().unwrap();
+        let queue = d.get_mut();
+        let mut dropped = 0;
+        while queue.len() >= capacity.max(1)
+        {
+            queue.pop_front();
+            dropped += 1;
+        }
+        queue.push_back(data);
+        self.record_len(queue.len());
+        self.evt.trigger();
+        self.do_data_trigger();
+        dropped
+    }
+
+    /// Pushes the data once the queue holds less than capacity
+    /// elements, i.e. blocks until the receiver popped enough.
+    /// Yields the data back if there is no room before the deadline.
+    /// A closed queue takes the data right away.
+    pub fn push_waiting(&self, data: T, capacity: usize, deadline: Instant) -> Result<(), T>
+    {
+        let full = |q: &mut Cell<VecDeque<T>>| q.get_mut().len() >= capacity.max(1) && !self.closed.load(Ordering::Relaxed);
+        let d = self.data.lock().unwrap();
+        let (mut d, _) = self.space.wait_timeout_while(d, deadline.saturating_duration_since(Instant::now()), |q| full(q)).unwrap();
+        if full(&mut d)
+        {
+            return Err(data);
+        }
+        d.get_mut().push_back(data);
+        self.record_len(d.get_mut().len());
+        self.evt.trigger();
+        self.do_data_trigger();
+        Ok(())
+    }
+
+    /// Marks the queue as no longer popped, e.g. because its
+    /// receiver was dropped, and releases all waiting pushes.
+    pub fn close(&self)
+    {
+        // Locked, so a push cannot miss the notification
+        // between checking for room and starting to wait.
+        let _d = self.data.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        self.space.notify_all();
+    }
+
+    fn record_len(&self, len: usize)
+    {
+        self.peak_len.fetch_max(len, Ordering::Relaxed);
+    }
+
+    /// Yields the max. number of elements that were queued at the same time.
+    pub fn peak_len(&self) -> usize
+    {
+        self.peak_len.load(Ordering::Relaxed)
+    }
+
     fn do_data_trigger(&self)
     {
         let mut trg = self.data_trigger
@@ -44,6 +134,22 @@ impl<T: Clone> AtomicQueue<T> {
             e.trigger(dat.1);
             dat.0 = Some(e);
         }        
+
+        #[cfg(feature = "async")]
+        {
+            if let Some(waker) = self.waker.lock().unwrap().take()
+            {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Lets the next push wake the given task, replaces
+    /// the waker of a previous call.
+    #[cfg(feature = "async")]
+    pub fn set_waker(&self, waker: Waker)
+    {
+        *self.waker.lock().unwrap() = Some(waker);
     }
 
     pub fn wait_data(&self)
@@ -95,7 +201,12 @@ impl<T: Clone> AtomicQueue<T> {
     pub fn pop(&self) -> Option<T>
     {
         // Rubbish: This never resets the data event, so 
-        return self.data.lock().unwrap().get_mut().pop_front();
+        let result = self.data.lock().unwrap().get_mut().pop_front();
+        if result.is_some()
+        {
+            self.space.notify_all();
+        }
+        return result;
     }
 
     pub fn len(&self) -> usize
@@ -126,6 +237,67 @@ mod tests {
          assert_eq!(32, d); 
      }
 
+     #[test]
+     fn try_push_rejects_data_if_full()
+     {
+         let q = AtomicQueue::new();
+         assert_eq!(Ok(()), q.try_push(1, 2));
+         assert_eq!(Ok(()), q.try_push(2, 2));
+         assert_eq!(Err(3), q.try_push(3, 2));
+         assert_eq!(2, q.len());
+     }
+
+     #[test]
+     fn push_dropping_oldest_keeps_newest_data()
+     {
+         let q = AtomicQueue::new();
+         assert_eq!(0, q.push_dropping_oldest(1, 2));
+         assert_eq!(0, q.push_dropping_oldest(2, 2));
+         assert_eq!(1, q.push_dropping_oldest(3, 2));
+         assert_eq!(Some(2), q.pop());
+         assert_eq!(Some(3), q.pop());
+     }
+
+     fn in_ms(ms: u64) -> Instant
+     {
+         Instant::now() + std::time::Duration::from_millis(ms)
+     }
+
+     #[test]
+     fn push_waiting_continues_after_pop()
+     {
+         let q = Arc::new(AtomicQueue::new());
+         assert_eq!(Ok(()), q.push_waiting(1, 1, in_ms(1000)));
+         let q2 = q.clone();
+         let pusher = std::thread::spawn(move || q2.push_waiting(2, 1, in_ms(1000)));
+         std::thread::sleep(std::time::Duration::from_millis(50));
+         assert_eq!(1, q.len());
+         assert_eq!(Some(1), q.pop());
+         assert_eq!(Ok(()), pusher.join().unwrap());
+         assert_eq!(Some(2), q.pop());
+     }
+
+     #[test]
+     fn push_waiting_yields_data_back_after_timeout()
+     {
+         let q = AtomicQueue::new();
+         assert_eq!(Ok(()), q.push_waiting(1, 1, in_ms(10)));
+         assert_eq!(Err(2), q.push_waiting(2, 1, in_ms(10)));
+         assert_eq!(1, q.len());
+     }
+
+     #[test]
+     fn close_releases_waiting_push()
+     {
+         let q = Arc::new(AtomicQueue::new());
+         q.push(1);
+         let q2 = q.clone();
+         let pusher = std::thread::spawn(move || q2.push_waiting(2, 1, in_ms(10000)));
+         std::thread::sleep(std::time::Duration::from_millis(50));
+         q.close();
+         assert_eq!(Ok(()), pusher.join().unwrap());
+     }
+
      #[test]
      fn returns_trigger_data()
      {        
@@ -1,4 +1,5 @@
 use crate::core::{SystemMessage, BootStage};
+use crate::core::dependencies::Dependencies;
 use crate::core::broadcast_channel::*;
 use crate::trace::trace_helper::TraceHelper;
 
@@ -8,53 +9,110 @@ pub fn boot_noop() {}
 /// "stage complete" for all stages,
 /// making it easier to boot modules
 /// that have no external dependencies.
-pub fn plain_boot(module_id: u32, sys_chan: &GenericSender<SystemMessage>, sys_chan_rx: &GenericReceiver<SystemMessage>, tracer: &TraceHelper)
+pub fn plain_boot(module_id: u32, deps: Dependencies, sys_chan: &GenericSender<SystemMessage>, sys_chan_rx: &GenericReceiver<SystemMessage>, tracer: &TraceHelper)
 {
     tracer.trace_str("Starting");
+    send_registration(module_id, deps, tracer, &sys_chan);
     send_stage_complete(module_id, BootStage::Sync, &sys_chan);
 
-    wait_for_stage(BootStage::LowLevelInit, &sys_chan_rx, tracer);
+    wait_for_stage(module_id, BootStage::LowLevelInit, &sys_chan_rx, tracer);
     tracer.trace_str("Runstage: LLI");
     send_stage_complete(module_id, BootStage::LowLevelInit, &sys_chan);
 
-    wait_for_stage(BootStage::HighLevelInit, &sys_chan_rx, tracer);
+    wait_for_stage(module_id, BootStage::HighLevelInit, &sys_chan_rx, tracer);
     tracer.trace_str("Runstage: HLI");
     send_stage_complete(module_id, BootStage::HighLevelInit, &sys_chan);
 
-    wait_for_stage(BootStage::Application, &sys_chan_rx, tracer);
+    wait_for_stage(module_id, BootStage::Application, &sys_chan_rx, tracer);
     tracer.trace_str("Runstage: APP");
 }
 
-/// This function will return a
-/// "stage complete" for all stages,
-/// making it easier to boot modules
-/// that have no external dependencies.
-pub fn boot<LliCb, HliCb>(module_id: u32, llicb: Option<LliCb>, hlicb: Option<HliCb>, sys_chan: &GenericSender<SystemMessage>, sys_chan_rx: &GenericReceiver<SystemMessage>, tracer: &TraceHelper)
+/// Walks the module through all stages, calling the
+/// given callbacks during LLI and HLI. The supervisor
+/// runs the LLI and HLI of a module only after all
+/// modules providing its dependencies have completed
+/// theirs.
+pub fn boot<LliCb, HliCb>(module_id: u32, deps: Dependencies, llicb: Option<LliCb>, hlicb: Option<HliCb>, sys_chan: &GenericSender<SystemMessage>, sys_chan_rx: &GenericReceiver<SystemMessage>, tracer: &TraceHelper)
     where LliCb: FnOnce() -> (), HliCb: FnOnce() -> ()
 {
     tracer.trace_str("Starting");
+    send_registration(module_id, deps, tracer, &sys_chan);
     send_stage_complete(module_id, BootStage::Sync, &sys_chan);        
 
-    wait_for_stage(BootStage::LowLevelInit, &sys_chan_rx, tracer);
+    wait_for_stage(module_id, BootStage::LowLevelInit, &sys_chan_rx, tracer);
     tracer.trace_str("Runstage: LLI");
     if let Some(lli) = llicb { lli();}
     send_stage_complete(module_id, BootStage::LowLevelInit, &sys_chan);
 
-    wait_for_stage(BootStage::HighLevelInit, &sys_chan_rx, tracer);
+    wait_for_stage(module_id, BootStage::HighLevelInit, &sys_chan_rx, tracer);
     tracer.trace_str("Runstage: HLI");
     if let Some(hli) = hlicb { hli();}
     send_stage_complete(module_id, BootStage::HighLevelInit, &sys_chan);
 
-    wait_for_stage(BootStage::Application, &sys_chan_rx, tracer);
+    wait_for_stage(module_id, BootStage::Application, &sys_chan_rx, tracer);
     tracer.trace_str("Runstage: APP");
 }
 
+/// Handles the system messages every module has to obey
+/// once it has entered the application stage, i.e. it answers
+/// heartbeats on behalf of the module. Returns false if the
+/// module is supposed to terminate, i.e. it was asked to
+/// shut down or to reboot. In both cases the shutdown stage is
+/// acknowledged on behalf of the module, so modules that need
+/// to persist data or to put their peripherals into a safe state
+/// have to do so before passing the message on, see is_stop_request.
+pub fn handle_system_message(module_id: u32, msg: SystemMessage, sys_chan: &GenericSender<SystemMessage>) -> bool
+{
+    if is_stop_request(module_id, &msg)
+    {
+        send_stage_complete(module_id, BootStage::Shutdown, sys_chan);
+        return false;
+    }
+
+    if msg == SystemMessage::Heartbeat
+    {
+        sys_chan.send(SystemMessage::HeartbeatResponse(module_id));
+    }
+    true
+}
+
+/// Checks if the message asks the given module to terminate,
+/// which is the case for a shutdown as well as for a reboot.
+pub fn is_stop_request(module_id: u32, msg: &SystemMessage) -> bool
+{
+    match msg
+    {
+        SystemMessage::ShutdownModule(id) | SystemMessage::Reboot(id) => *id == module_id,
+        _ => false
+    }
+}
+
+/// Processes all pending system messages without blocking,
+/// see handle_system_message. Intended for modules that
+/// poll their peripherals instead of waiting on channels.
+pub fn poll_system_messages(module_id: u32, sys_chan: &GenericSender<SystemMessage>, sys_chan_rx: &GenericReceiver<SystemMessage>) -> bool
+{
+    while let Some(msg) = sys_chan_rx.receive_with_timeout(0)
+    {
+        if !handle_system_message(module_id, msg, sys_chan)
+        {
+            return false;
+        }
+    }
+    true
+}
+
+fn send_registration(module_id: u32, deps: Dependencies, tracer: &TraceHelper, sys_chan: &GenericSender<SystemMessage>)
+{
+    sys_chan.send(SystemMessage::Register(module_id, tracer.module_name().to_string(), deps));
+}
+
 fn send_stage_complete(module_id: u32, stage: BootStage, sys_chan: &GenericSender<SystemMessage>)
 {
     sys_chan.send(SystemMessage::StageComplete(stage, module_id));
 }
 
-fn wait_for_stage(stage: BootStage, sys_chan_rx: &GenericReceiver<SystemMessage>, tracer: &TraceHelper)
+fn wait_for_stage(module_id: u32, stage: BootStage, sys_chan_rx: &GenericReceiver<SystemMessage>, tracer: &TraceHelper)
 {
     tracer.trace(format!("Wait for stage signal {}", stage as u32));
     loop
@@ -65,6 +123,9 @@ fn wait_for_stage(stage: BootStage, sys_chan_rx: &GenericReceiver<SystemMessage>
             SystemMessage::RunStage(s) => if s == stage {
                 break;
             },
+            SystemMessage::RunStageFor(s, id) => if s == stage && id == module_id {
+                break;
+            },
             _ => continue /*ABORTS!*/
         }
     }  
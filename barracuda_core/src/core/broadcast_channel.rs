@@ -1,33 +1,266 @@
-use std::sync::{Arc, Weak};
+use std::collections::VecDeque;
+use std::sync::{Arc, Weak, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::cell::*;
+use std::fmt;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
 use super::{event::DataEvent, atomic_queue::AtomicQueue, shareable::Shareable};
 
 const GARBAGE_THRESHOLD: u32 = 10;
 
+/// Time a sender waits for room in the queues of the receivers,
+/// if the channel uses the Block policy. This is the total for
+/// all receivers of a message, not the time per receiver.
+pub const BLOCK_TIMEOUT: u64 = 1000;   // in ms!
+
+/// Decides what happens to a message that is sent
+/// to a receiver whose queue is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy
+{
+    DropOldest,     // Discards the oldest queued message to make room for the new one.
+    DropNewest,     // Discards the new message.
+    Block,          // Blocks the sender until the receivers made room, receivers without room after BLOCK_TIMEOUT lose the message.
+    Error           // Discards the new message and reports the overflow to the sender, see GenericSender::try_send.
+}
+
+/// # Channel settings
+/// The capacity limits the number of messages queued
+/// for *each* receiver of the channel. Channels are
+/// unbounded unless configured otherwise, see
+/// ChannelManager::configure_channel.
+/// A channel retaining messages keeps the last messages
+/// sent and queues them for each receiver created later,
+/// i.e. a late receiver still gets e.g. the current
+/// configuration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelSettings
+{
+    pub capacity: Option<usize>,        // None means unbounded
+    pub overflow: OverflowPolicy,
+    pub retained: usize                 // Number of messages retained for late receivers, 0 means none
+}
+
+impl ChannelSettings
+{
+    pub fn unbounded() -> Self
+    {
+        ChannelSettings { capacity: None, overflow: OverflowPolicy::DropNewest, retained: 0 }
+    }
+
+    pub fn bounded(capacity: usize, overflow: OverflowPolicy) -> Self
+    {
+        ChannelSettings { capacity: Some(capacity), overflow, retained: 0 }
+    }
+
+    pub fn retaining(mut self, retained: usize) -> Self
+    {
+        self.retained = retained;
+        self
+    }
+}
+
+impl Default for ChannelSettings
+{
+    fn default() -> Self {
+        ChannelSettings::unbounded()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SendError
+{
+    Overflow(usize)     // Number of receivers that did not get the message
+}
+
+impl fmt::Display for SendError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            SendError::Overflow(num) => write!(f, "Message was not delivered to {} receiver(s), their queues are full", num)
+        }
+    }
+}
+
+/// State of the queue of a single receiver.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueueMetrics
+{
+    pub depth: usize,           // Messages currently queued
+    pub peak_depth: usize,      // Max. number of messages queued at the same time
+    pub overflows: u64          // Messages lost, because the queue was full
+}
+
+/// # Bus metrics
+/// Counters of a single channel, see ChannelManager::metrics.
+/// The counters start when the channel is created.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelMetrics
+{
+    pub message_type: String,
+    pub topic: Option<String>,      // None for the default topic of the message type
+    pub sent: u64,                  // Messages sent on the channel
+    pub receivers: usize,           // Live receivers
+    pub collected_receivers: u64,   // Dropped receivers removed from the channel
+    pub overflows: u64,             // Messages lost by all receivers, including dropped ones
+    pub queues: Vec<QueueMetrics>   // One entry per live receiver
+}
+
+/// Yields the metrics of a channel without
+/// knowing its message type.
+pub trait ChannelStats
+{
+    fn metrics(&self) -> ChannelMetrics;
+}
+
+/// Decides whether a message is queued for a receiver, see
+/// ChannelManager::get_receiver_filtered.
+pub type MessageFilter<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// Decides whether a new message supersedes a retained message, see
+/// ChannelManager::retain_latest_by.
+pub type RetainedKey<T> = Arc<dyn Fn(&T, &T) -> bool + Send + Sync>;
+
 pub struct ChannelImpl<T: Clone>
 {
-    receiver_queues:  Shareable<RefCell<Vec<Weak<ReceiverContent<T>>>>>
+    receiver_queues:  Shareable<RefCell<Vec<Weak<ReceiverContent<T>>>>>,
+    settings: Mutex<ChannelSettings>,
+    retained: Mutex<VecDeque<Arc<T>>>,
+    retained_key: Mutex<Option<RetainedKey<T>>>,     // None keeps the last messages regardless of their content
+    overflows: AtomicU64,       // Messages lost by all receivers of the channel, including dropped receivers
+    sent: AtomicU64,
+    collected_receivers: AtomicU64
 }
 
 impl <T: Clone> ChannelImpl<T>
 {
     pub fn new() -> Self{
+        ChannelImpl::with_settings(ChannelSettings::unbounded())
+    }
+
+    pub fn with_settings(settings: ChannelSettings) -> Self{
         ChannelImpl
         {        
-            receiver_queues: Shareable::new(RefCell::new(Vec::new()))
+            receiver_queues: Shareable::new(RefCell::new(Vec::new())),
+            settings: Mutex::new(settings),
+            retained: Mutex::new(VecDeque::new()),
+            retained_key: Mutex::new(None),
+            overflows: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            collected_receivers: AtomicU64::new(0)
         }
     }
 
-    pub fn push_message(&self, data: T)
+    /// Applies to all messages sent afterwards, messages already
+    /// queued are not affected. Retained messages exceeding the
+    /// new number of retained messages are dropped.
+    pub fn configure(&self, settings: ChannelSettings)
+    {
+        let mut retained = self.retained.lock().unwrap();
+        *self.settings.lock().unwrap() = settings;
+        while retained.len() > settings.retained
+        {
+            retained.pop_front();
+        }
+    }
+
+    /// A retained message is replaced by a new message the key function
+    /// considers the same, e.g. an older advertisement of the same module,
+    /// instead of keeping both.
+    pub fn set_retained_key(&self, key: Option<RetainedKey<T>>)
+    {
+        *self.retained_key.lock().unwrap() = key;
+    }
+
+    pub fn settings(&self) -> ChannelSettings
+    {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn overflows(&self) -> u64
+    {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    /// Yields the number of receivers that are still alive.
+    pub fn num_receivers(&self) -> usize
+    {
+        self.collect_receivers().len()
+    }
+
+    /// Delivers the message to every receiver, the overflow policy applies
+    /// to each receiver individually. Only the Error policy yields an error,
+    /// all policies count the lost messages. The message is not copied,
+    /// all receivers share it, see GenericReceiver::receive_shared.
+    pub fn push_message(&self, data: T) -> Result<(), SendError>
+    {
+        let data = Arc::new(data);
+        let settings = self.settings();
+        let receivers = {
+            // Receivers are added while the retained messages are locked,
+            // so a new receiver either gets the message as retained message
+            // or is one of the receivers it is delivered to, never both.
+            let mut retained = self.retained.lock().unwrap();
+            if settings.retained > 0
+            {
+                if let Some(key) = self.retained_key.lock().unwrap().as_ref()
+                {
+                    retained.retain(|x| !key(x, &data));
+                }
+                if retained.len() == settings.retained
+                {
+                    retained.pop_front();
+                }
+                retained.push_back(data.clone());
+            }
+            self.collect_receivers()
+        };
+        let mut rejected = 0;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        // The retained messages are no longer locked, so receivers can
+        // be created while the sender waits. All receivers share one
+        // deadline, a full receiver waited for does not extend it.
+        let deadline = Instant::now() + Duration::from_millis(BLOCK_TIMEOUT);
+        for receiver in receivers.iter()
+        {
+            if !receiver.accepts(&data)
+            {
+                continue;
+            }
+            let lost = receiver.push(data.clone(), settings, deadline);
+            if lost > 0
+            {
+                self.overflows.fetch_add(lost as u64, Ordering::Relaxed);
+                if settings.overflow != OverflowPolicy::DropOldest
+                {
+                    rejected += 1;
+                }
+            }
+        }
+
+        if rejected > 0 && settings.overflow == OverflowPolicy::Error
+        {
+            return Err(SendError::Overflow(rejected));
+        }
+        Ok(())
+    }
+
+    /// Yields all receivers that are still alive. The queues are
+    /// not locked while delivering, so a blocked sender does not
+    /// prevent other threads from creating receivers.
+    fn collect_receivers(&self) -> Vec<Arc<ReceiverContent<T>>>
     {
         let writeable_queues = self.receiver_queues.lock();
         let mut the_vec = writeable_queues.borrow_mut();
         let mut garbage = 0;
+        let mut receivers = Vec::new();
         for i in the_vec.iter()
         {
            if let Some(owned) = i.upgrade()
            {
-                owned.data.push(data.clone());
+                receivers.push(owned);
            }
            else
            {
@@ -43,16 +276,61 @@ impl <T: Clone> ChannelImpl<T>
         // threshold
         if garbage > GARBAGE_THRESHOLD
         {
+            let num_queues = the_vec.len();
             the_vec.retain(|x| x.upgrade().is_some());
+            self.collected_receivers.fetch_add((num_queues - the_vec.len()) as u64, Ordering::Relaxed);
         }
+        receivers
     }
 
+    /// Queues the retained messages for the receiver, before it
+    /// gets any new messages.
     pub fn add_receiver(&self, receiver: Weak<ReceiverContent<T>>)
     {
+        let retained = self.retained.lock().unwrap();
+        if let Some(content) = receiver.upgrade()
+        {
+            // Nobody drains the queue yet, so it must not block.
+            let mut settings = self.settings();
+            if settings.overflow == OverflowPolicy::Block
+            {
+                settings.overflow = OverflowPolicy::DropOldest;
+            }
+            for message in retained.iter().filter(|x| content.accepts(x))
+            {
+                let lost = content.push(message.clone(), settings, Instant::now());
+                self.overflows.fetch_add(lost as u64, Ordering::Relaxed);
+            }
+        }
         self.receiver_queues.lock().borrow_mut().push(receiver)
     }
 }
 
+impl <T: Clone> ChannelStats for ChannelImpl<T>
+{
+    fn metrics(&self) -> ChannelMetrics
+    {
+        let queues: Vec<QueueMetrics> = self.collect_receivers()
+                                            .iter()
+                                            .map(|x| QueueMetrics {
+                                                depth: x.data.len(),
+                                                peak_depth: x.data.peak_len(),
+                                                overflows: x.overflows.load(Ordering::Relaxed)
+                                            })
+                                            .collect();
+        ChannelMetrics
+        {
+            message_type: std::any::type_name::<T>().to_string(),
+            topic: None,
+            sent: self.sent.load(Ordering::Relaxed),
+            receivers: queues.len(),
+            collected_receivers: self.collected_receivers.load(Ordering::Relaxed),
+            overflows: self.overflows(),
+            queues
+        }
+    }
+}
+
 // pub fn make_receiver<T: Clone>(owner: Shareable<RefCell<ChannelImpl<T>>>) -> Arc<GenericReceiver<T>>
 // {
 //     let rec = Arc::new(GenericReceiver::<T>::new(owner.clone()));
@@ -65,7 +343,14 @@ impl <T: Clone> ChannelImpl<T>
 
 pub fn make_receiver<T: Clone>(owner: &Arc<ChannelImpl<T>>) -> GenericReceiver<T>
 {
-    let rec = GenericReceiver::<T>::new(&owner.clone());
+    make_filtered_receiver(owner, None)
+}
+
+/// Creates a receiver that only gets the messages the
+/// filter accepts, None accepts all messages.
+pub fn make_filtered_receiver<T: Clone>(owner: &Arc<ChannelImpl<T>>, filter: Option<MessageFilter<T>>) -> GenericReceiver<T>
+{
+    let rec = GenericReceiver::<T>::with_filter(owner, filter);
     let weak = Arc::downgrade(&rec.contents.clone());
     owner.add_receiver(weak);
     rec
@@ -87,7 +372,43 @@ pub fn make_sender<T: Clone>(owner: &Arc<ChannelImpl<T>>) -> GenericSender<T>
 pub struct ReceiverContent<T: Clone>
 {
     pub owner: Arc<ChannelImpl<T>>,
-    pub data: AtomicQueue<T>
+    pub data: AtomicQueue<Arc<T>>,
+    pub overflows: AtomicU64,
+    pub filter: Option<MessageFilter<T>>
+}
+
+impl <T: Clone> ReceiverContent<T>
+{
+    /// The filter is evaluated by the sending thread, messages
+    /// it rejects are neither cloned nor queued.
+    fn accepts(&self, data: &T) -> bool
+    {
+        match self.filter.as_ref()
+        {
+            Some(filter) => filter(data),
+            None => true
+        }
+    }
+
+    /// Queues the message according to the settings and
+    /// yields the number of messages lost by the receiver. The
+    /// Block policy waits for room until the deadline at most.
+    fn push(&self, data: Arc<T>, settings: ChannelSettings, deadline: Instant) -> usize
+    {
+        let lost = match settings.capacity
+        {
+            None => { self.data.push(data); 0 },
+            Some(capacity) => match settings.overflow
+            {
+                OverflowPolicy::DropOldest => self.data.push_dropping_oldest(data, capacity),
+                OverflowPolicy::DropNewest |
+                OverflowPolicy::Error => if self.data.try_push(data, capacity).is_ok() {0} else {1},
+                OverflowPolicy::Block => if self.data.push_waiting(data, capacity, deadline).is_ok() {0} else {1}
+            }
+        };
+        self.overflows.fetch_add(lost as u64, Ordering::Relaxed);
+        lost
+    }
 }
 
 pub struct GenericReceiver<T: Clone>
@@ -98,11 +419,18 @@ pub struct GenericReceiver<T: Clone>
 impl <T: Clone> GenericReceiver< T>
 {
     pub fn new(owner: &Arc<ChannelImpl<T>>) -> Self
+    {
+        GenericReceiver::with_filter(owner, None)
+    }
+
+    fn with_filter(owner: &Arc<ChannelImpl<T>>, filter: Option<MessageFilter<T>>) -> Self
     {
         GenericReceiver
         {
             contents: Arc::new(ReceiverContent{ owner: owner.clone(),
-                                                     data: AtomicQueue::<T>::new()
+                                                     data: AtomicQueue::<Arc<T>>::new(),
+                                                     overflows: AtomicU64::new(0),
+                                                     filter
                               })
         }
     }
@@ -112,14 +440,22 @@ impl <T: Clone> GenericReceiver< T>
         return make_sender(&self.contents.owner);
     }
 
+    /// The new receiver uses the same filter as this one.
     pub fn clone_receiver(&self) -> Self
     {
-        return make_receiver(&self.contents.owner)
+        return make_filtered_receiver(&self.contents.owner, self.contents.filter.clone())
     }
 
     pub fn push_message(&self, data: T)
     {
-        self.contents.data.push(data);
+        self.contents.data.push(Arc::new(data));
+    }
+
+    /// Yields the number of messages this receiver lost,
+    /// because its queue was full.
+    pub fn overflows(&self) -> u64
+    {
+        self.contents.overflows.load(Ordering::Relaxed)
     }
 
     #[allow(dead_code)]
@@ -128,9 +464,25 @@ impl <T: Clone> GenericReceiver< T>
         return self.contents.data.len() != 0;
     }
 
+    /// Yields the next message, waits until there is one. The
+    /// message is only copied if other receivers still share it.
     pub fn receive(&self) -> T
     {
-        let mut result: Option<T> = None;
+        into_owned(self.receive_shared())
+    }
+
+    pub fn receive_with_timeout(&self, milliseconds: u64) -> Option<T>
+    {
+        self.receive_shared_with_timeout(milliseconds).map(into_owned)
+    }
+
+    /// Like receive, but yields the message as it is shared with
+    /// the other receivers of the channel, i.e. it is never copied.
+    /// Receivers that only read the message (e.g. to serialize it)
+    /// should use this.
+    pub fn receive_shared(&self) -> Arc<T>
+    {
+        let mut result: Option<Arc<T>> = None;
         /*
             Note: Depending on how data arrives,
             there are situations where we actually
@@ -147,7 +499,7 @@ impl <T: Clone> GenericReceiver< T>
         return result.unwrap();   
     }
 
-    pub fn receive_with_timeout(&self, milliseconds: u64) -> Option<T>
+    pub fn receive_shared_with_timeout(&self, milliseconds: u64) -> Option<Arc<T>>
     {
         self.contents.data.wait_with_timeout(milliseconds);
         return self.contents.data.pop();
@@ -159,8 +511,25 @@ impl <T: Clone> GenericReceiver< T>
     }
 }
 
+/// Senders that are blocked on the queue of the receiver
+/// continue right away, instead of waiting for the timeout.
+impl <T: Clone> Drop for GenericReceiver<T>
+{
+    fn drop(&mut self)
+    {
+        self.contents.data.close();
+    }
+}
+
 
 
+/// Takes the message from the Arc if no one else holds it,
+/// copies it otherwise.
+fn into_owned<T: Clone>(data: Arc<T>) -> T
+{
+    Arc::try_unwrap(data).unwrap_or_else(|shared| (*shared).clone())
+}
+
 pub struct GenericSender<T: Clone>
 {
     source: Arc<ChannelImpl<T>>
@@ -174,9 +543,38 @@ impl <T: Clone> GenericSender<T>
         }
     }
 
+    /// Sends the message to all receivers. Messages that
+    /// overflow a receiver's queue are lost silently, i.e.
+    /// they only show up in the overflow counters.
     pub fn send(&self, data: T)
     {
-        self.source.push_message(data.clone());
+        let _ = self.source.push_message(data);
+    }
+
+    /// Like send, but reports overflows if the channel
+    /// uses the Error policy.
+    pub fn try_send(&self, data: T) -> Result<(), SendError>
+    {
+        self.source.push_message(data)
+    }
+
+    /// Yields the number of messages lost by all
+    /// receivers of the channel.
+    pub fn overflows(&self) -> u64
+    {
+        self.source.overflows()
+    }
+
+    pub fn num_receivers(&self) -> usize
+    {
+        self.source.num_receivers()
+    }
+
+    /// Yields a new receiver of the channel, e.g. for a module
+    /// that only receives while someone needs the messages.
+    pub fn create_receiver(&self) -> GenericReceiver<T>
+    {
+        make_receiver(&self.source)
     }
 
     pub fn clone(&self) -> Self
@@ -188,6 +586,33 @@ impl <T: Clone> GenericSender<T>
 }
 
 
+/// # Async receiving
+/// With the "async" feature a receiver is a Stream of the messages
+/// sent on its channel, so a module can wait for bus messages and
+/// e.g. sockets in the same task, using any executor. The stream
+/// never ends, as channels are never closed.
+#[cfg(feature = "async")]
+impl <T: Clone> futures_core::Stream for GenericReceiver<T>
+{
+    type Item = T;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<T>>
+    {
+        if let Some(msg) = self.contents.data.pop()
+        {
+            return std::task::Poll::Ready(Some(into_owned(msg)));
+        }
+
+        self.contents.data.set_waker(cx.waker().clone());
+        // A message sent before the waker was set would not wake the task.
+        match self.contents.data.pop()
+        {
+            Some(msg) => std::task::Poll::Ready(Some(into_owned(msg))),
+            None => std::task::Poll::Pending
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
      use crate::core::broadcast_channel::*;
@@ -245,4 +670,272 @@ mod tests {
         tx.send(24);
         assert!(Some(24) == rx.receive_with_timeout(50))
     }
+
+    pub fn make_bounded_chan<T: Clone>(capacity: usize, overflow: OverflowPolicy) -> (GenericSender<T>, GenericReceiver<T>)
+    {
+        let chan = Arc::new(ChannelImpl::<T>::with_settings(ChannelSettings::bounded(capacity, overflow)));
+        (make_sender(&chan), make_receiver(&chan))
+    }
+
+    #[test]
+    fn drop_oldest_keeps_newest_messages()
+    {
+        let (tx, rx) = make_bounded_chan(2, OverflowPolicy::DropOldest);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(Ok(()), tx.try_send(3));
+        assert_eq!(2, rx.receive());
+        assert_eq!(3, rx.receive());
+        assert_eq!(1, rx.overflows());
+    }
+
+    #[test]
+    fn drop_newest_keeps_oldest_messages()
+    {
+        let (tx, rx) = make_bounded_chan(2, OverflowPolicy::DropNewest);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(Ok(()), tx.try_send(3));
+        assert_eq!(1, rx.receive());
+        assert_eq!(2, rx.receive());
+        assert_eq!(None, rx.receive_with_timeout(10));
+        assert_eq!(1, tx.overflows());
+    }
+
+    #[test]
+    fn error_policy_reports_full_receivers()
+    {
+        let (tx, rx) = make_bounded_chan(1, OverflowPolicy::Error);
+        let rx2 = rx.clone_receiver();
+        tx.send(1);
+        rx2.receive();
+        assert_eq!(Err(SendError::Overflow(1)), tx.try_send(2));
+        assert_eq!(2, rx2.receive());
+        assert_eq!(1, rx.overflows());
+        assert_eq!(0, rx2.overflows());
+    }
+
+    #[test]
+    fn block_policy_gives_up_on_receiver_that_does_not_drain()
+    {
+        let (tx, rx) = make_bounded_chan(1, OverflowPolicy::Block);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(1, rx.overflows());
+        assert_eq!(1, rx.receive());
+        assert!(rx.receive_with_timeout(0).is_none());
+    }
+
+    #[test]
+    fn block_policy_waits_once_for_all_receivers()
+    {
+        let (tx, rx) = make_bounded_chan(1, OverflowPolicy::Block);
+        let receivers = vec![rx.clone_receiver(), rx.clone_receiver(), rx.clone_receiver()];
+        tx.send(1);
+        let started = std::time::Instant::now();
+        tx.send(2);
+        assert!(started.elapsed() < std::time::Duration::from_millis(BLOCK_TIMEOUT * 3 / 2));
+        assert_eq!(4, tx.overflows());
+        assert!(receivers.iter().all(|x| x.overflows() == 1));
+    }
+
+    #[test]
+    fn receivers_can_be_created_while_sender_blocks()
+    {
+        let chan = Arc::new(ChannelImpl::<i32>::with_settings(ChannelSettings::bounded(1, OverflowPolicy::Block).retaining(1)));
+        let tx = make_sender(&chan);
+        let _rx = make_receiver(&chan);
+        tx.send(1);
+        let sender = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let started = std::time::Instant::now();
+        let late = make_receiver(&chan);
+        assert!(started.elapsed() < std::time::Duration::from_millis(BLOCK_TIMEOUT / 2));
+        assert_eq!(Some(2), late.receive_with_timeout(0));
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn block_policy_releases_sender_if_receiver_is_dropped()
+    {
+        let (tx, rx) = make_bounded_chan(1, OverflowPolicy::Block);
+        tx.send(1);
+        let started = std::time::Instant::now();
+        let sender = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(rx);
+        sender.join().unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(BLOCK_TIMEOUT / 2));
+    }
+
+    #[test]
+    fn block_policy_waits_for_receiver()
+    {
+        let (tx, rx) = make_bounded_chan(1, OverflowPolicy::Block);
+        tx.send(1);
+        let sender = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(1, rx.receive());
+        sender.join().unwrap();
+        assert_eq!(2, rx.receive());
+        assert_eq!(0, rx.overflows());
+    }
+
+    static CLONES: AtomicU64 = AtomicU64::new(0);
+
+    struct Token(Vec<u8>);
+
+    impl Clone for Token
+    {
+        fn clone(&self) -> Self
+        {
+            CLONES.fetch_add(1, Ordering::Relaxed);
+            Token(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn receivers_share_the_message()
+    {
+        let chan = Arc::new(ChannelImpl::<Token>::new());
+        let tx = make_sender(&chan);
+        let shared = make_receiver(&chan);
+        let owned = make_receiver(&chan);
+        let last = make_receiver(&chan);
+        tx.send(Token(vec![1, 2, 3, 4]));
+
+        let shared_msg = shared.receive_shared();
+        assert_eq!(vec![1, 2, 3, 4], owned.receive().0);
+        drop(shared_msg);
+        // The last receiver holds the only reference, so nothing is copied.
+        assert_eq!(vec![1, 2, 3, 4], last.receive().0);
+        assert_eq!(1, CLONES.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn late_receiver_gets_retained_messages()
+    {
+        let chan = Arc::new(ChannelImpl::<u32>::with_settings(ChannelSettings::unbounded().retaining(2)));
+        let tx = make_sender(&chan);
+        let early = make_receiver(&chan);
+        for i in 1..4
+        {
+            tx.send(i);
+        }
+
+        let late = make_receiver(&chan);
+        tx.send(4);
+        assert_eq!(vec![2, 3, 4], vec![late.receive(), late.receive(), late.receive()]);
+        assert_eq!(None, late.receive_with_timeout(0));
+        assert_eq!(vec![1, 2, 3, 4], vec![early.receive(), early.receive(), early.receive(), early.receive()]);
+        assert_eq!(None, early.receive_with_timeout(0));
+    }
+
+    #[test]
+    fn retained_messages_are_dropped_when_reconfigured()
+    {
+        let chan = Arc::new(ChannelImpl::<u32>::with_settings(ChannelSettings::unbounded().retaining(2)));
+        let tx = make_sender(&chan);
+        tx.send(1);
+        tx.send(2);
+        chan.configure(ChannelSettings::unbounded().retaining(1));
+
+        let late = make_receiver(&chan);
+        assert_eq!(2, late.receive());
+        assert_eq!(None, late.receive_with_timeout(0));
+    }
+
+    #[test]
+    fn overflows_of_dropped_receivers_are_still_counted()
+    {
+        let (tx, rx) = make_bounded_chan(1, OverflowPolicy::DropNewest);
+        tx.send(1);
+        tx.send(2);
+        drop(rx);
+        assert_eq!(1, tx.overflows());
+    }
+
+    #[test]
+    fn metrics_count_messages_and_queue_depth()
+    {
+        let (tx, rx) = make_chan::<u32>();
+        tx.send(1);
+        tx.send(2);
+        let _ = rx.receive();
+        tx.send(3);
+
+        let metrics = tx.source.metrics();
+        assert_eq!("u32", metrics.message_type);
+        assert_eq!(3, metrics.sent);
+        assert_eq!(1, metrics.receivers);
+        assert_eq!(vec![QueueMetrics { depth: 2, peak_depth: 2, overflows: 0 }], metrics.queues);
+    }
+
+    #[test]
+    fn metrics_count_collected_receivers()
+    {
+        let (tx, rx) = make_chan::<u32>();
+        for _ in 0..GARBAGE_THRESHOLD + 1
+        {
+            drop(rx.clone_receiver());
+        }
+        tx.send(1);
+
+        let metrics = tx.source.metrics();
+        assert_eq!(1, metrics.receivers);
+        assert_eq!((GARBAGE_THRESHOLD + 1) as u64, metrics.collected_receivers);
+    }
+
+    #[cfg(feature = "async")]
+    mod stream {
+        use super::*;
+        use futures_core::Stream;
+        use std::{pin::Pin, sync::atomic::AtomicBool, task::{Context, Poll, Wake, Waker}, thread, time::Duration};
+
+        struct FlagWaker(AtomicBool);
+
+        impl Wake for FlagWaker
+        {
+            fn wake(self: Arc<Self>)
+            {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        fn poll_next(rx: &mut GenericReceiver<u32>, waker: &Arc<FlagWaker>) -> Poll<Option<u32>>
+        {
+            let waker = Waker::from(waker.clone());
+            Pin::new(rx).poll_next(&mut Context::from_waker(&waker))
+        }
+
+        #[test]
+        fn stream_yields_queued_messages()
+        {
+            let (tx, mut rx) = make_chan();
+            let waker = Arc::new(FlagWaker(AtomicBool::new(false)));
+            tx.send(1);
+            tx.send(2);
+            assert_eq!(Poll::Ready(Some(1)), poll_next(&mut rx, &waker));
+            assert_eq!(Poll::Ready(Some(2)), poll_next(&mut rx, &waker));
+            assert_eq!(Poll::Pending, poll_next(&mut rx, &waker));
+        }
+
+        #[test]
+        fn send_wakes_pending_stream()
+        {
+            let (tx, mut rx) = make_chan();
+            let waker = Arc::new(FlagWaker(AtomicBool::new(false)));
+            assert_eq!(Poll::Pending, poll_next(&mut rx, &waker));
+
+            let sender = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send(4711);
+            });
+            sender.join().unwrap();
+
+            assert!(waker.0.load(Ordering::SeqCst));
+            assert_eq!(Poll::Ready(Some(4711)), poll_next(&mut rx, &waker));
+        }
+    }
 }
@@ -2,13 +2,32 @@
 use crate::core::broadcast_channel::*;
 extern crate anymap;
 use anymap::AnyMap;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use crate::core::shareable::Shareable;
+use crate::core::clock::{Clock, system_clock};
+use crate::core::timer::Timer;
 
 unsafe impl Send for ChannelManager{}
 
+/// All channels of a message type, keyed by their topic.
+/// None is the topic of the channel used by get_sender
+/// and get_receiver.
+type Topics<T> = HashMap<Option<String>, Arc<ChannelImpl<T>>>;
+
+/// Statistics of a channel along with its topic.
+type TopicStats = (Option<String>, Arc<dyn ChannelStats>);
+
+/// # The channel manager
+/// Provides one channel per message type and topic. Most channels
+/// only use the default topic (get_sender/get_receiver), topics
+/// separate the traffic of e.g. multiple instances of a module:
+/// Messages sent on a topic only reach the receivers of the same
+/// topic, they do not reach receivers of the default topic.
 pub struct ChannelManager {
     channels: Shareable<anymap::Map>,
+    stats: Shareable<Vec<TopicStats>>,
+    clock: Shareable<Arc<dyn Clock>>,
+    timer: Shareable<Option<Arc<Timer>>>
 }
 
 impl ChannelManager  {
@@ -16,29 +35,175 @@ impl ChannelManager  {
     pub fn new() -> Self {
         let res = ChannelManager {
             channels: Shareable::new(AnyMap::new()),
+            stats: Shareable::new(Vec::new()),
+            clock: Shareable::new(system_clock()),
+            timer: Shareable::new(None)
         };
         res
     }
 
-    fn ensure_channel_exists<T: 'static + Clone>(&mut self)
+    /// Yields the channel for messages of type T on the
+    /// given topic, creating the channel if necessary.
+    fn channel<T: 'static + Clone + Send + Sync>(&mut self, topic: Option<&str>) -> Arc<ChannelImpl<T>>
     {
         let mut writeable_channels = self.channels.lock();
-        if !writeable_channels.contains::<Arc<ChannelImpl<T>>>()
+        if !writeable_channels.contains::<Topics<T>>()
         {
-            writeable_channels.insert(Arc::new(ChannelImpl::<T>::new()));
+            writeable_channels.insert(Topics::<T>::new());
         }
+
+        let topics = writeable_channels.get_mut::<Topics<T>>().unwrap();
+        let stats = &self.stats;
+        topics.entry(topic.map(|x| x.to_string()))
+              .or_insert_with(|| {
+                  let channel = Arc::new(ChannelImpl::<T>::new());
+                  stats.lock().push((topic.map(|x| x.to_string()), channel.clone()));
+                  channel
+              })
+              .clone()
+    }
+
+    /// Sets the capacity and overflow policy of the channel
+    /// for messages of type T, creating the channel if necessary.
+    /// Applies to all messages sent afterwards.
+    pub fn configure_channel<T: 'static + Clone + Send + Sync>(&mut self, settings: ChannelSettings)
+    {
+        self.channel::<T>(None).configure(settings);
+    }
+
+    /// Keeps the last count messages of type T and delivers them
+    /// to each receiver created later, e.g. to a module that is
+    /// rebooted. The capacity and overflow policy of the channel
+    /// are kept. Has to be called before the messages are sent.
+    pub fn retain_messages<T: 'static + Clone + Send + Sync>(&mut self, count: usize)
+    {
+        let channel = self.channel::<T>(None);
+        channel.configure(channel.settings().retaining(count));
     }
 
-    pub fn get_receiver<T: 'static + Clone>(&mut self) -> GenericReceiver<T> 
+    /// Like retain_messages, for the channel of the given topic.
+    pub fn retain_topic_messages<T: 'static + Clone + Send + Sync>(&mut self, topic: &str, count: usize)
     {
-        self.ensure_channel_exists::<T>();
-        make_receiver(self.channels.lock().get_mut::<Arc<ChannelImpl<T>>>().unwrap())
+        let channel = self.channel::<T>(Some(topic));
+        channel.configure(channel.settings().retaining(count));
     }
 
-    pub fn get_sender<T: 'static + Clone>(&mut self) -> GenericSender<T> 
+    /// Like retain_messages, but a new message replaces the retained
+    /// message that same_key considers the same, so e.g. repeated
+    /// messages of one module do not push out those of others.
+    pub fn retain_latest_by<T, F>(&mut self, count: usize, same_key: F)
+        where T: 'static + Clone + Send + Sync, F: Fn(&T, &T) -> bool + Send + Sync + 'static
     {
-        self.ensure_channel_exists::<T>();
-        make_sender(self.channels.lock().get::<Arc<ChannelImpl<T>>>().unwrap())
+        let channel = self.channel::<T>(None);
+        channel.set_retained_key(Some(Arc::new(same_key)));
+        channel.configure(channel.settings().retaining(count));
+    }
+
+    /// Like retain_latest_by, for the channel of the given topic.
+    pub fn retain_topic_latest_by<T, F>(&mut self, topic: &str, count: usize, same_key: F)
+        where T: 'static + Clone + Send + Sync, F: Fn(&T, &T) -> bool + Send + Sync + 'static
+    {
+        let channel = self.channel::<T>(Some(topic));
+        channel.set_retained_key(Some(Arc::new(same_key)));
+        channel.configure(channel.settings().retaining(count));
+    }
+
+    /// Like configure_channel, for the channel of the given topic.
+    pub fn configure_topic_channel<T: 'static + Clone + Send + Sync>(&mut self, topic: &str, settings: ChannelSettings)
+    {
+        self.channel::<T>(Some(topic)).configure(settings);
+    }
+
+    /// Yields the metrics of all channels, ordered by message type and topic.
+    pub fn metrics(&self) -> Vec<ChannelMetrics>
+    {
+        let mut result: Vec<ChannelMetrics> = self.stats.lock()
+                                                  .iter()
+                                                  .map(|(topic, channel)| ChannelMetrics { topic: topic.clone(), ..channel.metrics() })
+                                                  .collect();
+        result.sort_by(|a, b| (&a.message_type, &a.topic).cmp(&(&b.message_type, &b.topic)));
+        result
+    }
+
+    /// Yields the clock all modules sharing this channel manager
+    /// shall use, which is the system clock unless set otherwise.
+    pub fn clock(&self) -> Arc<dyn Clock>
+    {
+        self.clock.lock().clone()
+    }
+
+    /// Note: Has to be called before the modules are created,
+    /// modules obtain the clock only once.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>)
+    {
+        *self.clock.lock() = clock;
+    }
+
+    /// Yields the timer shared by all modules using this channel
+    /// manager. It is started on first use and runs on the clock
+    /// of the channel manager.
+    pub fn timer(&self) -> Arc<Timer>
+    {
+        let mut timer = self.timer.lock();
+        if timer.is_none()
+        {
+            *timer = Some(Timer::with_clock(self.clock()));
+        }
+        timer.as_ref().unwrap().clone()
+    }
+
+    /// Stops the timer if it was started, see Timer::stop.
+    /// Modules still holding the timer can no longer use it.
+    pub fn stop_timer(&self)
+    {
+        let timer = self.timer.lock().take();
+        if let Some(timer) = timer
+        {
+            timer.stop();
+        }
+    }
+
+    pub fn get_receiver<T: 'static + Clone + Send + Sync>(&mut self) -> GenericReceiver<T> 
+    {
+        make_receiver(&self.channel::<T>(None))
+    }
+
+    /// Yields a receiver that only gets the messages of type T
+    /// the filter accepts, e.g. the requests for the access points
+    /// of a module. The filter is evaluated by the sender, so
+    /// rejected messages are never cloned into the receiver's queue
+    /// and do not wake it up. It must therefore be cheap and must
+    /// not send messages of type T itself.
+    pub fn get_receiver_filtered<T, F>(&mut self, filter: F) -> GenericReceiver<T>
+        where T: 'static + Clone + Send + Sync, F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        make_filtered_receiver(&self.channel::<T>(None), Some(Arc::new(filter)))
+    }
+
+    pub fn get_sender<T: 'static + Clone + Send + Sync>(&mut self) -> GenericSender<T> 
+    {
+        make_sender(&self.channel::<T>(None))
+    }
+
+    /// Yields a receiver for messages of type T that were
+    /// sent on the given topic.
+    pub fn get_topic_receiver<T: 'static + Clone + Send + Sync>(&mut self, topic: &str) -> GenericReceiver<T>
+    {
+        make_receiver(&self.channel::<T>(Some(topic)))
+    }
+
+    /// Like get_receiver_filtered, for messages of type T that
+    /// were sent on the given topic.
+    pub fn get_topic_receiver_filtered<T, F>(&mut self, topic: &str, filter: F) -> GenericReceiver<T>
+        where T: 'static + Clone + Send + Sync, F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        make_filtered_receiver(&self.channel::<T>(Some(topic)), Some(Arc::new(filter)))
+    }
+
+    /// Yields a sender for messages of type T on the given topic.
+    pub fn get_topic_sender<T: 'static + Clone + Send + Sync>(&mut self, topic: &str) -> GenericSender<T>
+    {
+        make_sender(&self.channel::<T>(Some(topic)))
     }
 }
 
@@ -47,7 +212,10 @@ impl Clone for ChannelManager
     fn clone(&self) -> Self {
         Self
         {
-            channels: self.channels.clone()
+            channels: self.channels.clone(),
+            stats: self.stats.clone(),
+            clock: self.clock.clone(),
+            timer: self.timer.clone()
         }
     }
 }
@@ -85,4 +253,186 @@ mod tests {
         assert_eq!(4711, rx1.receive());
         assert_eq!(4951, rx1.receive())       
     }
+
+    #[test]
+    fn configuration_applies_to_existing_receivers()
+    {
+        let mut ch = ChannelManager::new();
+        let rx1 = ch.get_receiver::<i32>();
+        ch.configure_channel::<i32>(ChannelSettings::bounded(1, OverflowPolicy::DropOldest));
+        let tx1 = ch.get_sender::<i32>();
+
+        tx1.send(4711);
+        tx1.send(4951);
+        assert_eq!(4951, rx1.receive());
+        assert_eq!(1, tx1.overflows());
+    }
+
+    #[test]
+    fn retaining_keeps_capacity()
+    {
+        let mut ch = ChannelManager::new();
+        ch.configure_channel::<i32>(ChannelSettings::bounded(1, OverflowPolicy::DropOldest));
+        ch.retain_messages::<i32>(1);
+        ch.get_sender::<i32>().send(4711);
+
+        let rx1 = ch.get_receiver::<i32>();
+        assert_eq!(4711, rx1.receive());
+        assert_eq!(ChannelSettings::bounded(1, OverflowPolicy::DropOldest).retaining(1), ch.channel::<i32>(None).settings());
+    }
+
+    #[test]
+    fn retaining_latest_by_key_replaces_older_messages()
+    {
+        let mut ch = ChannelManager::new();
+        ch.retain_latest_by::<(u32, u32), _>(2, |a, b| a.0 == b.0);
+        let tx = ch.get_sender::<(u32, u32)>();
+        tx.send((1, 1));
+        tx.send((2, 1));
+        tx.send((2, 2));
+        tx.send((2, 3));
+
+        let rx = ch.get_receiver::<(u32, u32)>();
+        assert_eq!(vec![(1, 1), (2, 3)], vec![rx.receive(), rx.receive()]);
+        assert_eq!(None, rx.receive_with_timeout(0));
+    }
+
+    #[test]
+    fn filtered_receiver_only_gets_accepted_messages()
+    {
+        let mut ch = ChannelManager::new();
+        ch.retain_messages::<i32>(2);
+        let tx = ch.get_sender::<i32>();
+        tx.send(1);
+        tx.send(2);
+
+        let even = ch.get_receiver_filtered::<i32, _>(|x| x % 2 == 0);
+        let all = ch.get_receiver::<i32>();
+        tx.send(3);
+        tx.send(4);
+
+        assert_eq!(vec![2, 4], vec![even.receive(), even.receive()]);
+        assert_eq!(None, even.receive_with_timeout(0));
+        assert_eq!(vec![1, 2, 3, 4], vec![all.receive(), all.receive(), all.receive(), all.receive()]);
+
+        // The clone gets the retained 3 and 4 as well, filtered.
+        let cloned = even.clone_receiver();
+        tx.send(5);
+        tx.send(6);
+        assert_eq!(vec![4, 6], vec![cloned.receive(), cloned.receive()]);
+        assert_eq!(None, cloned.receive_with_timeout(0));
+    }
+
+    #[test]
+    fn clones_share_the_timer()
+    {
+        let ch = ChannelManager::new();
+        let clone = ch.clone();
+        assert!(Arc::ptr_eq(&ch.timer(), &clone.timer()));
+    }
+
+    #[test]
+    fn metrics_cover_all_channels()
+    {
+        let mut ch = ChannelManager::new();
+        let _rx = ch.get_receiver::<i32>();
+        ch.get_sender::<i32>().send(4711);
+        ch.get_sender::<u8>().send(1);
+
+        let metrics = ch.clone().metrics();
+        assert_eq!(2, metrics.len());
+        assert_eq!(("i32", 1, 1), (metrics[0].message_type.as_str(), metrics[0].sent, metrics[0].receivers));
+        assert_eq!(("u8", 1, 0), (metrics[1].message_type.as_str(), metrics[1].sent, metrics[1].receivers));
+    }
+
+    #[test]
+    fn topics_are_isolated()
+    {
+        let mut ch = ChannelManager::new();
+        let default_rx = ch.get_receiver::<i32>();
+        let door1_rx = ch.get_topic_receiver::<i32>("door1");
+        let door2_rx = ch.get_topic_receiver::<i32>("door2");
+
+        ch.get_topic_sender::<i32>("door1").send(4711);
+        ch.get_sender::<i32>().send(4951);
+
+        assert_eq!(4711, door1_rx.receive());
+        assert_eq!(4951, default_rx.receive());
+        assert!(!door1_rx.has_data());
+        assert!(!door2_rx.has_data());
+        assert!(!default_rx.has_data());
+    }
+
+    #[test]
+    fn topic_settings_are_isolated()
+    {
+        let mut ch = ChannelManager::new();
+        ch.configure_topic_channel::<i32>("door1", ChannelSettings::bounded(1, OverflowPolicy::DropOldest));
+        let door1_rx = ch.get_topic_receiver::<i32>("door1");
+        let door2_rx = ch.get_topic_receiver::<i32>("door2");
+        for topic in ["door1", "door2"].iter()
+        {
+            let tx = ch.get_topic_sender::<i32>(topic);
+            tx.send(1);
+            tx.send(2);
+        }
+
+        assert_eq!(2, door1_rx.receive());
+        assert_eq!(1, door1_rx.overflows());
+        assert_eq!(vec![1, 2], vec![door2_rx.receive(), door2_rx.receive()]);
+        assert_eq!(ChannelSettings::unbounded(), ch.channel::<i32>(Some("door2")).settings());
+    }
+
+    #[test]
+    fn topic_retention_is_isolated()
+    {
+        let mut ch = ChannelManager::new();
+        ch.retain_topic_messages::<i32>("door1", 1);
+        ch.retain_topic_latest_by::<(u32, u32), _>("door2", 2, |a, b| a.0 == b.0);
+        ch.get_topic_sender::<i32>("door1").send(4711);
+        ch.get_topic_sender::<i32>("door2").send(4951);
+        let pairs = ch.get_topic_sender::<(u32, u32)>("door2");
+        pairs.send((1, 1));
+        pairs.send((1, 2));
+        ch.get_topic_sender::<(u32, u32)>("door1").send((2, 1));
+
+        assert_eq!(Some(4711), ch.get_topic_receiver::<i32>("door1").receive_with_timeout(0));
+        assert_eq!(None, ch.get_topic_receiver::<i32>("door2").receive_with_timeout(0));
+        assert_eq!(None, ch.get_receiver::<i32>().receive_with_timeout(0));
+        let door2_rx = ch.get_topic_receiver::<(u32, u32)>("door2");
+        assert_eq!(Some((1, 2)), door2_rx.receive_with_timeout(0));
+        assert_eq!(None, door2_rx.receive_with_timeout(0));
+        assert_eq!(None, ch.get_topic_receiver::<(u32, u32)>("door1").receive_with_timeout(0));
+    }
+
+    #[test]
+    fn topic_filters_are_isolated()
+    {
+        let mut ch = ChannelManager::new();
+        let door1_even = ch.get_topic_receiver_filtered::<i32, _>("door1", |x| x % 2 == 0);
+        let door2_rx = ch.get_topic_receiver::<i32>("door2");
+        for topic in ["door1", "door2"].iter()
+        {
+            let tx = ch.get_topic_sender::<i32>(topic);
+            tx.send(1);
+            tx.send(2);
+        }
+
+        assert_eq!(2, door1_even.receive());
+        assert!(!door1_even.has_data());
+        assert_eq!(vec![1, 2], vec![door2_rx.receive(), door2_rx.receive()]);
+    }
+
+    #[test]
+    fn metrics_contain_topic()
+    {
+        let mut ch = ChannelManager::new();
+        ch.get_topic_sender::<i32>("door1").send(4711);
+        ch.get_sender::<i32>().send(4951);
+
+        let metrics = ch.metrics();
+        assert_eq!(2, metrics.len());
+        assert_eq!(None, metrics[0].topic);
+        assert_eq!(Some("door1".to_string()), metrics[1].topic);
+    }
 }
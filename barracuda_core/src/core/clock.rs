@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use super::event::Event;
+
+/// Interval in which a wait on a virtual clock checks
+/// if the clock was advanced past its timeout.
+const VIRTUAL_POLL_INTERVAL: u64 = 1;
+
+/// # The source of time
+/// All time dependent behavior (timers, time profiles) shall
+/// read the time from a clock instead of the system, so tests
+/// can use a VirtualClock and jump in time instead of sleeping.
+/// Modules obtain the clock from the channel manager.
+pub trait Clock: Send + Sync
+{
+    /// Monotonic time, to be used for delays and timeouts.
+    fn now(&self) -> Instant;
+
+    /// Local wall clock time, to be used for schedules.
+    fn local_time(&self) -> DateTime<Local>;
+
+    /// Waits until the event is triggered or the timeout has
+    /// passed on this clock. Yields true if the event was triggered.
+    fn wait(&self, evt: &Event, timeout: Duration) -> bool;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock
+{
+    fn now(&self) -> Instant
+    {
+        Instant::now()
+    }
+
+    fn local_time(&self) -> DateTime<Local>
+    {
+        Local::now()
+    }
+
+    fn wait(&self, evt: &Event, timeout: Duration) -> bool
+    {
+        evt.wait_with_timeout(timeout.as_millis() as u64)
+    }
+}
+
+pub fn system_clock() -> Arc<dyn Clock>
+{
+    Arc::new(SystemClock)
+}
+
+/// A clock that stands still until it is advanced.
+pub struct VirtualClock
+{
+    start: Instant,
+    start_local: DateTime<Local>,
+    elapsed: Mutex<Duration>
+}
+
+impl Default for VirtualClock
+{
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl VirtualClock
+{
+    /// Creates a clock starting at the current time.
+    pub fn new() -> Self
+    {
+        VirtualClock::starting_at(Local::now())
+    }
+
+    pub fn starting_at(local_time: DateTime<Local>) -> Self
+    {
+        VirtualClock
+        {
+            start: Instant::now(),
+            start_local: local_time,
+            elapsed: Mutex::new(Duration::from_millis(0))
+        }
+    }
+
+    pub fn advance(&self, duration: Duration)
+    {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    fn elapsed(&self) -> Duration
+    {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for VirtualClock
+{
+    fn now(&self) -> Instant
+    {
+        self.start + self.elapsed()
+    }
+
+    fn local_time(&self) -> DateTime<Local>
+    {
+        self.start_local + chrono::Duration::from_std(self.elapsed()).unwrap()
+    }
+
+    fn wait(&self, evt: &Event, timeout: Duration) -> bool
+    {
+        let deadline = self.now() + timeout;
+        loop
+        {
+            if evt.wait_with_timeout(VIRTUAL_POLL_INTERVAL)
+            {
+                return true;
+            }
+            if self.now() >= deadline
+            {
+                return false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::thread;
+
+    #[test]
+    fn virtual_clock_stands_still_until_advanced()
+    {
+        let clock = VirtualClock::starting_at(Local.with_ymd_and_hms(2020, 5, 4, 7, 59, 0).unwrap());
+        let start = clock.now();
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(start, clock.now());
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(start + Duration::from_secs(90), clock.now());
+        assert_eq!(Local.with_ymd_and_hms(2020, 5, 4, 8, 0, 30).unwrap(), clock.local_time());
+    }
+
+    #[test]
+    fn virtual_wait_ends_once_clock_was_advanced()
+    {
+        let clock = Arc::new(VirtualClock::new());
+        let advancing_clock = clock.clone();
+        let advancer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            advancing_clock.advance(Duration::from_secs(3600));
+        });
+        assert!(!clock.wait(&Event::new(), Duration::from_secs(3600)));
+        advancer.join().unwrap();
+    }
+
+    #[test]
+    fn virtual_wait_yields_triggered_event()
+    {
+        let clock = VirtualClock::new();
+        let evt = Event::new();
+        evt.trigger();
+        assert!(clock.wait(&evt, Duration::from_secs(1)));
+    }
+}
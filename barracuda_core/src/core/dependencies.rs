@@ -0,0 +1,187 @@
+use serde::Serialize;
+use std::fmt;
+
+/// # Module dependencies
+/// Declares what a module provides to other modules and
+/// what it depends on. Both are plain names, e.g. "CfgHolder"
+/// or "Inputs", that are agreed upon by the modules using them.
+/// A module that depends on a name will only be started once
+/// *all* modules providing that name have been started.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Dependencies
+{
+    pub provides: Vec<String>,
+    pub depends: Vec<String>
+}
+
+impl Dependencies
+{
+    pub fn none() -> Self
+    {
+        Dependencies::default()
+    }
+
+    pub fn new(provides: &[&str], depends: &[&str]) -> Self
+    {
+        Dependencies
+        {
+            provides: provides.iter().map(|x| x.to_string()).collect(),
+            depends: depends.iter().map(|x| x.to_string()).collect()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DependencyError
+{
+    MissingProvider(u32, String),       // id of the module, name nobody provides
+    Cycle(Vec<u32>)                     // ids of the modules depending on each other
+}
+
+impl fmt::Display for DependencyError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            DependencyError::MissingProvider(id, name) => write!(f, "Module id {:#010X} depends on {}, which no module provides", id, name),
+            DependencyError::Cycle(ids) => {
+                let ids: Vec<String> = ids.iter().map(|x| format!("{:#010X}", x)).collect();
+                write!(f, "Modules depend on each other: {}", ids.join(" -> "))
+            }
+        }
+    }
+}
+
+/// Computes the order the given modules have to be started in,
+/// i.e. each module is preceded by all providers of the names
+/// it depends on. Modules that do not depend on each other keep
+/// the order they were passed in. Yields all missing providers
+/// or the first cycle found if there is no valid order.
+pub fn start_order(modules: &[(u32, Dependencies)]) -> Result<Vec<u32>, Vec<DependencyError>>
+{
+    // prerequisites[i] contains the indices of all modules that must be started before module i.
+    let mut prerequisites: Vec<Vec<usize>> = Vec::new();
+    let mut errors = Vec::new();
+    for (id, deps) in modules.iter()
+    {
+        let mut before = Vec::new();
+        for name in deps.depends.iter()
+        {
+            let providers: Vec<usize> = modules.iter()
+                                               .enumerate()
+                                               .filter(|(_, (other, other_deps))| other != id && other_deps.provides.contains(name))
+                                               .map(|(index, _)| index)
+                                               .collect();
+            if providers.is_empty()
+            {
+                errors.push(DependencyError::MissingProvider(*id, name.clone()));
+            }
+            before.extend(providers);
+        }
+        prerequisites.push(before);
+    }
+
+    if !errors.is_empty()
+    {
+        return Err(errors);
+    }
+
+    let mut started = vec![false; modules.len()];
+    let mut order = Vec::new();
+    while order.len() < modules.len()
+    {
+        let next = (0..modules.len()).find(|&i| !started[i] && prerequisites[i].iter().all(|&p| started[p]));
+        match next
+        {
+            Some(index) => {
+                started[index] = true;
+                order.push(modules[index].0);
+            },
+            None => {
+                let first_blocked = (0..modules.len()).find(|&i| !started[i]).unwrap();
+                let cycle = find_cycle(first_blocked, &prerequisites, &started);
+                return Err(vec![DependencyError::Cycle(cycle.iter().map(|&i| modules[i].0).collect())]);
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// Follows unstarted prerequisites from the given module, which
+/// must end up in a cycle, as no blocked module can be started.
+fn find_cycle(start: usize, prerequisites: &Vec<Vec<usize>>, started: &Vec<bool>) -> Vec<usize>
+{
+    let mut path = vec![start];
+    loop
+    {
+        let current = *path.last().unwrap();
+        let next = *prerequisites[current].iter().find(|&&p| !started[p]).unwrap();
+        if let Some(pos) = path.iter().position(|&x| x == next)
+        {
+            return path.split_off(pos);
+        }
+        path.push(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn independent_modules_keep_their_order()
+    {
+        let modules = vec![(1, Dependencies::none()), (2, Dependencies::none()), (3, Dependencies::none())];
+        assert_eq!(Ok(vec![1, 2, 3]), start_order(&modules));
+    }
+
+    #[test]
+    fn providers_start_before_dependents()
+    {
+        let modules = vec![(1, Dependencies::new(&[], &["CfgHolder"])),
+                           (2, Dependencies::new(&[], &["Inputs"])),
+                           (3, Dependencies::new(&["CfgHolder"], &[])),
+                           (4, Dependencies::new(&["Inputs"], &[]))];
+        assert_eq!(Ok(vec![3, 1, 4, 2]), start_order(&modules));
+    }
+
+    #[test]
+    fn all_providers_start_before_dependent()
+    {
+        let modules = vec![(1, Dependencies::new(&[], &["AccessPoints"])),
+                           (2, Dependencies::new(&["AccessPoints"], &[])),
+                           (3, Dependencies::new(&["AccessPoints"], &["Inputs"])),
+                           (4, Dependencies::new(&["Inputs"], &[]))];
+        assert_eq!(Ok(vec![2, 4, 3, 1]), start_order(&modules));
+    }
+
+    #[test]
+    fn module_providing_what_it_depends_on_is_no_cycle()
+    {
+        let modules = vec![(1, Dependencies::new(&["Inputs"], &["Inputs"])),
+                           (2, Dependencies::new(&["Inputs"], &[]))];
+        assert_eq!(Ok(vec![2, 1]), start_order(&modules));
+    }
+
+    #[test]
+    fn missing_providers_are_reported()
+    {
+        let modules = vec![(1, Dependencies::new(&[], &["CfgHolder", "Inputs"])),
+                           (2, Dependencies::new(&[], &["Outputs"]))];
+        let errors = start_order(&modules).unwrap_err();
+        assert_eq!(vec![DependencyError::MissingProvider(1, "CfgHolder".to_string()),
+                        DependencyError::MissingProvider(1, "Inputs".to_string()),
+                        DependencyError::MissingProvider(2, "Outputs".to_string())], errors);
+    }
+
+    #[test]
+    fn cycle_is_detected()
+    {
+        let modules = vec![(1, Dependencies::none()),
+                           (2, Dependencies::new(&["A"], &["C"])),
+                           (3, Dependencies::new(&["B"], &["A"])),
+                           (4, Dependencies::new(&["C"], &["B"]))];
+        let errors = start_order(&modules).unwrap_err();
+        assert_eq!(vec![DependencyError::Cycle(vec![2, 4, 3])], errors);
+    }
+}
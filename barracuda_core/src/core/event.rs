@@ -99,11 +99,16 @@ impl<T: Copy+Sync> DataEvent<T>
 
     pub fn wait(&self) -> T
     {
-        self.evt.wait();
-        return self.data.lock()
-                        .unwrap()
-                        .take()
-                        .unwrap();
+        loop
+        {
+            self.evt.wait();
+            // The event might still be set by a trigger whose
+            // data was already taken by the previous wait.
+            if let Some(data) = self.data.lock().unwrap().take()
+            {
+                return data;
+            }
+        }
     }
 
     #[allow(dead_code)]
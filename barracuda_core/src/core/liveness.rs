@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+/// # The liveness monitor
+/// Keeps track of which modules answered the heartbeats
+/// sent by the supervisor. Each time the supervisor sends
+/// a new heartbeat it will evaluate the monitor, which
+/// yields all modules that have missed too many consecutive
+/// heartbeats.
+pub struct LivenessMonitor
+{
+    missed_heartbeats: HashMap<u32, u32>,
+    max_missed: u32
+}
+
+impl LivenessMonitor
+{
+    pub fn new(max_missed: u32) -> Self
+    {
+        LivenessMonitor
+        {
+            missed_heartbeats: HashMap::new(),
+            max_missed
+        }
+    }
+
+    pub fn add_module(&mut self, module_id: u32)
+    {
+        self.missed_heartbeats.insert(module_id, 0);
+    }
+
+    pub fn remove_module(&mut self, module_id: u32)
+    {
+        self.missed_heartbeats.remove(&module_id);
+    }
+
+    pub fn is_supervised(&self, module_id: u32) -> bool
+    {
+        self.missed_heartbeats.contains_key(&module_id)
+    }
+
+    pub fn on_heartbeat_response(&mut self, module_id: u32)
+    {
+        // Note: Answers of modules we don't know are ignored,
+        // these modules never checked in during boot.
+        if let Some(missed) = self.missed_heartbeats.get_mut(&module_id)
+        {
+            *missed = 0;
+        }
+    }
+
+    /// Called right before a new heartbeat is sent. Counts a
+    /// missed heartbeat for every module that did not answer
+    /// since the last call and returns all modules that have
+    /// exceeded the allowed number of missed heartbeats.
+    /// Note that the counter is not reset for modules returned
+    /// here, it is up to the caller to decide what happens to
+    /// the offending modules.
+    pub fn evaluate(&mut self) -> Vec<u32>
+    {
+        let mut unresponsive = Vec::new();
+        for (module_id, missed) in self.missed_heartbeats.iter_mut()
+        {
+            *missed += 1;
+            if *missed > self.max_missed
+            {
+                unresponsive.push(*module_id);
+            }
+        }
+        unresponsive.sort_unstable();
+        unresponsive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LivenessMonitor;
+
+    #[test]
+    fn module_answering_is_never_reported()
+    {
+        let mut m = LivenessMonitor::new(1);
+        m.add_module(0x04000000);
+        for _ in 0..10
+        {
+            m.on_heartbeat_response(0x04000000);
+            assert!(m.evaluate().is_empty());
+        }
+    }
+
+    #[test]
+    fn silent_module_is_reported_after_max_missed()
+    {
+        let mut m = LivenessMonitor::new(2);
+        m.add_module(0x04000000);
+        m.evaluate();
+        assert!(m.evaluate().is_empty());
+        assert_eq!(vec![0x04000000], m.evaluate());
+    }
+
+    #[test]
+    fn answer_resets_missed_counter()
+    {
+        let mut m = LivenessMonitor::new(1);
+        m.add_module(0x04000000);
+        m.evaluate();
+        m.on_heartbeat_response(0x04000000);
+        m.evaluate();
+        assert!(m.evaluate().len() == 1);
+    }
+
+    #[test]
+    fn removed_module_is_no_longer_reported()
+    {
+        let mut m = LivenessMonitor::new(0);
+        m.add_module(0x04000000);
+        m.add_module(0x07000000);
+        m.remove_module(0x04000000);
+        assert_eq!(vec![0x07000000], m.evaluate());
+        assert!(!m.is_supervised(0x04000000));
+    }
+
+    #[test]
+    fn unknown_module_is_ignored()
+    {
+        let mut m = LivenessMonitor::new(0);
+        m.on_heartbeat_response(0x04000000);
+        assert!(m.evaluate().is_empty());
+    }
+}
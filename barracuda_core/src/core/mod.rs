@@ -6,7 +6,9 @@ for the rest of the appliaction, most notably
 * The implementation of the channel manager
 * queues
 * the supervisor
+* waiting on multiple channels (select)
 * a timer service (I cannot believe I have to write my own timer!)
+* the clock all time dependent behavior is based on
 
 */
 
@@ -19,26 +21,51 @@ pub mod supervisor;
 pub mod bootstage_helper;
 pub mod shareable;
 pub mod timer;
-
-#[derive(Clone, Copy, PartialEq)]
+pub mod clock;
+pub mod liveness;
+pub mod registry;
+pub mod dependencies;
+pub mod rpc;
+pub mod select;
+pub mod module_thread;
+pub mod sud;
+
+use serde::{Serialize, Deserialize};
+use dependencies::Dependencies;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum BootStage
 {
     Sync,
     LowLevelInit,
     HighLevelInit,
     Application,
-    _Shutdown
+    Shutdown
 }
 
 #[derive(Clone, PartialEq)]
 pub enum SystemMessage
 {
-    Shutdown,
+    Shutdown,                               // Asks the supervisor to shut down the whole system.
+    Register(u32, String, Dependencies),    // Module ID, name and dependencies of the module. Sent right before checking in for Sync
     StageComplete(BootStage, u32),
     RunStage(BootStage),
-    _Reboot(u32),               // Contains the module ID of the module that is supposed to reboot.
-    _Heartbeat,
-    _HeartbeatResponse(u32)
+    RunStageFor(BootStage, u32),            // Contains the module ID of the only module that is supposed to run the stage.
+    Reboot(u32),                            // Contains the module ID of the module that is supposed to reboot.
+    RebootRefused(u32),                     // Sent by modules that cannot be rebooted instead of acknowledging the reboot.
+    ShutdownModule(u32),                    // Contains the module ID of the module that is supposed to run the shutdown stage.
+    Heartbeat,
+    HeartbeatResponse(u32),                 // Contains the module ID of the module answering the heartbeat.
+    ModulePanicked(u32, String)             // Module ID and panic message of a module whose thread panicked, see module_thread.
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ModuleFault
+{
+    HeartbeatMissed(u32),           // Contains the module ID of the module that stopped answering heartbeats.
+    BootFailed(u32, BootStage),     // Module ID and stage of an optional module that failed to boot, the system runs degraded.
+    Panicked(u32, String),          // Module ID and panic message of a module whose thread panicked.
+    BootAborted(String)             // Reason why a critical module could not be booted, the system does not start.
 }
 
 
@@ -51,12 +78,11 @@ pub enum SystemMessage
 // the index of the component within the module:
 // AAAA AAAA BBBB BBBB CCCC CCCC CCCC CCCC
 // C: Used to uniquely identify a given component of a module
+// See sud::Sud for a typed SUD.
 #[allow(dead_code)]
 pub fn make_sud(module_id: u8, module_instance: u8, object_index: u16) -> u32
 {
-    let m = (module_id as u32) << 24;
-    let i = (module_instance as u32) << 16;
-    return m | i | (object_index as u32);
+    return sud::Sud::new(module_id, module_instance, object_index).into();
 }
 
 pub fn _modid_from_sud(sud: u32) -> u32
@@ -77,18 +103,34 @@ all called functions to:
 i.e. it shall check in when a RunStage command is
 sent.
 
-* Panics if any of the stages fail to check in within
-2.5 seconds after a run-stage command, except for
-the "Application" stage which is not expected to
-be answered.
+* Yields a BootError if a critical module fails to check in
+within the boot timeout (2.5 seconds by default) after a
+run-stage command, except for the "Application" stage which
+is not expected to be answered. No further modules are
+started in that case. Optional modules that fail to check
+in are dropped and the system runs degraded. Use
+launch!(settings: boot_settings, heartbeat_settings; ...)
+to configure timeouts and optional modules.
+
+The modules are started in the order they are passed in,
+unless their dependencies demand otherwise, and stopped
+in reverse start order upon shutdown. Launch returns once
+all modules were shut down.
 **/
 #[macro_export]
 macro_rules! launch {
+    (settings: $boot: expr, $heartbeat: expr; $($threadlist: expr),+) => (
+        {
+            let mut supervisor = crate::core::supervisor::Supervisor::new();
+            supervisor.set_boot_settings($boot);
+            supervisor.set_heartbeat_settings($heartbeat);
+            launch_impl!(supervisor, $($threadlist),+).and_then(|_| supervisor.run())
+        }
+    );
     ($($threadlist: expr),+) => (
         {
             let mut supervisor = crate::core::supervisor::Supervisor::new();
-            launch_impl!(supervisor, $($threadlist),+);            
-            supervisor.run();
+            launch_impl!(supervisor, $($threadlist),+).and_then(|_| supervisor.run())
         }
     )
 }
@@ -97,76 +139,12 @@ macro_rules! launch {
 macro_rules! launch_impl {
     ($supervisor: expr, $head: expr, $($threadlist: expr),+) => (
         {
-            $supervisor.start_thread($head);
-            launch_impl!($supervisor, $($threadlist),+)                    
+            $supervisor.start_thread($head).and_then(|_| launch_impl!($supervisor, $($threadlist),+))
         }
     );
     ($supervisor: expr, $head: expr) => (
         {
-            $supervisor.start_thread($head);         
-        }
-    )
-}
-
-#[macro_export]
-macro_rules! wait_for {
-    ($evt: ident, $id: expr, $head: expr) => (
-        {
-            $head.set_data_trigger($evt.clone(), $id);
-            ($evt.wait())
-        }
-    );
-    ($evt: ident, $id: expr, $head: expr, $($tail: expr),+) =>(
-        {
-
-            $head.set_data_trigger($evt.clone(), $id);
-            (wait_for!($evt,$id+1, $($tail),+))
+            $supervisor.start_thread($head)
         }
     )
 }
-
-#[macro_export]
-macro_rules! select_chan {
-    ($($channels: expr),+) => (
-        {
-            let evt = Arc::new(DataEvent::<u32>::new("<unnamed>".to_string()));
-            (wait_for!(evt, 0, $($channels),+))
-        }
-    );
-}
-
-#[allow(unused_macros)]
-#[macro_export]
-macro_rules! wait_for_with_timeout {
-    ($evt: expr, $timeout: expr, $id: expr, $head: expr) => (
-        {
-            if $head.has_data() { 
-                Some($id)
-            }
-            else
-            {
-                $head.set_data_trigger($evt, $id);
-                ($evt.wait_with_timeout($timeout))
-            }
-        }
-    );
-    ($evt: expr, $timeout: expr, $id: expr, $head: expr, $($tail: expr),+) =>(
-        {
-            if $head.has_data()
-            {
-                Some($id)
-            }
-            else
-            {
-                $head.set_data_trigger($evt, $id);
-                (wait_for_with_timeout!($evt, $timeout, $id + 1, $($tail),+))
-            }
-        }
-    )
-}
-
-#[allow(unused_macros)]
-#[macro_export]
-macro_rules! select_chan_with_timeout {
-    ($timeout: expr, $($channels: expr),+) => (wait_for_with_timeout!(Arc::new(DataEvent::<u32>::new()), $timeout, 0, $($channels),+));
-}
\ No newline at end of file
@@ -0,0 +1,70 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, JoinHandle};
+
+use super::SystemMessage;
+use super::channel_manager::ChannelManager;
+
+/// # Runs a module thread
+/// Spawns a thread named after the module, which runs body.
+/// If body panics, the panic is reported to the supervisor as
+/// SystemMessage::ModulePanicked with the module ID and the panic
+/// message, the supervisor then applies its fault policy.
+pub fn spawn_module<F>(module_id: u32, name: &str, chm: &mut ChannelManager, body: F) -> JoinHandle<()>
+    where F: FnOnce() + Send + 'static
+{
+    let sys_tx = chm.get_sender::<SystemMessage>();
+    thread::Builder::new().name(name.to_string())
+                          .spawn(move || {
+                              if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(body))
+                              {
+                                  sys_tx.send(SystemMessage::ModulePanicked(module_id, panic_message(payload.as_ref())));
+                              }
+                          })
+                          .expect("Failed to spawn module thread")
+}
+
+/// Yields the message passed to panic!, which is either
+/// a &str or a String, depending on how panic! was called.
+fn panic_message(payload: &(dyn Any + Send)) -> String
+{
+    if let Some(msg) = payload.downcast_ref::<&str>()
+    {
+        return msg.to_string();
+    }
+    if let Some(msg) = payload.downcast_ref::<String>()
+    {
+        return msg.clone();
+    }
+    "<no message>".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_is_reported_with_message()
+    {
+        let mut chm = ChannelManager::new();
+        let sys_rx = chm.get_receiver::<SystemMessage>();
+        let handle = spawn_module(0x03000000, "ACM/Whitelist", &mut chm, || {
+            let token: Vec<u8> = Vec::new();
+            panic!("Malformed token {:?}", token);
+        });
+
+        assert!(handle.join().is_ok());
+        assert!(sys_rx.receive() == SystemMessage::ModulePanicked(0x03000000, "Malformed token []".to_string()));
+    }
+
+    #[test]
+    fn regular_exit_is_not_reported()
+    {
+        let mut chm = ChannelManager::new();
+        let sys_rx = chm.get_receiver::<SystemMessage>();
+        let handle = spawn_module(0x03000000, "ACM/Whitelist", &mut chm, || {});
+
+        assert!(handle.join().is_ok());
+        assert!(sys_rx.receive_with_timeout(0).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::core::BootStage;
+use crate::core::dependencies::Dependencies;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModuleInfo
+{
+    pub id: u32,                    // SUD of the module, i.e. object index 0
+    pub name: String,
+    pub stage: Option<BootStage>,   // Last stage the module completed, None if it only registered
+    pub failed_stage: Option<BootStage>,    // Stage the module failed to complete, None if it did not fail
+    pub dependencies: Dependencies
+}
+
+impl ModuleInfo
+{
+    pub fn module_type(&self) -> u32
+    {
+        (self.id & 0xFF000000) >> 24
+    }
+
+    pub fn instance(&self) -> u32
+    {
+        (self.id & 0x00FF0000) >> 16
+    }
+}
+
+impl fmt::Display for ModuleInfo
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}.{})", self.name, self.module_type(), self.instance())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegistryError
+{
+    DuplicateId(u32, String),           // id, name of the module that already uses the id
+    DuplicateCheckIn(u32, BootStage),   // id, stage the module checked in for twice
+    UnknownModule(u32)
+}
+
+impl fmt::Display for RegistryError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            RegistryError::DuplicateId(id, name) => write!(f, "Module id {:#010X} is already used by {}", id, name),
+            RegistryError::DuplicateCheckIn(id, stage) => write!(f, "Module id {:#010X} checked in twice for stage {:?}", id, stage),
+            RegistryError::UnknownModule(id) => write!(f, "Module id {:#010X} is not registered", id)
+        }
+    }
+}
+
+/// # The module registry
+/// Contains all modules known to the supervisor along
+/// with their names and the bootstage they are in.
+/// The supervisor publishes the registry as a
+/// Shareable<ModuleRegistry> once all modules have
+/// checked in, so it can be queried at runtime.
+pub struct ModuleRegistry
+{
+    modules: Vec<ModuleInfo>
+}
+
+impl Default for ModuleRegistry
+{
+    fn default() -> Self {
+        ModuleRegistry::new()
+    }
+}
+
+impl ModuleRegistry
+{
+    pub fn new() -> Self
+    {
+        ModuleRegistry
+        {
+            modules: Vec::new()
+        }
+    }
+
+    pub fn register(&mut self, id: u32, name: String, dependencies: Dependencies) -> Result<(), RegistryError>
+    {
+        if let Some(existing) = self.get(id)
+        {
+            return Err(RegistryError::DuplicateId(id, existing.name.clone()));
+        }
+
+        self.modules.push(ModuleInfo { id, name, stage: None, failed_stage: None, dependencies });
+        Ok(())
+    }
+
+    /// Removes a module from the registry, e.g. because
+    /// it is about to be rebooted and will register again.
+    pub fn unregister(&mut self, id: u32) -> Result<ModuleInfo, RegistryError>
+    {
+        let pos = self.modules.iter()
+                              .position(|x| x.id == id)
+                              .ok_or(RegistryError::UnknownModule(id))?;
+        Ok(self.modules.remove(pos))
+    }
+
+    pub fn check_in(&mut self, id: u32, stage: BootStage) -> Result<(), RegistryError>
+    {
+        let module = self.modules.iter_mut()
+                                 .find(|x| x.id == id)
+                                 .ok_or(RegistryError::UnknownModule(id))?;
+        if module.stage == Some(stage)
+        {
+            return Err(RegistryError::DuplicateCheckIn(id, stage));
+        }
+
+        module.stage = Some(stage);
+        Ok(())
+    }
+
+    /// Marks a module as failed, i.e. the system runs without it.
+    /// The mark is cleared when the module registers again.
+    pub fn mark_failed(&mut self, id: u32, stage: BootStage) -> Result<(), RegistryError>
+    {
+        let module = self.modules.iter_mut()
+                                 .find(|x| x.id == id)
+                                 .ok_or(RegistryError::UnknownModule(id))?;
+        module.failed_stage = Some(stage);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u32) -> Option<&ModuleInfo>
+    {
+        self.modules.iter().find(|x| x.id == id)
+    }
+
+    /// Yields a printable description of the module, which
+    /// falls back to the plain id for unknown modules.
+    pub fn describe(&self, id: u32) -> String
+    {
+        match self.get(id)
+        {
+            Some(module) => module.to_string(),
+            None => format!("<unknown> ({}.{})", (id & 0xFF000000) >> 24, (id & 0x00FF0000) >> 16)
+        }
+    }
+
+    pub fn modules(&self) -> Vec<ModuleInfo>
+    {
+        self.modules.clone()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.modules.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_register_module()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x03000000, "ACM/Whitelist".to_string(), Dependencies::none()).unwrap();
+        let m = r.get(0x03000000).unwrap();
+        assert_eq!("ACM/Whitelist", m.name);
+        assert_eq!(None, m.stage);
+    }
+
+    #[test]
+    fn duplicate_id_is_rejected()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x0C000000, "ProfileControl".to_string(), Dependencies::none()).unwrap();
+        let err = r.register(0x0C000000, "SIG/SigCtrl".to_string(), Dependencies::none()).unwrap_err();
+        assert_eq!(RegistryError::DuplicateId(0x0C000000, "ProfileControl".to_string()), err);
+        assert_eq!(1, r.len());
+    }
+
+    #[test]
+    fn check_in_updates_stage()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x03000000, "ACM/Whitelist".to_string(), Dependencies::none()).unwrap();
+        r.check_in(0x03000000, BootStage::Sync).unwrap();
+        r.check_in(0x03000000, BootStage::LowLevelInit).unwrap();
+        assert_eq!(Some(BootStage::LowLevelInit), r.get(0x03000000).unwrap().stage);
+    }
+
+    #[test]
+    fn checking_in_twice_is_rejected()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x03000000, "ACM/Whitelist".to_string(), Dependencies::none()).unwrap();
+        r.check_in(0x03000000, BootStage::Sync).unwrap();
+        assert!(r.check_in(0x03000000, BootStage::Sync).is_err());
+    }
+
+    #[test]
+    fn check_in_of_unknown_module_is_rejected()
+    {
+        let mut r = ModuleRegistry::new();
+        assert_eq!(Err(RegistryError::UnknownModule(0x03000000)), r.check_in(0x03000000, BootStage::Sync));
+    }
+
+    #[test]
+    fn unregistered_module_can_register_again()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x03000000, "ACM/Whitelist".to_string(), Dependencies::none()).unwrap();
+        r.check_in(0x03000000, BootStage::Sync).unwrap();
+        let m = r.unregister(0x03000000).unwrap();
+        assert_eq!(Some(BootStage::Sync), m.stage);
+        assert!(r.unregister(0x03000000).is_err());
+        r.register(0x03000000, "ACM/Whitelist".to_string(), Dependencies::none()).unwrap();
+        r.check_in(0x03000000, BootStage::Sync).unwrap();
+    }
+
+    #[test]
+    fn failed_module_is_cleared_by_registering_again()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x0B000000, "ARM/MFRC522".to_string(), Dependencies::none()).unwrap();
+        r.check_in(0x0B000000, BootStage::Sync).unwrap();
+        r.mark_failed(0x0B000000, BootStage::LowLevelInit).unwrap();
+        let m = r.get(0x0B000000).unwrap();
+        assert_eq!(Some(BootStage::Sync), m.stage);
+        assert_eq!(Some(BootStage::LowLevelInit), m.failed_stage);
+
+        r.unregister(0x0B000000).unwrap();
+        r.register(0x0B000000, "ARM/MFRC522".to_string(), Dependencies::none()).unwrap();
+        assert_eq!(None, r.get(0x0B000000).unwrap().failed_stage);
+        assert!(r.mark_failed(0x07000000, BootStage::Sync).is_err());
+    }
+
+    #[test]
+    fn describe_decodes_type_and_instance()
+    {
+        let mut r = ModuleRegistry::new();
+        r.register(0x0D020000, "DCM/ADCM".to_string(), Dependencies::none()).unwrap();
+        assert_eq!("DCM/ADCM (13.2)", r.describe(0x0D020000));
+        assert_eq!("<unknown> (7.1)", r.describe(0x07010000));
+    }
+}
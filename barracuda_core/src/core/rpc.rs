@@ -0,0 +1,263 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::fmt;
+use super::broadcast_channel::*;
+use super::channel_manager::ChannelManager;
+use super::event::DataEvent;
+use super::select::Selectable;
+
+/// # Request/response on top of the channel manager
+/// Requests of type Req are broadcast on the channel for
+/// Request<Req, Resp>, i.e. there is one channel per pair
+/// of request and response type. Each request carries a
+/// correlation id and the reply queue of its requester, so
+/// the response is routed only to the module that asked.
+/// If several modules respond to the same request, the
+/// first response wins.
+pub struct Request<Req: Clone, Resp: Clone>
+{
+    id: u64,
+    pub payload: Req,
+    reply_to: Arc<GenericSender<Reply<Resp>>>
+}
+
+impl <Req: Clone, Resp: Clone> Clone for Request<Req, Resp>
+{
+    fn clone(&self) -> Self {
+        Request
+        {
+            id: self.id,
+            payload: self.payload.clone(),
+            reply_to: self.reply_to.clone()
+        }
+    }
+}
+
+impl <Req: Clone, Resp: Clone> Request<Req, Resp>
+{
+    /// Sends the response to the requester. Responses to
+    /// requests that already timed out are discarded.
+    pub fn reply(&self, response: Resp)
+    {
+        self.reply_to.send(Reply { id: self.id, payload: response });
+    }
+}
+
+#[derive(Clone)]
+pub struct Reply<Resp: Clone>
+{
+    id: u64,
+    payload: Resp
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RpcError
+{
+    NoResponder,        // Nobody listens for requests of this type
+    Timeout(u64)        // No response within the given time in ms
+}
+
+impl fmt::Display for RpcError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            RpcError::NoResponder => write!(f, "No module responds to requests of this type"),
+            RpcError::Timeout(ms) => write!(f, "No response within {} ms", ms)
+        }
+    }
+}
+
+/// Sends requests and waits for the matching response. A requester
+/// can be shared between threads, requests are processed one
+/// after the other.
+pub struct Requester<Req: Clone, Resp: Clone>
+{
+    request_tx: GenericSender<Request<Req, Resp>>,
+    reply_tx: Arc<GenericSender<Reply<Resp>>>,
+    reply_rx: Mutex<GenericReceiver<Reply<Resp>>>,
+    next_id: AtomicU64
+}
+
+impl <Req: 'static + Clone + Send + Sync, Resp: 'static + Clone + Send + Sync> Requester<Req, Resp>
+{
+    pub fn new(chm: &mut ChannelManager) -> Self
+    {
+        // The reply channel is not part of the channel manager,
+        // so nobody but this requester can receive the responses.
+        let reply_chan = Arc::new(ChannelImpl::<Reply<Resp>>::new());
+        Requester
+        {
+            request_tx: chm.get_sender(),
+            reply_tx: Arc::new(make_sender(&reply_chan)),
+            reply_rx: Mutex::new(make_receiver(&reply_chan)),
+            next_id: AtomicU64::new(0)
+        }
+    }
+
+    /// Sends the request and waits for at most timeout ms
+    /// for the response.
+    pub fn request(&self, payload: Req, timeout: u64) -> Result<Resp, RpcError>
+    {
+        if self.request_tx.num_receivers() == 0
+        {
+            return Err(RpcError::NoResponder);
+        }
+
+        let reply_rx = self.reply_rx.lock().unwrap();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.request_tx.send(Request { id, payload, reply_to: self.reply_tx.clone() });
+
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+        loop
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0
+            {
+                return Err(RpcError::Timeout(timeout));
+            }
+
+            // Late responses to earlier requests, as well as additional
+            // responses to this one, are skipped.
+            match reply_rx.receive_with_timeout(remaining.as_millis() as u64)
+            {
+                Some(reply) if reply.id == id => return Ok(reply.payload),
+                _ => continue
+            }
+        }
+    }
+}
+
+/// Receives requests of type Req, answer them using Request::reply.
+/// Can be passed to Select::recv.
+pub struct Responder<Req: Clone, Resp: Clone>
+{
+    request_rx: GenericReceiver<Request<Req, Resp>>
+}
+
+impl <Req: 'static + Clone + Send + Sync, Resp: 'static + Clone + Send + Sync> Responder<Req, Resp>
+{
+    pub fn new(chm: &mut ChannelManager) -> Self
+    {
+        Responder
+        {
+            request_rx: chm.get_receiver()
+        }
+    }
+
+    pub fn receive(&self) -> Request<Req, Resp>
+    {
+        self.request_rx.receive()
+    }
+
+    pub fn receive_with_timeout(&self, milliseconds: u64) -> Option<Request<Req, Resp>>
+    {
+        self.request_rx.receive_with_timeout(milliseconds)
+    }
+
+    pub fn has_data(&self) -> bool
+    {
+        self.request_rx.has_data()
+    }
+
+    pub fn set_data_trigger(&self, d: Arc<DataEvent<u32>>, trigger_data: u32)
+    {
+        self.request_rx.set_data_trigger(d, trigger_data)
+    }
+}
+
+impl <Req: 'static + Clone + Send + Sync, Resp: 'static + Clone + Send + Sync> Selectable for Responder<Req, Resp>
+{
+    type Item = Request<Req, Resp>;
+
+    fn has_data(&self) -> bool
+    {
+        Responder::has_data(self)
+    }
+
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32)
+    {
+        Responder::set_data_trigger(self, evt, trigger_data)
+    }
+
+    fn try_receive(&self) -> Option<Request<Req, Resp>>
+    {
+        self.request_rx.try_receive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct DoorBlockedQuery(u32);
+
+    fn spawn_responder(chm: &mut ChannelManager, num_requests: usize)
+    {
+        let responder = Responder::<DoorBlockedQuery, bool>::new(chm);
+        thread::spawn(move || {
+            for _ in 0..num_requests
+            {
+                let req = responder.receive();
+                req.reply(req.payload.0 == 1);
+            }
+        });
+    }
+
+    #[test]
+    fn request_yields_response()
+    {
+        let mut chm = ChannelManager::new();
+        spawn_responder(&mut chm, 2);
+        let requester = Requester::<DoorBlockedQuery, bool>::new(&mut chm);
+        assert_eq!(Ok(true), requester.request(DoorBlockedQuery(1), 500));
+        assert_eq!(Ok(false), requester.request(DoorBlockedQuery(2), 500));
+    }
+
+    #[test]
+    fn request_without_responder_fails()
+    {
+        let mut chm = ChannelManager::new();
+        let requester = Requester::<DoorBlockedQuery, bool>::new(&mut chm);
+        assert_eq!(Err(RpcError::NoResponder), requester.request(DoorBlockedQuery(1), 500));
+    }
+
+    #[test]
+    fn request_times_out_if_nobody_replies()
+    {
+        let mut chm = ChannelManager::new();
+        let _responder = Responder::<DoorBlockedQuery, bool>::new(&mut chm);
+        let requester = Requester::<DoorBlockedQuery, bool>::new(&mut chm);
+        assert_eq!(Err(RpcError::Timeout(50)), requester.request(DoorBlockedQuery(1), 50));
+    }
+
+    #[test]
+    fn reply_is_routed_to_requester_only()
+    {
+        let mut chm = ChannelManager::new();
+        spawn_responder(&mut chm, 1);
+        let requester = Requester::<DoorBlockedQuery, bool>::new(&mut chm);
+        let other = Requester::<DoorBlockedQuery, bool>::new(&mut chm);
+        assert_eq!(Ok(true), requester.request(DoorBlockedQuery(1), 500));
+        assert_eq!(None, other.reply_rx.lock().unwrap().receive_with_timeout(10).map(|x| x.payload));
+    }
+
+    #[test]
+    fn late_reply_is_not_mistaken_for_next_response()
+    {
+        let mut chm = ChannelManager::new();
+        let responder = Responder::<DoorBlockedQuery, bool>::new(&mut chm);
+        let requester = Requester::<DoorBlockedQuery, bool>::new(&mut chm);
+        assert_eq!(Err(RpcError::Timeout(20)), requester.request(DoorBlockedQuery(1), 20));
+        responder.receive().reply(true);
+
+        thread::spawn(move || {
+            let req = responder.receive();
+            req.reply(req.payload.0 == 1);
+        });
+        assert_eq!(Ok(false), requester.request(DoorBlockedQuery(2), 500));
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::{broadcast_channel::GenericReceiver, event::DataEvent};
+
+/// Anything a module can wait on using a Select,
+/// i.e. receivers and responders.
+pub trait Selectable
+{
+    type Item;
+    fn has_data(&self) -> bool;
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32);
+    /// Yields the next message without waiting.
+    fn try_receive(&self) -> Option<Self::Item>;
+}
+
+impl <T: Clone> Selectable for GenericReceiver<T>
+{
+    type Item = T;
+
+    fn has_data(&self) -> bool
+    {
+        GenericReceiver::has_data(self)
+    }
+
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32)
+    {
+        GenericReceiver::set_data_trigger(self, evt, trigger_data)
+    }
+
+    fn try_receive(&self) -> Option<T>
+    {
+        if !GenericReceiver::has_data(self)
+        {
+            return None;
+        }
+        self.receive_with_timeout(0)
+    }
+}
+
+/// # Waiting on multiple channels
+/// A module keeps a Selector for its lifetime and uses it
+/// to wait for the next message on any of its channels:
+/// ```ignore
+/// let result = self.selector.select()
+///                  .recv(&self.input_rx, |evt| Work::Input(evt))
+///                  .recv(&self.system_events_rx, |msg| Work::System(msg))
+///                  .timeout(5000, || Work::Idle)
+///                  .wait();
+/// ```
+/// The closure of the branch whose channel had data is called with
+/// the message and its result is yielded by wait(). The receivers are
+/// only borrowed until wait() returns, so the result can be handled
+/// using the module itself.
+/// If multiple channels have data, the channels are served round
+/// robin, i.e. a busy channel cannot starve the others (e.g. the
+/// system channel).
+pub struct Selector
+{
+    event: Arc<DataEvent<u32>>,
+    next_branch: usize          // Branch that is checked first by the next wait
+}
+
+impl Selector
+{
+    pub fn new(name: &str) -> Self
+    {
+        Selector
+        {
+            event: Arc::new(DataEvent::new(name.to_string())),
+            next_branch: 0
+        }
+    }
+
+    pub fn select<'a, R>(&'a mut self) -> Select<'a, R>
+    {
+        Select
+        {
+            selector: self,
+            branches: Vec::new(),
+            timeout: None
+        }
+    }
+}
+
+trait Branch<R>
+{
+    fn has_data(&self) -> bool;
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32);
+    fn fire(&mut self) -> Option<R>;
+}
+
+/// M is the form the message is passed to the handler in,
+/// which is S::Item unless the branch was added by recv_shared.
+struct ReceiveBranch<'a, S: Selectable, M, F: FnOnce(M) -> R, R>
+{
+    source: &'a S,
+    receive: fn(&S) -> Option<M>,
+    handler: Option<F>
+}
+
+impl <'a, S: Selectable, M, F: FnOnce(M) -> R, R> Branch<R> for ReceiveBranch<'a, S, M, F, R>
+{
+    fn has_data(&self) -> bool
+    {
+        self.source.has_data()
+    }
+
+    fn set_data_trigger(&self, evt: Arc<DataEvent<u32>>, trigger_data: u32)
+    {
+        self.source.set_data_trigger(evt, trigger_data)
+    }
+
+    fn fire(&mut self) -> Option<R>
+    {
+        let msg = (self.receive)(self.source)?;
+        self.handler.take().map(|handler| handler(msg))
+    }
+}
+
+pub struct Select<'a, R>
+{
+    selector: &'a mut Selector,
+    branches: Vec<Box<dyn Branch<R> + 'a>>,
+    timeout: Option<(u64, Box<dyn FnOnce() -> R + 'a>)>
+}
+
+impl <'a, R: 'a> Select<'a, R>
+{
+    /// Adds a branch that calls the handler with the next message of the source.
+    pub fn recv<S: Selectable, F: FnOnce(S::Item) -> R + 'a>(mut self, source: &'a S, handler: F) -> Self
+    {
+        self.branches.push(Box::new(ReceiveBranch { source, receive: S::try_receive, handler: Some(handler) }));
+        self
+    }
+
+    /// Like recv, but passes the message as it is shared with the
+    /// other receivers, see GenericReceiver::receive_shared.
+    pub fn recv_shared<T: Clone + 'a, F: FnOnce(Arc<T>) -> R + 'a>(mut self, source: &'a GenericReceiver<T>, handler: F) -> Self
+    {
+        let receive: fn(&GenericReceiver<T>) -> Option<Arc<T>> = |rx| {
+            if !rx.has_data()
+            {
+                return None;
+            }
+            rx.receive_shared_with_timeout(0)
+        };
+        self.branches.push(Box::new(ReceiveBranch { source, receive, handler: Some(handler) }));
+        self
+    }
+
+    /// Calls the handler if none of the sources had data
+    /// within the given time (in ms!).
+    pub fn timeout<F: FnOnce() -> R + 'a>(mut self, millis: u64, handler: F) -> Self
+    {
+        self.timeout = Some((millis, Box::new(handler)));
+        self
+    }
+
+    /// Waits until one of the sources has data, without
+    /// a timeout this might wait forever.
+    pub fn wait(mut self) -> R
+    {
+        let deadline = self.timeout.as_ref().map(|(millis, _)| Instant::now() + Duration::from_millis(*millis));
+        loop
+        {
+            if let Some(result) = self.fire_next()
+            {
+                return result;
+            }
+
+            for (index, branch) in self.branches.iter().enumerate()
+            {
+                branch.set_data_trigger(self.selector.event.clone(), index as u32);
+            }
+
+            match deadline
+            {
+                None => { self.selector.event.wait(); },
+                Some(deadline) =>
+                {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.as_millis() == 0
+                    {
+                        let (_, handler) = self.timeout.take().unwrap();
+                        return handler();
+                    }
+                    self.selector.event.wait_with_timeout(remaining.as_millis() as u64);
+                }
+            }
+        }
+    }
+
+    fn fire_next(&mut self) -> Option<R>
+    {
+        let num_branches = self.branches.len();
+        for offset in 0..num_branches
+        {
+            let index = (self.selector.next_branch + offset) % num_branches;
+            if self.branches[index].has_data()
+            {
+                if let Some(result) = self.branches[index].fire()
+                {
+                    self.selector.next_branch = index + 1;
+                    return Some(result);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::channel_manager::ChannelManager;
+    use std::thread;
+
+    #[derive(Debug, PartialEq)]
+    enum Work
+    {
+        Number(u32),
+        Text(String),
+        Idle
+    }
+
+    #[test]
+    fn select_yields_result_of_branch_with_data()
+    {
+        let mut chm = ChannelManager::new();
+        let numbers = chm.get_receiver::<u32>();
+        let texts = chm.get_receiver::<String>();
+        let mut selector = Selector::new("Test");
+
+        chm.get_sender::<String>().send("door".to_string());
+        let result = selector.select()
+                             .recv(&numbers, Work::Number)
+                             .recv(&texts, Work::Text)
+                             .wait();
+        assert_eq!(Work::Text("door".to_string()), result);
+    }
+
+    #[test]
+    fn select_waits_for_data()
+    {
+        let mut chm = ChannelManager::new();
+        let numbers = chm.get_receiver::<u32>();
+        let tx = chm.get_sender::<u32>();
+        let mut selector = Selector::new("Test");
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            tx.send(4711);
+        });
+        assert_eq!(Work::Number(4711), selector.select().recv(&numbers, Work::Number).timeout(2000, || Work::Idle).wait());
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn select_calls_timeout_handler()
+    {
+        let mut chm = ChannelManager::new();
+        let numbers = chm.get_receiver::<u32>();
+        let mut selector = Selector::new("Test");
+        assert_eq!(Work::Idle, selector.select().recv(&numbers, Work::Number).timeout(10, || Work::Idle).wait());
+    }
+
+    #[test]
+    fn shared_branch_passes_message_of_channel()
+    {
+        let mut chm = ChannelManager::new();
+        let texts = chm.get_receiver::<String>();
+        let other_texts = chm.get_receiver::<String>();
+        let mut selector = Selector::new("Test");
+
+        chm.get_sender::<String>().send("door".to_string());
+        let result = selector.select().recv_shared(&texts, |msg| msg).wait();
+        assert!(Arc::ptr_eq(&result, &other_texts.receive_shared()));
+    }
+
+    #[test]
+    fn busy_channel_does_not_starve_others()
+    {
+        let mut chm = ChannelManager::new();
+        let numbers = chm.get_receiver::<u32>();
+        let texts = chm.get_receiver::<String>();
+        let number_tx = chm.get_sender::<u32>();
+        let mut selector = Selector::new("Test");
+
+        for n in 0..10
+        {
+            number_tx.send(n);
+        }
+        chm.get_sender::<String>().send("stop".to_string());
+
+        let mut results = Vec::new();
+        for _ in 0..3
+        {
+            results.push(selector.select().recv(&numbers, Work::Number).recv(&texts, Work::Text).wait());
+        }
+        assert_eq!(vec![Work::Number(0), Work::Text("stop".to_string()), Work::Number(1)], results);
+    }
+}
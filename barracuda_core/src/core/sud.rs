@@ -0,0 +1,194 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor};
+
+/// # A System Unique iD (SUD)
+/// Addresses a component of a module instance, see make_sud:
+/// AAAA AAAA BBBB BBBB CCCC CCCC CCCC CCCC
+/// A: module type, B: instance of the module, C: object index.
+/// Written as "<module type>.<instance>.<object>", e.g. "12.0.4"
+/// is object 4 of the first instance of module type 12. Serialized
+/// in this notation, deserialized from this notation as well as from
+/// the plain number, so configuration files containing numbers can
+/// still be read.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Sud(u32);
+
+impl Sud
+{
+    pub fn new(module_type: u8, instance: u8, object: u16) -> Self
+    {
+        Sud(((module_type as u32) << 24) | ((instance as u32) << 16) | (object as u32))
+    }
+
+    pub fn module_type(&self) -> u8
+    {
+        (self.0 >> 24) as u8
+    }
+
+    pub fn instance(&self) -> u8
+    {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn object(&self) -> u16
+    {
+        self.0 as u16
+    }
+}
+
+impl From<u32> for Sud
+{
+    fn from(value: u32) -> Self {
+        Sud(value)
+    }
+}
+
+impl From<Sud> for u32
+{
+    fn from(sud: Sud) -> Self {
+        sud.0
+    }
+}
+
+impl fmt::Display for Sud
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.module_type(), self.instance(), self.object())
+    }
+}
+
+impl fmt::Debug for Sud
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sud({})", self)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SudParseError
+{
+    InvalidFormat,      // Not of the form <module type>.<instance>.<object>
+    OutOfRange          // A part exceeds its range, e.g. a module type > 255
+}
+
+impl fmt::Display for SudParseError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            SudParseError::InvalidFormat => write!(f, "Expected <module type>.<instance>.<object>"),
+            SudParseError::OutOfRange => write!(f, "Module type and instance must be < 256, the object < 65536")
+        }
+    }
+}
+
+impl FromStr for Sud
+{
+    type Err = SudParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split('.').collect();
+        if parts.len() != 3
+        {
+            return Err(SudParseError::InvalidFormat);
+        }
+
+        let mut values = [0u32; 3];
+        for (value, part) in values.iter_mut().zip(parts.iter())
+        {
+            if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit())
+            {
+                return Err(SudParseError::InvalidFormat);
+            }
+            *value = part.parse::<u32>().map_err(|_| SudParseError::OutOfRange)?;
+        }
+
+        if values[0] > 0xFF || values[1] > 0xFF || values[2] > 0xFFFF
+        {
+            return Err(SudParseError::OutOfRange);
+        }
+        Ok(Sud::new(values[0] as u8, values[1] as u8, values[2] as u16))
+    }
+}
+
+impl Serialize for Sud
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct SudVisitor;
+
+impl<'de> Visitor<'de> for SudVisitor
+{
+    type Value = Sud;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a SUD, either as number or as \"<module type>.<instance>.<object>\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Sud, E> {
+        if value > u32::MAX as u64
+        {
+            return Err(E::custom(SudParseError::OutOfRange));
+        }
+        Ok(Sud(value as u32))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Sud, E> {
+        if value < 0
+        {
+            return Err(E::custom(SudParseError::OutOfRange));
+        }
+        self.visit_u64(value as u64)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Sud, E> {
+        value.parse::<Sud>().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sud
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SudVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dotted_notation_survives_roundtrip()
+    {
+        let sud = Sud::new(12, 0, 4);
+        assert_eq!((12, 0, 4), (sud.module_type(), sud.instance(), sud.object()));
+        assert_eq!("12.0.4", sud.to_string());
+        assert_eq!(Ok(sud), "12.0.4".parse::<Sud>());
+        assert_eq!(0x0C000004, u32::from(sud));
+    }
+
+    #[test]
+    fn malformed_notation_is_rejected()
+    {
+        assert_eq!(Err(SudParseError::InvalidFormat), "12.0".parse::<Sud>());
+        assert_eq!(Err(SudParseError::InvalidFormat), "12.-1.4".parse::<Sud>());
+        assert_eq!(Err(SudParseError::InvalidFormat), "218103808".parse::<Sud>());
+        assert_eq!(Err(SudParseError::OutOfRange), "256.0.4".parse::<Sud>());
+        assert_eq!(Err(SudParseError::OutOfRange), "12.0.65536".parse::<Sud>());
+    }
+
+    #[test]
+    fn both_forms_are_deserialized()
+    {
+        let sud = Sud::new(13, 0, 0);
+        assert_eq!("\"13.0.0\"", serde_json::to_string(&sud).unwrap());
+        assert_eq!(sud, serde_json::from_str::<Sud>("\"13.0.0\"").unwrap());
+        assert_eq!(sud, serde_json::from_str::<Sud>("218103808").unwrap());
+        assert!(serde_json::from_str::<Sud>("-1").is_err());
+    }
+}
@@ -6,21 +6,161 @@
     desired api: launch!(acm::generic_whitelist::launch, trace::launch, othermodule::launch)
 */
 
-use crate::core::{SystemMessage, BootStage};
+use crate::core::{SystemMessage, BootStage, ModuleFault};
 use crate::core::broadcast_channel::*;
+use crate::core::channel_manager::ChannelManager;
+use crate::core::dependencies;
+use crate::core::liveness::LivenessMonitor;
+use crate::core::registry::{ModuleRegistry, RegistryError};
+use crate::core::shareable::Shareable;
 use crate::trace::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Time a module has to complete the shutdown stage, e.g. before
+/// it is considered dead and relaunched regardless when rebooting.
+const SHUTDOWN_TIMEOUT: u64 = 2500;  // in ms!
+
+/// Launches a module, see Supervisor::start_thread.
+type Launcher = Box<dyn Fn(&mut ChannelManager) + Send>;
+
+/// Outcome of asking a module to run its shutdown stage.
+#[derive(PartialEq)]
+enum StopResult
+{
+    Stopped,
+    Refused,
+    TimedOut
+}
+
+/// Controls how long modules may take to complete each
+/// bootstage and which modules the system can run without.
+/// Modules that are not optional are critical, i.e. if they
+/// fail to boot, the whole boot fails.
+#[derive(Clone, Debug)]
+pub struct BootSettings
+{
+    pub stage_timeout: u64,                                 // in ms! Applies to all stages and modules unless overridden
+    pub stage_timeouts: HashMap<BootStage, u64>,            // in ms! Overrides the timeout of a single stage
+    pub module_timeouts: HashMap<(u32, BootStage), u64>,    // in ms! Overrides the timeout of a stage for a single module
+    pub optional_modules: Vec<u32>
+}
+
+impl Default for BootSettings
+{
+    fn default() -> Self {
+        BootSettings
+        {
+            stage_timeout: 2500,
+            stage_timeouts: HashMap::new(),
+            module_timeouts: HashMap::new(),
+            optional_modules: Vec::new()
+        }
+    }
+}
+
+impl BootSettings
+{
+    /// Yields the time the module has to complete the stage, use
+    /// None if the module is not known yet (i.e. during Sync).
+    pub fn timeout(&self, module_id: Option<u32>, stage: BootStage) -> u64
+    {
+        if let Some(timeout) = module_id.and_then(|id| self.module_timeouts.get(&(id, stage)))
+        {
+            return *timeout;
+        }
+        *self.stage_timeouts.get(&stage).unwrap_or(&self.stage_timeout)
+    }
+
+    pub fn is_optional(&self, module_id: u32) -> bool
+    {
+        self.optional_modules.contains(&module_id)
+    }
+}
+
+/// Controls what the supervisor does with a module that
+/// stopped answering heartbeats or whose thread panicked.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeartbeatPolicy
+{
+    Restart,        // Reboot the module, i.e. launch a fresh instance of it
+    Panic,          // Terminate the software, the board adaption is expected to reboot the device
+    Degrade         // Stop supervising the module and keep running without it
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeartbeatSettings
+{
+    pub interval: u64,      // in ms!
+    pub max_missed: u32,
+    pub policy: HeartbeatPolicy
+}
+
+impl Default for HeartbeatSettings
+{
+    fn default() -> Self {
+        HeartbeatSettings
+        {
+            interval: 5000,
+            max_missed: 2,
+            policy: HeartbeatPolicy::Panic
+        }
+    }
+}
+
+/// Reason why the system failed to boot, i.e. why a critical module
+/// could not be started. Reported once by the supervisor and returned
+/// by start_thread and run (and thus by launch!).
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootError
+{
+    NoSync(usize),                          // Position of a launcher whose module neither registered nor synced
+    StageFailed(String, BootStage, u64),    // Critical module, stage it failed to run and the timeout in ms
+    Dependencies(dependencies::DependencyError),
+    Registry(RegistryError)
+}
+
+impl fmt::Display for BootError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            BootError::NoSync(position) => write!(f, "Module #{} did not register nor sync.", position),
+            BootError::StageFailed(module, stage, timeout) => write!(f, "Critical module {} failed to run stage {:?} within {} ms.", module, stage, timeout),
+            BootError::Dependencies(e) => write!(f, "Cannot compute start order: {}", e),
+            BootError::Registry(e) => write!(f, "Module rejected: {}", e)
+        }
+    }
+}
 
 pub struct Supervisor{
     sysrec: GenericReceiver<SystemMessage>,
     tracer: trace_helper::TraceHelper,
-    chm: crate::core::channel_manager::ChannelManager,
-    num_threads: u32
+    chm: ChannelManager,
+    boot: BootSettings,
+    heartbeat: HeartbeatSettings,
+    liveness: LivenessMonitor,
+    registry: Shareable<ModuleRegistry>,
+    launchers: Vec<Launcher>,
+    launcher_of_module: HashMap<u32, usize>,    // module id -> index into launchers
+    start_order: Vec<u32>,
+    pending: VecDeque<SystemMessage>,           // Received while waiting for a module, handled afterwards
+    rebooting: Option<u32>,                     // Module that is currently being rebooted
+    boot_error: Option<BootError>               // First failure, no further modules are started once set
+}
+
+impl Default for Supervisor
+{
+    fn default() -> Self {
+        Supervisor::new()
+    }
 }
 
 impl Supervisor
 {
     pub fn new() -> Self{
-        let mut chanmgr = crate::core::channel_manager::ChannelManager::new();
+        let mut chanmgr = ChannelManager::new();
         let syschan = chanmgr.get_receiver();
 
         Supervisor
@@ -28,115 +168,865 @@ impl Supervisor
             sysrec: syschan,
             tracer: trace_helper::TraceHelper::new("SYS/Sypervisor".to_string(), &mut chanmgr),
             chm: chanmgr,            
-            num_threads: 0
+            boot: BootSettings::default(),
+            heartbeat: HeartbeatSettings::default(),
+            liveness: LivenessMonitor::new(HeartbeatSettings::default().max_missed),
+            registry: Shareable::new(ModuleRegistry::new()),
+            launchers: Vec::new(),
+            launcher_of_module: HashMap::new(),
+            start_order: Vec::new(),
+            pending: VecDeque::new(),
+            rebooting: None,
+            boot_error: None
         }
     }
 
-    pub fn start_thread<T>(&mut self, launcher: T)
-        where T: FnOnce(&mut crate::core::channel_manager::ChannelManager)
+    /// Yields the registry of all modules, that registered
+    /// with the supervisor.
+    pub fn registry(&self) -> Shareable<ModuleRegistry>
+    {
+        self.registry.clone()
+    }
+
+    /// Note: Has to be called before the first module is launched
+    /// to apply to all modules.
+    pub fn set_boot_settings(&mut self, settings: BootSettings)
     {
-        self.num_threads += 1;
+        self.boot = settings;
+    }
+
+    pub fn set_heartbeat_settings(&mut self, settings: HeartbeatSettings)
+    {
+        self.heartbeat = settings;
+        self.liveness = LivenessMonitor::new(settings.max_missed);
+    }
+
+    /// Launches a module. The launcher is expected to start exactly
+    /// one module, which checks in for the Sync stage right away.
+    /// The launcher is kept, so the module can be launched again
+    /// if it has to be rebooted later on. Fails if the module is
+    /// critical and did not sync, the launcher is not called at
+    /// all if the boot failed already.
+    pub fn start_thread<T>(&mut self, launcher: T) -> Result<(), BootError>
+        where T: Fn(&mut ChannelManager) + Send + 'static
+    {
+        self.boot_result()?;
         launcher(&mut self.chm);
+        let index = self.launchers.len();
+        self.launchers.push(Box::new(launcher));
+        match self.wait_for_stage_completion(BootStage::Sync, None)
+        {
+            Some(mod_id) => { self.launcher_of_module.insert(mod_id, index); },
+            None if self.boot_error.is_some() => {},
+            None => {
+                // The module might have registered before it got stuck,
+                // otherwise there is no way to tell if it is optional.
+                let registered = self.registry.lock()
+                                              .modules()
+                                              .iter()
+                                              .find(|x| x.stage.is_none() && x.failed_stage.is_none())
+                                              .map(|x| x.id);
+                match registered
+                {
+                    Some(mod_id) => {
+                        self.launcher_of_module.insert(mod_id, index);
+                        self.on_stage_timeout(mod_id, BootStage::Sync);
+                    },
+                    None => self.fail_boot(BootError::NoSync(index + 1))
+                }
+            }
+        }
+        self.boot_result()
     }
 
-    pub fn run(&mut self)
+    /// Boots all launched modules and supervises them until the
+    /// system is shut down. Fails if a critical module could not
+    /// be booted, or rebooted later on. In the latter case the
+    /// remaining modules are shut down before.
+    pub fn run(&mut self) -> Result<(), BootError>
     {        
+        self.boot_result()?;
         self.tracer.trace_str("Starting system.");
         self.do_startup();        
+        self.boot_result()?;
+        let heartbeat_interval = Duration::from_millis(self.heartbeat.interval);
+        let sender = self.chm.get_sender::<SystemMessage>();
+        sender.send(SystemMessage::Heartbeat);
+        let mut last_heartbeat = Instant::now();
         loop {
-            let event = self.sysrec.receive_with_timeout(5000);
+            let remaining = heartbeat_interval.checked_sub(last_heartbeat.elapsed())
+                                              .unwrap_or(Duration::from_millis(0));
+            let (event, deferred) = match self.pending.pop_front()
+            {
+                Some(e) => (Some(e), true),
+                None => (self.sysrec.receive_with_timeout(remaining.as_millis() as u64), false)
+            };
             if let Some(e) = event
             {
                 match e
                 {
-                    SystemMessage::Shutdown => break,
-                    _ => continue
+                    SystemMessage::Shutdown => {
+                        self.do_shutdown();
+                        break;
+                    },
+                    SystemMessage::HeartbeatResponse(mod_id) => self.liveness.on_heartbeat_response(mod_id),
+                    SystemMessage::Reboot(mod_id) => {
+                        self.reboot_module(mod_id, deferred);
+                        if self.boot_error.is_some()
+                        {
+                            self.do_shutdown();
+                            break;
+                        }
+                    },
+                    SystemMessage::ModulePanicked(mod_id, msg) => self.on_module_panicked(mod_id, msg),
+                    _ => {}
                 }
             }
-            else
+
+            if last_heartbeat.elapsed() >= heartbeat_interval
             {
-                // Nothing happened. Send a heartbeat message to make sure
-                // all modules are still alive:
-                // Note: Disabled for now
-                //let sender = self.chm.get_sender::<SystemMessage>();
-                //sender.send(SystemMessage::Heartbeat);
+                // All modules had a whole interval to answer the last
+                // heartbeat, check who didn't and send the next one.
+                self.check_liveness();
+                sender.send(SystemMessage::Heartbeat);
+                last_heartbeat = Instant::now();
+            }
+        }
+        self.boot_result()
+    }
+
+    fn boot_result(&self) -> Result<(), BootError>
+    {
+        match &self.boot_error
+        {
+            Some(e) => Err(e.clone()),
+            None => Ok(())
+        }
+    }
+
+    fn check_liveness(&mut self)
+    {
+        let fault_tx = self.chm.get_sender::<ModuleFault>();
+        for mod_id in self.liveness.evaluate()
+        {
+            let description = self.registry.lock().describe(mod_id);
+            self.tracer.trace(format!("Module {} failed to answer {} heartbeats.", description, self.heartbeat.max_missed + 1));
+            fault_tx.send(ModuleFault::HeartbeatMissed(mod_id));
+            self.apply_fault_policy(mod_id, &description, "is unresponsive");
+        }
+    }
+
+    /// A module thread that panicked is gone, while other threads of
+    /// the module might still be running. Either way the module is
+    /// not working correctly anymore.
+    fn on_module_panicked(&mut self, mod_id: u32, msg: String)
+    {
+        let description = self.registry.lock().describe(mod_id);
+        self.tracer.error(format!("Module {} panicked: {}", description, msg));
+        self.chm.get_sender::<ModuleFault>().send(ModuleFault::Panicked(mod_id, msg));
+        self.apply_fault_policy(mod_id, &description, "panicked");
+    }
+
+    fn apply_fault_policy(&mut self, mod_id: u32, description: &str, fault: &str)
+    {
+        match self.heartbeat.policy
+        {
+            HeartbeatPolicy::Restart => {
+                // The supervisor receives its own request as well and
+                // supervises the module again once it has rebooted. A
+                // dead module does not acknowledge the reboot and is
+                // relaunched once the shutdown timeout has passed.
+                self.liveness.remove_module(mod_id);
+                self.chm.get_sender::<SystemMessage>().send(SystemMessage::Reboot(mod_id));
+            },
+            HeartbeatPolicy::Panic => {
+                panic!("Module {} {}.", description, fault)
+            },
+            HeartbeatPolicy::Degrade => {
+                self.tracer.trace(format!("Module {} is no longer supervised, running degraded.", description));
+                self.liveness.remove_module(mod_id);
+            }
+        }
+    }
+
+    /// Keeps a message that was received while waiting for a module
+    /// and has to be handled once the wait is over. Messages the run
+    /// loop does not handle are dropped, except for the answers to stop
+    /// requests, which a later wait_for_stop might be waiting for.
+    fn defer(&mut self, msg: SystemMessage)
+    {
+        match msg
+        {
+            // Fulfilled by the reboot that is in progress.
+            SystemMessage::Reboot(id) if self.rebooting == Some(id) => {},
+            SystemMessage::Shutdown |
+            SystemMessage::Reboot(_) |
+            SystemMessage::ModulePanicked(_, _) |
+            SystemMessage::StageComplete(BootStage::Shutdown, _) |
+            SystemMessage::RebootRefused(_) => self.pending.push_back(msg),
+            _ => {}
+        }
+    }
+
+    /// Reboots a single module: The module is asked to terminate
+    /// and a fresh instance is launched using the launcher the
+    /// module was started with. The new instance runs through all
+    /// bootstages on its own, while the rest of the system stays
+    /// in the application stage.
+    fn reboot_module(&mut self, mod_id: u32, deferred: bool)
+    {
+        self.rebooting = Some(mod_id);
+        self.do_reboot(mod_id, deferred);
+        self.rebooting = None;
+    }
+
+    fn do_reboot(&mut self, mod_id: u32, deferred: bool)
+    {
+        let description = self.registry.lock().describe(mod_id);
+        let launcher = match self.launcher_of_module.get(&mod_id)
+        {
+            Some(index) => *index,
+            None => {
+                self.tracer.trace(format!("Cannot reboot module {}, it was not launched by the supervisor.", description));
+                return;
+            }
+        };
+
+        self.tracer.trace(format!("Rebooting module {}", description));
+        self.liveness.remove_module(mod_id);
+        // A deferred request might not have reached the module, e.g.
+        // because it was still booting, so it is asked again unless
+        // it has answered already.
+        if deferred && !self.pending.iter().any(|x| Supervisor::stop_result(x, mod_id).is_some())
+        {
+            self.chm.get_sender::<SystemMessage>().send(SystemMessage::Reboot(mod_id));
+        }
+        // A module that does not answer at all is considered
+        // dead and is relaunched regardless.
+        if self.wait_for_stop(mod_id) == StopResult::Refused
+        {
+            self.tracer.trace(format!("Module {} refused to reboot.", description));
+            self.liveness.add_module(mod_id);
+            return;
+        }
+
+        let _ = self.registry.lock().unregister(mod_id);
+        (self.launchers[launcher])(&mut self.chm);
+
+        if self.wait_for_stage_completion(BootStage::Sync, Some(mod_id)).is_none()
+        {
+            if self.boot_error.is_none()
+            {
+                self.on_stage_timeout(mod_id, BootStage::Sync);
+            }
+            return;
+        }
+        if !self.run_init_stages(mod_id)
+        {
+            return;
+        }
+        // A module that failed to boot initially is part of the system again.
+        if !self.start_order.contains(&mod_id)
+        {
+            self.start_order.push(mod_id);
+        }
+        self.chm.get_sender::<SystemMessage>().send(SystemMessage::RunStageFor(BootStage::Application, mod_id));
+        let _ = self.registry.lock().check_in(mod_id, BootStage::Application);
+
+        self.liveness.add_module(mod_id);
+        self.tracer.trace(format!("Module {} rebooted.", description));
+    }
+
+    /// Shuts down all modules in the reverse start order, so that
+    /// modules stop before the modules they depend on, e.g. the IO
+    /// manager can still switch all outputs to a safe state using
+    /// the platform module. Each module runs its shutdown stage on
+    /// its own and has to complete it in time.
+    fn do_shutdown(&mut self)
+    {
+        self.tracer.trace_str("Shutting down.");
+        let sender = self.chm.get_sender::<SystemMessage>();
+        for mod_id in self.start_order.clone().into_iter().rev()
+        {
+            let description = self.registry.lock().describe(mod_id);
+            self.tracer.trace(format!("Bootstage: Shutdown {}", description));
+            self.liveness.remove_module(mod_id);
+            sender.send(SystemMessage::ShutdownModule(mod_id));
+            match self.wait_for_stop(mod_id)
+            {
+                StopResult::Stopped => { let _ = self.registry.lock().check_in(mod_id, BootStage::Shutdown); },
+                _ => self.tracer.trace(format!("Module {} failed to shut down within {} ms.", description, SHUTDOWN_TIMEOUT))
+            }
+        }
+        // All modules are stopped, no one needs the timer anymore.
+        self.chm.stop_timer();
+        self.tracer.trace_str("Shutdown complete.");
+    }
+
+    /// Waits for the module to complete the shutdown stage, which
+    /// is the answer to a reboot as well as to a shutdown request.
+    fn wait_for_stop(&mut self, mod_id: u32) -> StopResult
+    {
+        if let Some(index) = self.pending.iter().position(|x| Supervisor::stop_result(x, mod_id).is_some())
+        {
+            let answer = self.pending.remove(index).unwrap();
+            return Supervisor::stop_result(&answer, mod_id).unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(SHUTDOWN_TIMEOUT);
+        loop
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0
+            {
+                return StopResult::TimedOut;
+            }
+
+            match self.sysrec.receive_with_timeout(remaining.as_millis() as u64)
+            {
+                Some(SystemMessage::HeartbeatResponse(id)) => self.liveness.on_heartbeat_response(id),
+                Some(msg) => match Supervisor::stop_result(&msg, mod_id)
+                {
+                    Some(result) => return result,
+                    None => self.defer(msg)
+                },
+                None => return StopResult::TimedOut
             }
         }
     }
 
+    /// Yields the result if the message answers a stop request
+    /// sent to the given module.
+    fn stop_result(msg: &SystemMessage, mod_id: u32) -> Option<StopResult>
+    {
+        match msg
+        {
+            SystemMessage::StageComplete(BootStage::Shutdown, id) if *id == mod_id => Some(StopResult::Stopped),
+            SystemMessage::RebootRefused(id) if *id == mod_id => Some(StopResult::Refused),
+            _ => None
+        }
+    }
+
     fn do_startup(&mut self)
     {                
         let sender = self.chm.get_sender::<SystemMessage>();  
 
-        // All modules will send a sync message upon starup to signal that they are ready.
-        self.tracer.trace_str("Wait for Sync");        
-        self.wait_for_stage_completion(&self.sysrec, BootStage::Sync, self.num_threads);
+        // All modules are known now, make the registry available to
+        // anyone interested (e.g. the configuration interface).
+        self.chm.get_sender::<Shareable<ModuleRegistry>>().send(self.registry.clone());
 
-        // Once all threads are go, send a message to the threads to actually start:
-        self.tracer.trace_str("Bootstage: LowLevelInit");
-        sender.send(SystemMessage::RunStage(BootStage::LowLevelInit));
-        self.wait_for_stage_completion(&self.sysrec, BootStage::LowLevelInit, self.num_threads);
-    
-        // After lowlevel init is done, do the highlevel init
-        self.tracer.trace_str("Bootstage: HighLevelInit");
-        sender.send(SystemMessage::RunStage(BootStage::HighLevelInit));
-        self.wait_for_stage_completion(&self.sysrec, BootStage::HighLevelInit, self.num_threads);
+        // Note: Modules that already failed to sync are part of the computation,
+        // the things they provide are just not available when running degraded.
+        self.start_order = self.compute_start_order();
+        if self.boot_error.is_some()
+        {
+            return;
+        }
+        let registry = self.registry.clone();
+        self.start_order.retain(|x| registry.lock().get(*x).is_some_and(|m| m.failed_stage.is_none()));
+        let order: Vec<String> = self.start_order.iter().map(|x| registry.lock().describe(*x)).collect();
+        self.tracer.trace(format!("Start order: {}", order.join(", ")));
+
+        // Each module runs its init stages only after all modules
+        // providing its dependencies are done with theirs.
+        for mod_id in self.start_order.clone()
+        {
+            if !self.run_init_stages(mod_id) && self.boot_error.is_some()
+            {
+                return;
+            }
+        }
     
         // Now all modules should have the required data present for running without problems
-        // and can enter the application stage.
-        self.tracer.trace_str("Boot complete. Barracuda is ready.");
+        // and can enter the application stage. Every module that got here is supervised
+        // from now on.
+        let failed: Vec<String> = registry.lock()
+                                          .modules()
+                                          .iter()
+                                          .filter(|x| x.failed_stage.is_some())
+                                          .map(|x| x.to_string())
+                                          .collect();
+        if failed.is_empty()
+        {
+            self.tracer.trace_str("Boot complete. Barracuda is ready.");
+        }
+        else
+        {
+            self.tracer.trace(format!("Boot complete. Barracuda is running degraded, failed modules: {}", failed.join(", ")));
+        }
         sender.send(SystemMessage::RunStage(BootStage::Application));
+        for mod_id in self.start_order.iter()
+        {
+            let _ = registry.lock().check_in(*mod_id, BootStage::Application);
+            self.liveness.add_module(*mod_id);
+        }
         // No need to wait here, this is where the rest of the application happens.
         //wait_for_stage_completion(recv, core::BootStage::Application, 0);
     }
 
-    fn wait_for_stage_completion(&self, recv: &GenericReceiver<SystemMessage>, stage: BootStage, num_participants: u32 ) -> bool
+    /// Orders all launched modules by their dependencies, modules
+    /// that don't depend on each other keep their launch order.
+    fn compute_start_order(&mut self) -> Vec<u32>
+    {
+        let mut launched: Vec<(usize, u32)> = self.launcher_of_module.iter()
+                                                                     .map(|(mod_id, launcher)| (*launcher, *mod_id))
+                                                                     .collect();
+        launched.sort_unstable();
+
+        let registry = self.registry.lock();
+        let modules: Vec<(u32, dependencies::Dependencies)> = launched.iter()
+                                                                      .map(|(_, mod_id)| (*mod_id, registry.get(*mod_id).unwrap().dependencies.clone()))
+                                                                      .collect();
+        match dependencies::start_order(&modules)
+        {
+            Ok(order) => order,
+            Err(errors) => {
+                drop(registry);
+                for e in errors.iter().skip(1)
+                {
+                    self.tracer.error(e.to_string());
+                }
+                self.fail_boot(BootError::Dependencies(errors[0].clone()));
+                Vec::new()
+            }
+        }
+    }
+
+    /// Runs the LLI and HLI stages of a single module, yields false
+    /// if the module failed to complete them.
+    fn run_init_stages(&mut self, mod_id: u32) -> bool
+    {
+        let sender = self.chm.get_sender::<SystemMessage>();
+        let description = self.registry.lock().describe(mod_id);
+        for stage in [BootStage::LowLevelInit, BootStage::HighLevelInit].iter()
+        {
+            self.tracer.trace(format!("Bootstage: {:?} {}", stage, description));
+            sender.send(SystemMessage::RunStageFor(*stage, mod_id));
+            if self.wait_for_stage_completion(*stage, Some(mod_id)).is_none()
+            {
+                if self.boot_error.is_none()
+                {
+                    self.on_stage_timeout(mod_id, *stage);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Handles a module that failed to complete a stage in time: A
+    /// critical module stops the boot, an optional module is dropped,
+    /// i.e. the system keeps running degraded without it.
+    fn on_stage_timeout(&mut self, mod_id: u32, stage: BootStage)
     {
-        let mut checked_in = Vec::<u32>::new();
-        let mut messages_left = num_participants;
-        while messages_left > 0
+        let description = self.registry.lock().describe(mod_id);
+        let timeout = self.boot.timeout(Some(mod_id), stage);
+        if self.boot.is_optional(mod_id)
+        {
+            self.tracer.trace(format!("Optional module {} failed to run stage {:?} within {} ms, running degraded.", description, stage, timeout));
+            self.chm.get_sender::<ModuleFault>().send(ModuleFault::BootFailed(mod_id, stage));
+        }
+        else
         {
-            let data = recv.receive_with_timeout(2500);
+            self.fail_boot(BootError::StageFailed(description, stage, timeout));
+        }
+
+        let _ = self.registry.lock().mark_failed(mod_id, stage);
+        self.start_order.retain(|x| *x != mod_id);
+        self.liveness.remove_module(mod_id);
+    }
+
+    /// Waits until a module completed the given stage. If a participant
+    /// is given, check ins of all other modules are ignored, which is
+    /// used when running the stages of a single module. Yields the module
+    /// that checked in or None, if the configured timeout has passed or
+    /// the boot failed.
+    fn wait_for_stage_completion(&mut self, stage: BootStage, participant: Option<u32>) -> Option<u32>
+    {
+        let started = Instant::now();
+        let mut deadline = started + Duration::from_millis(self.boot.timeout(participant, stage));
+        loop
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0
+            {
+                return None;
+            }
+
+            let data = self.sysrec.receive_with_timeout(remaining.as_millis() as u64);
             if let Some(received) = data {
                 match received
                 {
-                    SystemMessage::StageComplete(the_stage, mod_id) => 
+                    SystemMessage::Register(mod_id, name, deps) =>
+                    {
+                        let result = self.registry.lock().register(mod_id, name, deps);
+                        if let Err(e) = result
+                        {
+                            self.fail_boot(BootError::Registry(e));
+                            return None;
+                        }
+                        // Without a participant the module is unknown until
+                        // it registers, its own timeout applies from now on.
+                        if participant.is_none()
+                        {
+                            deadline = started + Duration::from_millis(self.boot.timeout(Some(mod_id), stage));
+                        }
+                    }
+                    SystemMessage::StageComplete(the_stage, mod_id) if the_stage == stage && (participant.is_none() || participant == Some(mod_id)) =>
+                    {
+                        let mut registry = self.registry.lock();
+                        // Modules that did not register are still accepted,
+                        // they just don't have a name.
+                        if stage == BootStage::Sync && registry.get(mod_id).is_none()
+                        {
+                            let _ = registry.register(mod_id, "<unnamed>".to_string(), dependencies::Dependencies::none());
+                        }
+                        let result = registry.check_in(mod_id, stage);
+                        let description = registry.describe(mod_id);
+                        drop(registry);
+
+                        if let Err(e) = result
+                        {
+                            self.fail_boot(BootError::Registry(e));
+                            return None;
+                        }
+                        self.tracer.trace(format!("Module {} checked in for stage {:?}", description, the_stage));
+                        return Some(mod_id);
+                    }
+                    SystemMessage::HeartbeatResponse(mod_id) => self.liveness.on_heartbeat_response(mod_id),
+                    // A module that panics while booting is handled like
+                    // a module that did not complete the stage in time.
+                    SystemMessage::ModulePanicked(mod_id, msg) if participant.is_none() || participant == Some(mod_id) =>
                     {
-                        if std::mem::discriminant(&the_stage) == std::mem::discriminant(&stage) {
-                            let mod_type = (mod_id & 0xFF000000) >> 24;
-                            let mod_instance = mod_id & 0x00FF0000 >> 16;
-                            // Note: It would probably be a good idea to not just count
-                            // modules, but to also make sure, that no module checks in
-                            // multiple times. This should also help to find ID clashes
-                            // of modules!
-                            self.tracer.trace(format!("Module {}, instance {} checked in for stage {} ", mod_type, mod_instance, the_stage as u32));
-                            checked_in.push(mod_id);
-                            messages_left -= 1
-                        }                     
+                        let description = self.registry.lock().describe(mod_id);
+                        self.tracer.error(format!("Module {} panicked during stage {:?}: {}", description, stage, msg));
+                        return None;
                     }
-                    _ => continue
+                    // E.g. a shutdown requested while still booting is
+                    // carried out once the system is up.
+                    other => self.defer(other)
                 }
 
             }
             else
             {
-                break;
+                return None;
             }
         }
+    }
 
-        if messages_left > 0
+    /// Reports the first failure only, as any further ones
+    /// are most likely a consequence of it.
+    fn fail_boot(&mut self, error: BootError)
+    {
+        if self.boot_error.is_none()
         {
-            let msg = format!("{} modules failed to run stage {}", messages_left, stage as u32);
-            println!("{}", msg);
-            for i in checked_in.iter()
+            self.tracer.error(format!("Failed to boot. {}", error));
+            self.chm.get_sender::<ModuleFault>().send(ModuleFault::BootAborted(error.to_string()));
+            self.boot_error = Some(error);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::bootstage_helper::*;
+    use crate::core::module_thread::spawn_module;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    const TEST_MODULE: u32 = 0x42000000;
+    const OTHER_MODULE: u32 = 0x43000000;
+
+    /// How the first instance of a test module misbehaves,
+    /// any later instance boots and runs regularly.
+    #[derive(Clone)]
+    enum Behaviour
+    {
+        Regular,
+        SendOnReboot(SystemMessage),    // Requests its own reboot once booted, sends the message before acknowledging it
+        SendDuringLli(SystemMessage),
+        HangBeforeSync,                 // Registers, but never syncs
+        DelaySync(u64),                 // in ms!
+        PanicDuringLli,
+        PanicOnHeartbeat                // i.e. once it is supervised
+    }
+
+    fn test_module(mod_id: u32, behaviour: Behaviour, launches: Arc<AtomicUsize>) -> impl Fn(&mut ChannelManager) + Send + 'static
+    {
+        move |chm: &mut ChannelManager| {
+            let instance = launches.fetch_add(1, Ordering::SeqCst);
+            let behaviour = if instance == 0 { behaviour.clone() } else { Behaviour::Regular };
+            let tx = chm.get_sender::<SystemMessage>();
+            let rx = chm.get_receiver::<SystemMessage>();
+            spawn_module(mod_id, "TST/Module", chm, move || {
+                tx.send(SystemMessage::Register(mod_id, "TST/Module".to_string(), dependencies::Dependencies::none()));
+                match behaviour
+                {
+                    Behaviour::HangBeforeSync => loop { thread::sleep(Duration::from_secs(10)); },
+                    Behaviour::DelaySync(delay) => thread::sleep(Duration::from_millis(delay)),
+                    _ => {}
+                }
+                tx.send(SystemMessage::StageComplete(BootStage::Sync, mod_id));
+                loop
+                {
+                    let msg = rx.receive();
+                    match (&behaviour, &msg)
+                    {
+                        (_, SystemMessage::RunStageFor(stage, id)) if *id == mod_id && *stage != BootStage::Application => {
+                            match (stage, &behaviour)
+                            {
+                                (BootStage::LowLevelInit, Behaviour::SendDuringLli(extra)) => tx.send(extra.clone()),
+                                (BootStage::LowLevelInit, Behaviour::PanicDuringLli) => panic!("Peripheral not found"),
+                                _ => {}
+                            }
+                            tx.send(SystemMessage::StageComplete(*stage, mod_id));
+                        },
+                        (Behaviour::SendOnReboot(_), SystemMessage::RunStage(BootStage::Application)) => tx.send(SystemMessage::Reboot(mod_id)),
+                        (Behaviour::SendOnReboot(extra), SystemMessage::Reboot(id)) if *id == mod_id => tx.send(extra.clone()),
+                        (Behaviour::PanicOnHeartbeat, SystemMessage::Heartbeat) => panic!("Malformed token"),
+                        _ => {}
+                    }
+                    if !handle_system_message(mod_id, msg, &tx)
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    fn boot_settings(stage_timeout: u64) -> BootSettings
+    {
+        let mut settings = BootSettings::default();
+        settings.stage_timeout = stage_timeout;
+        settings
+    }
+
+    fn heartbeat_settings(policy: HeartbeatPolicy) -> HeartbeatSettings
+    {
+        HeartbeatSettings { interval: 100, max_missed: 2, policy }
+    }
+
+    fn wait_until<F: Fn() -> bool>(timeout: u64, condition: F) -> bool
+    {
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+        while Instant::now() < deadline
+        {
+            if condition()
             {
-                let mod_type = (i &0xFF000000) >> 24;
-                let mod_instance = i & 0x00FF0000 >> 16;
-                println!("module {}, instance {} checked in", mod_type, mod_instance);
+                return true;
             }
-            panic!("Failed to boot. Not all modules checked in.")
+            thread::sleep(Duration::from_millis(10));
         }
+        condition()
+    }
+
+    #[test]
+    fn shutdown_requested_during_reboot_is_carried_out()
+    {
+        let launches = Arc::new(AtomicUsize::new(0));
+        let mut s = Supervisor::new();
+        s.start_thread(test_module(TEST_MODULE, Behaviour::SendOnReboot(SystemMessage::Shutdown), launches.clone())).unwrap();
+        let registry = s.registry();
+        let handle = thread::spawn(move || s.run());
+
+        assert!(wait_until(3000, || handle.is_finished()));
+        assert_eq!(2, launches.load(Ordering::SeqCst));
+        assert_eq!(Some(BootStage::Shutdown), registry.lock().get(TEST_MODULE).unwrap().stage);
+    }
+
+    #[test]
+    fn shutdown_requested_during_boot_is_carried_out()
+    {
+        let launches = Arc::new(AtomicUsize::new(0));
+        let mut s = Supervisor::new();
+        s.start_thread(test_module(TEST_MODULE, Behaviour::SendDuringLli(SystemMessage::Shutdown), launches.clone())).unwrap();
+        let registry = s.registry();
+        let handle = thread::spawn(move || s.run());
+
+        assert!(wait_until(3000, || handle.is_finished()));
+        assert_eq!(1, launches.load(Ordering::SeqCst));
+        assert_eq!(Some(BootStage::Shutdown), registry.lock().get(TEST_MODULE).unwrap().stage);
+    }
+
+    #[test]
+    fn repeated_reboot_request_is_coalesced()
+    {
+        let launches = Arc::new(AtomicUsize::new(0));
+        let mut s = Supervisor::new();
+        s.start_thread(test_module(TEST_MODULE, Behaviour::SendOnReboot(SystemMessage::Reboot(TEST_MODULE)), launches.clone())).unwrap();
+        let registry = s.registry();
+        let shutdown_tx = s.chm.get_sender::<SystemMessage>();
+        let handle = thread::spawn(move || s.run());
 
-        return messages_left <= 0 
+        assert!(wait_until(3000, || registry.lock().get(TEST_MODULE).unwrap().stage == Some(BootStage::Application)
+                                    && launches.load(Ordering::SeqCst) == 2));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(2, launches.load(Ordering::SeqCst));
+        shutdown_tx.send(SystemMessage::Shutdown);
+        assert!(wait_until(3000, || handle.is_finished()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn optional_module_that_does_not_sync_runs_degraded()
+    {
+        let mut settings = boot_settings(300);
+        settings.optional_modules.push(TEST_MODULE);
+        let mut s = Supervisor::new();
+        s.set_boot_settings(settings);
+        let fault_rx = s.chm.get_receiver::<ModuleFault>();
+        let shutdown_tx = s.chm.get_sender::<SystemMessage>();
+
+        assert!(s.start_thread(test_module(TEST_MODULE, Behaviour::HangBeforeSync, Arc::new(AtomicUsize::new(0)))).is_ok());
+        assert!(s.start_thread(test_module(OTHER_MODULE, Behaviour::Regular, Arc::new(AtomicUsize::new(0)))).is_ok());
+        assert!(fault_rx.receive_with_timeout(0) == Some(ModuleFault::BootFailed(TEST_MODULE, BootStage::Sync)));
+
+        let registry = s.registry();
+        let handle = thread::spawn(move || s.run());
+        assert!(wait_until(3000, || registry.lock().get(OTHER_MODULE).unwrap().stage == Some(BootStage::Application)));
+        assert_eq!(Some(BootStage::Sync), registry.lock().get(TEST_MODULE).unwrap().failed_stage);
+        shutdown_tx.send(SystemMessage::Shutdown);
+        assert_eq!(Ok(()), handle.join().unwrap());
+    }
+
+    #[test]
+    fn module_timeout_applies_to_sync()
+    {
+        let mut settings = boot_settings(300);
+        settings.module_timeouts.insert((TEST_MODULE, BootStage::Sync), 1500);
+        let mut s = Supervisor::new();
+        s.set_boot_settings(settings);
+
+        assert!(s.start_thread(test_module(TEST_MODULE, Behaviour::DelaySync(600), Arc::new(AtomicUsize::new(0)))).is_ok());
+        assert_eq!(Some(BootStage::Sync), s.registry().lock().get(TEST_MODULE).unwrap().stage);
+    }
+
+    #[test]
+    fn critical_module_that_does_not_sync_fails_the_boot()
+    {
+        let mut s = Supervisor::new();
+        s.set_boot_settings(boot_settings(300));
+        let fault_rx = s.chm.get_receiver::<ModuleFault>();
+        let launches = Arc::new(AtomicUsize::new(0));
+
+        let result = s.start_thread(test_module(TEST_MODULE, Behaviour::HangBeforeSync, Arc::new(AtomicUsize::new(0))));
+        assert!(matches!(result, Err(BootError::StageFailed(_, BootStage::Sync, 300))));
+        assert!(matches!(fault_rx.receive_with_timeout(0), Some(ModuleFault::BootAborted(_))));
+
+        // No further modules are started.
+        assert_eq!(result, s.start_thread(test_module(OTHER_MODULE, Behaviour::Regular, launches.clone())));
+        assert_eq!(0, launches.load(Ordering::SeqCst));
+        assert_eq!(result, s.run());
+    }
+
+    #[test]
+    fn panic_of_optional_module_during_boot_stage_degrades()
+    {
+        let mut settings = boot_settings(300);
+        settings.optional_modules.push(TEST_MODULE);
+        let mut s = Supervisor::new();
+        s.set_boot_settings(settings);
+        let fault_rx = s.chm.get_receiver::<ModuleFault>();
+        let shutdown_tx = s.chm.get_sender::<SystemMessage>();
+        s.start_thread(test_module(TEST_MODULE, Behaviour::PanicDuringLli, Arc::new(AtomicUsize::new(0)))).unwrap();
+        s.start_thread(test_module(OTHER_MODULE, Behaviour::Regular, Arc::new(AtomicUsize::new(0)))).unwrap();
+        let registry = s.registry();
+        let handle = thread::spawn(move || s.run());
+
+        assert!(wait_until(3000, || registry.lock().get(OTHER_MODULE).unwrap().stage == Some(BootStage::Application)));
+        assert_eq!(Some(BootStage::LowLevelInit), registry.lock().get(TEST_MODULE).unwrap().failed_stage);
+        assert!(fault_rx.receive_with_timeout(0) == Some(ModuleFault::BootFailed(TEST_MODULE, BootStage::LowLevelInit)));
+        shutdown_tx.send(SystemMessage::Shutdown);
+        assert_eq!(Ok(()), handle.join().unwrap());
+    }
+
+    #[test]
+    fn panic_of_critical_module_during_boot_stage_fails_the_boot()
+    {
+        let mut s = Supervisor::new();
+        s.set_boot_settings(boot_settings(300));
+        s.start_thread(test_module(TEST_MODULE, Behaviour::PanicDuringLli, Arc::new(AtomicUsize::new(0)))).unwrap();
+        s.start_thread(test_module(OTHER_MODULE, Behaviour::Regular, Arc::new(AtomicUsize::new(0)))).unwrap();
+        let registry = s.registry();
+
+        assert!(matches!(s.run(), Err(BootError::StageFailed(_, BootStage::LowLevelInit, _))));
+        // The boot stops right away, later modules are not started.
+        assert_eq!(Some(BootStage::Sync), registry.lock().get(OTHER_MODULE).unwrap().stage);
+    }
+
+    #[test]
+    fn panic_with_degrade_policy_stops_supervision()
+    {
+        let mut s = Supervisor::new();
+        s.set_heartbeat_settings(heartbeat_settings(HeartbeatPolicy::Degrade));
+        let fault_rx = s.chm.get_receiver::<ModuleFault>();
+        let shutdown_tx = s.chm.get_sender::<SystemMessage>();
+        let launches = Arc::new(AtomicUsize::new(0));
+        s.start_thread(test_module(TEST_MODULE, Behaviour::PanicOnHeartbeat, launches.clone())).unwrap();
+        let handle = thread::spawn(move || s.run());
+
+        assert!(fault_rx.receive_with_timeout(3000) == Some(ModuleFault::Panicked(TEST_MODULE, "Malformed token".to_string())));
+        // Neither relaunched nor reported for the missing heartbeats.
+        assert!(fault_rx.receive_with_timeout(500).is_none());
+        assert_eq!(1, launches.load(Ordering::SeqCst));
+        assert!(!handle.is_finished());
+        shutdown_tx.send(SystemMessage::Shutdown);
+        assert_eq!(Ok(()), handle.join().unwrap());
+    }
+
+    #[test]
+    fn panic_with_restart_policy_relaunches_module()
+    {
+        let mut s = Supervisor::new();
+        s.set_heartbeat_settings(heartbeat_settings(HeartbeatPolicy::Restart));
+        let fault_rx = s.chm.get_receiver::<ModuleFault>();
+        let shutdown_tx = s.chm.get_sender::<SystemMessage>();
+        let launches = Arc::new(AtomicUsize::new(0));
+        s.start_thread(test_module(TEST_MODULE, Behaviour::PanicOnHeartbeat, launches.clone())).unwrap();
+        let registry = s.registry();
+        let handle = thread::spawn(move || s.run());
+
+        assert!(fault_rx.receive_with_timeout(3000) == Some(ModuleFault::Panicked(TEST_MODULE, "Malformed token".to_string())));
+        // The dead module does not acknowledge the reboot, it is
+        // relaunched once the shutdown timeout has passed.
+        assert!(wait_until(SHUTDOWN_TIMEOUT + 2000, || launches.load(Ordering::SeqCst) == 2
+                                                       && registry.lock().get(TEST_MODULE).unwrap().stage == Some(BootStage::Application)));
+        shutdown_tx.send(SystemMessage::Shutdown);
+        assert_eq!(Ok(()), handle.join().unwrap());
+    }
+
+    #[test]
+    fn panic_with_panic_policy_terminates_supervisor()
+    {
+        let mut s = Supervisor::new();
+        s.set_heartbeat_settings(heartbeat_settings(HeartbeatPolicy::Panic));
+        s.start_thread(test_module(TEST_MODULE, Behaviour::PanicOnHeartbeat, Arc::new(AtomicUsize::new(0)))).unwrap();
+        let handle = thread::spawn(move || s.run());
+
+        assert!(wait_until(3000, || handle.is_finished()));
+        assert!(handle.join().is_err());
+    }
+
+    #[test]
+    fn module_timeout_overrides_stage_timeout()
+    {
+        let mut settings = BootSettings::default();
+        settings.stage_timeouts.insert(BootStage::LowLevelInit, 5000);
+        settings.module_timeouts.insert((0x0B000000, BootStage::LowLevelInit), 10000);
+        assert_eq!(10000, settings.timeout(Some(0x0B000000), BootStage::LowLevelInit));
+        assert_eq!(5000, settings.timeout(Some(0x03000000), BootStage::LowLevelInit));
+        assert_eq!(5000, settings.timeout(None, BootStage::LowLevelInit));
+        assert_eq!(2500, settings.timeout(Some(0x0B000000), BootStage::HighLevelInit));
+    }
+
+    #[test]
+    fn modules_are_critical_by_default()
+    {
+        let mut settings = BootSettings::default();
+        settings.optional_modules.push(0x0B000000);
+        assert!(settings.is_optional(0x0B000000));
+        assert!(!settings.is_optional(0x03000000));
+    }
+}
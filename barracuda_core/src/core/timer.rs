@@ -1,160 +1,377 @@
-use super::{shareable::Shareable, event::Event};
-use std::{time::{Instant, Duration}, thread, sync::{Weak, Arc}};
+use super::{shareable::Shareable, event::Event, clock::{Clock, system_clock}};
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, time::{Instant, Duration}, thread::{self, JoinHandle}, sync::{Weak, Arc, Mutex}};
+
+enum TimerCallback
+{
+    Once(Box<dyn FnOnce() + Send>),
+    Periodic(Box<dyn FnMut() + Send>, Duration)
+}
 
 struct TimerEntry
 {
-    callback: Option<Box<dyn FnOnce() -> () + Send>>,
+    callback: Option<TimerCallback>,    // None while a periodic callback is running
     due_time: Instant,
-    remove: bool,
-    guard: Weak<bool>
+    guard: Option<Weak<bool>>
+}
+
+/// All pending timers, ordered by their due time. Cancelled
+/// and rescheduled timers leave their old due time in the heap,
+/// these stale entries are skipped once they come up.
+struct TimerQueue
+{
+    entries: HashMap<u64, TimerEntry>,
+    due: BinaryHeap<Reverse<(Instant, u64)>>,
+    next_id: u64
+}
+
+impl TimerQueue
+{
+    fn insert(&mut self, entry: TimerEntry) -> u64
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.due.push(Reverse((entry.due_time, id)));
+        self.entries.insert(id, entry);
+        id
+    }
+
+    fn reschedule(&mut self, id: u64, due_time: Instant) -> bool
+    {
+        if let Some(entry) = self.entries.get_mut(&id)
+        {
+            entry.due_time = due_time;
+            self.due.push(Reverse((due_time, id)));
+            return true;
+        }
+        false
+    }
+
+    fn next_due(&self) -> Option<Instant>
+    {
+        self.due.peek().map(|x| (x.0).0)
+    }
+
+    /// Yields the next callback that is due at the given time. Periodic
+    /// timers stay in the queue with their next due time, their
+    /// callback has to be put back by restore() once it was called.
+    fn take_due(&mut self, now: Instant) -> Option<(u64, TimerCallback)>
+    {
+        while let Some(&Reverse((due_time, id))) = self.due.peek()
+        {
+            if due_time > now
+            {
+                return None;
+            }
+            self.due.pop();
+
+            let entry = match self.entries.get_mut(&id)
+            {
+                Some(e) if e.due_time == due_time => e,
+                _ => continue
+            };
+
+            if entry.guard.as_ref().is_some_and(|g| g.upgrade().is_none())
+            {
+                self.entries.remove(&id);
+                continue;
+            }
+
+            match entry.callback.take()
+            {
+                Some(TimerCallback::Periodic(callback, period)) =>
+                {
+                    // A timer that fell behind does not fire the periods it missed.
+                    let mut next_due = due_time + period;
+                    if next_due <= now
+                    {
+                        next_due = now + period;
+                    }
+                    entry.due_time = next_due;
+                    self.due.push(Reverse((next_due, id)));
+                    return Some((id, TimerCallback::Periodic(callback, period)));
+                }
+                Some(once) =>
+                {
+                    self.entries.remove(&id);
+                    return Some((id, once));
+                }
+                None => continue
+            }
+        }
+        None
+    }
+
+    fn restore(&mut self, id: u64, callback: TimerCallback)
+    {
+        // The timer might have been cancelled by its own callback.
+        if let Some(entry) = self.entries.get_mut(&id)
+        {
+            entry.callback = Some(callback);
+        }
+    }
 }
 
+/// # Timer service
+/// Calls callbacks after a delay or periodically, all on a single
+/// thread. Modules should not create timers of their own but use
+/// the timer of the channel manager (ChannelManager::timer()), so
+/// a device runs a single timer thread no matter how many doors
+/// it has. The callbacks run on the timer's thread and should
+/// therefore be short, e.g. send a message to the module.
+/// The thread terminates once the timer is stopped or dropped.
 pub struct Timer where
 {
-    scheduled_calls: Shareable<Vec<TimerEntry>>,
-    wait_event: Event,
-    terminate: Shareable<bool>
+    queue: Shareable<TimerQueue>,
+    firing: Mutex<()>,
+    wait_event: Arc<Event>,
+    terminate: Shareable<bool>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    clock: Arc<dyn Clock>
+}
+
+/// Refers to a timer started by schedule_once or schedule_periodic.
+/// Unlike the guard yielded by schedule, dropping the handle does
+/// not cancel the timer.
+pub struct TimerHandle
+{
+    id: u64,
+    timer: Weak<Timer>
+}
+
+impl TimerHandle
+{
+    /// Yields false if the timer had already fired (one-shot timers)
+    /// or was cancelled before.
+    pub fn cancel(&self) -> bool
+    {
+        match self.timer.upgrade()
+        {
+            Some(timer) => timer.queue.lock().entries.remove(&self.id).is_some(),
+            None => false
+        }
+    }
+
+    /// Lets the timer fire after the given delay (in ms!) instead of
+    /// its current due time, periodic timers continue with their
+    /// period afterwards. Yields false if the timer is not pending
+    /// anymore.
+    pub fn reschedule(&self, delay: u64) -> bool
+    {
+        match self.timer.upgrade()
+        {
+            Some(timer) =>
+            {
+                let due_time = timer.clock.now() + Duration::from_millis(delay);
+                let result = timer.queue.lock().reschedule(self.id, due_time);
+                timer.wait_event.trigger();
+                result
+            }
+            None => false
+        }
+    }
+
+    pub fn is_pending(&self) -> bool
+    {
+        match self.timer.upgrade()
+        {
+            Some(timer) => timer.queue.lock().entries.contains_key(&self.id),
+            None => false
+        }
+    }
 }
 
 impl Timer
 {
     pub fn new() -> Arc<Self>
+    {
+        Timer::with_clock(system_clock())
+    }
+
+    /// Creates a timer whose delays pass on the given clock,
+    /// e.g. the clock of the channel manager.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Arc<Self>
     {
         let result = Arc::new(Timer {
-            scheduled_calls:    Shareable::new(Vec::new()),
-            wait_event:         Event::new(),
-            terminate:          Shareable::new(false)         
+            queue:              Shareable::new(TimerQueue { entries: HashMap::new(), due: BinaryHeap::new(), next_id: 0 }),
+            firing:             Mutex::new(()),
+            wait_event:         Arc::new(Event::new()),
+            terminate:          Shareable::new(false),
+            thread:             Mutex::new(None),
+            clock
         });
-        Timer::start(result.clone());
-        return result;
+        *result.thread.lock().unwrap() = Some(Timer::start(&result));
+        result
     }
 
-    fn start(tm: Arc<Timer>)
+    /// The thread only holds a weak reference while waiting,
+    /// so it does not keep the timer alive.
+    fn start(tm: &Arc<Timer>) -> JoinHandle<()>
     {
-        let timer = tm.clone();
+        let timer = Arc::downgrade(tm);
+        let wait_event = tm.wait_event.clone();
+        let clock = tm.clock.clone();
         thread::spawn(move|| {             
-           timer.thread_func();              
-        });
+           Timer::thread_func(timer, wait_event, clock);
+        })
     }
 
-    #[allow(dead_code)]
+    /// Terminates the timer's thread and waits for it, i.e. for a
+    /// callback that is running. Pending callbacks are not called
+    /// anymore.
     pub fn stop(&self)
     {
-        let mut term = self.terminate.lock();
-        *term = true;
+        *self.terminate.lock() = true;
         self.wait_event.trigger();
+        let handle = self.thread.lock().unwrap().take();
+        if let Some(handle) = handle
+        {
+            // A callback that stops the timer runs on the thread itself.
+            if handle.thread().id() != thread::current().id()
+            {
+                let _ = handle.join();
+            }
+        }
     }
 
-    pub fn schedule(&self, callback: Box<dyn FnOnce() -> () + Send>, delay: u64) -> Arc<bool>
+    /// Calls the callback after delay ms, as long as the returned
+    /// guard is alive. Dropping the guard cancels the call.
+    pub fn schedule(&self, callback: Box<dyn FnOnce() + Send>, delay: u64) -> Arc<bool>
     {
-        let mut calls = self.scheduled_calls.lock();
-        let due_time = Instant::now() + Duration::from_millis(delay);
         let guard = Arc::new(false);
-        calls.push(TimerEntry{
-            callback: Some(callback), 
-            due_time: due_time, 
-            remove: false,
-            guard: Arc::downgrade(&guard)});
-        self.wait_event.trigger();
-        return guard;
+        self.add(TimerCallback::Once(callback), delay, Some(Arc::downgrade(&guard)));
+        guard
     }
 
-    fn get_min_remaining_timeout(&self) -> Instant
+    /// Calls the callback once after delay ms.
+    pub fn schedule_once(self: &Arc<Self>, callback: Box<dyn FnOnce() + Send>, delay: u64) -> TimerHandle
     {
-        let calls = self.scheduled_calls.lock();
-        let min_remaining = calls.iter()
-                                                         .min_by_key(|x| x.due_time);
+        let id = self.add(TimerCallback::Once(callback), delay, None);
+        TimerHandle { id, timer: Arc::downgrade(self) }
+    }
 
-        if let Some(remaining) = min_remaining
-        {
-            return remaining.due_time;
-        }
-        else
-        {   
-            // Nothing valid found, we either have no entries left or
-            // the first entry found is already in the past.
-            // In the first case we want to trigger right away, in the latter
-            // case we'll wait for as long as necessary.
-            if calls.len() != 0
-            {
-                return Instant::now();
-            }
-            else
-            {
-                return Instant::now() + Duration::from_secs(10);
-            }
-        }
+    /// Calls the callback every period ms, starting period ms from
+    /// now, until the timer is cancelled.
+    pub fn schedule_periodic(self: &Arc<Self>, callback: Box<dyn FnMut() + Send>, period: u64) -> TimerHandle
+    {
+        // A period of 0 would keep the timer's thread busy forever
+        let period = Duration::from_millis(period.max(1));
+        let id = self.add(TimerCallback::Periodic(callback, period), period.as_millis() as u64, None);
+        TimerHandle { id, timer: Arc::downgrade(self) }
     }
 
-    fn trigger_active_entries(&self)
+    fn add(&self, callback: TimerCallback, delay: u64, guard: Option<Weak<bool>>) -> u64
     {
-        let mut calls = self.scheduled_calls.lock();
+        let due_time = self.clock.now() + Duration::from_millis(delay);
+        let id = self.queue.lock().insert(TimerEntry { callback: Some(callback), due_time, guard });
+        self.wait_event.trigger();
+        id
+    }
 
-        calls.iter_mut().for_each(|elem|{
-            let now = Instant::now();
-            let due_time = elem.due_time;
-            if now >= due_time
-            {  
-                if elem.guard.upgrade().is_some()
+    /// Calls all callbacks that are due. This is done by the
+    /// timer's thread, but tests using a virtual clock can call
+    /// it right after advancing the clock, so they don't have
+    /// to wait for the thread. Once it returns, all callbacks
+    /// due at the current time were called.
+    pub fn fire_due(&self)
+    {
+        // Callbacks are called without holding the queue, so they
+        // can schedule or cancel timers themselves.
+        let _firing = self.firing.lock().unwrap();
+        loop
+        {
+            let next = self.queue.lock().take_due(self.clock.now());
+            match next
+            {
+                Some((_, TimerCallback::Once(callback))) => callback(),
+                Some((id, TimerCallback::Periodic(mut callback, period))) =>
                 {
-                    let tmp = elem.callback.take();
-                    (tmp.unwrap())();
+                    callback();
+                    self.queue.lock().restore(id, TimerCallback::Periodic(callback, period));
                 }
-                elem.remove = true;
+                None => return
             }
-        });
-
-        // all the entries that have been called have their remove flag
-        // set -> we only keep those, that have not been called yet.
-        calls.retain(|x| !x.remove);
-
+        }
     }
 
-    fn thread_func(&self)
+    fn thread_func(timer: Weak<Timer>, wait_event: Arc<Event>, clock: Arc<dyn Clock>)
     {
         loop {
-            let min_remaining = self.get_min_remaining_timeout();            
-            let due_ticks = min_remaining.checked_duration_since(Instant::now());
-
-            let wait_timeout: u64;
-            if let Some(ticks) = due_ticks
+            let next_due = match timer.upgrade()
             {
-                wait_timeout = ticks.as_millis() as u64;
-            }
-            else
-            {
-                // Nothing valid found, we either have no entries left or
-                // the first entry found is already in the past.
-                wait_timeout = 0;
-            }
+                Some(tm) if !*tm.terminate.lock() => tm.queue.lock().next_due(),
+                _ => return
+            };
+            // With nothing scheduled we'll wait until something is added.
+            let next_due = next_due.unwrap_or(clock.now() + Duration::from_secs(10));
+            let wait_timeout = next_due.saturating_duration_since(clock.now());
 
-            if self.wait_event.wait_with_timeout(wait_timeout)
-            {
-                // event was triggered -> this means someone has either
-                // added a new element to the remaining calls or
-                // the d'tor was called.
-                let term = self.terminate.lock();
-                if *term == true
-                {
-                    return;
-                }
-            }
-            else
+            // If the event was triggered, someone has either added
+            // a new element to the remaining calls or stopped the
+            // timer, which is checked above.
+            if !clock.wait(&wait_event, wait_timeout)
             {
                 // nothing received - we got a timeout, which means we should
                 // attempt to trigger 
-                self.trigger_active_entries();     
+                match timer.upgrade()
+                {
+                    Some(tm) => tm.fire_due(),
+                    None => return
+                }
             }
         }
     }
 }
 
+impl Drop for Timer
+{
+    fn drop(&mut self)
+    {
+        self.stop();
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::Timer;
     use crate::core::shareable::Shareable;
-    use std::{time::Duration, thread::sleep};
+    use crate::core::clock::VirtualClock;
+    use std::{time::Duration, thread::sleep, sync::Arc};
+
+    #[test]
+    fn dropped_timer_is_not_kept_alive_by_its_thread()
+    {
+        let t = Timer::new();
+        let weak = Arc::downgrade(&t);
+        drop(t);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn stopped_timer_does_not_call_callback()
+    {
+        let flag = Shareable::new(false);
+        let t = Timer::new();
+        let c = flag.clone();
+        let _handle = t.schedule_once(Box::new(move || *c.lock() = true), 50);
+        t.stop();
+
+        sleep(Duration::from_millis(100));
+        assert!(!*flag.lock());
+    }
+
+    #[test]
+    fn callback_can_stop_timer()
+    {
+        let flag = Shareable::new(false);
+        let t = Timer::new();
+        let timer = t.clone();
+        let c = flag.clone();
+        t.schedule_once(Box::new(move || { timer.stop(); *c.lock() = true; }), 10);
+
+        sleep(Duration::from_millis(100));
+        assert!(*flag.lock());
+    }
 
     #[test]
     fn can_create_timer()
@@ -198,4 +415,91 @@ mod tests {
         assert!(*flag.lock() == false); 
         t.stop();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn timer_uses_virtual_clock()
+    {
+        let flag = Shareable::new(false);
+        let clock = Arc::new(VirtualClock::new());
+        let t = Timer::with_clock(clock.clone());
+        let movable_clone = flag.clone();
+        let _guard = t.schedule(Box::new(move || {
+            *movable_clone.lock() = true;
+        }), 60000);
+
+        clock.advance(Duration::from_millis(59999));
+        t.fire_due();
+        assert!(*flag.lock() == false);
+
+        clock.advance(Duration::from_millis(1));
+        t.fire_due();
+        assert!(*flag.lock() == true);
+        t.stop();
+    }
+
+    #[test]
+    fn callbacks_are_called_in_order_of_due_time()
+    {
+        let calls = Shareable::new(Vec::new());
+        let clock = Arc::new(VirtualClock::new());
+        let t = Timer::with_clock(clock.clone());
+        for delay in [300, 100, 200].iter()
+        {
+            let c = calls.clone();
+            let d = *delay;
+            t.schedule_once(Box::new(move || c.lock().push(d)), d);
+        }
+
+        clock.advance(Duration::from_millis(300));
+        t.fire_due();
+        assert_eq!(vec![100, 200, 300], *calls.lock());
+        t.stop();
+    }
+
+    #[test]
+    fn periodic_timer_fires_until_cancelled()
+    {
+        let count = Shareable::new(0);
+        let clock = Arc::new(VirtualClock::new());
+        let t = Timer::with_clock(clock.clone());
+        let c = count.clone();
+        let handle = t.schedule_periodic(Box::new(move || *c.lock() += 1), 100);
+
+        for _ in 0..3
+        {
+            clock.advance(Duration::from_millis(100));
+            t.fire_due();
+        }
+        assert_eq!(3, *count.lock());
+
+        assert!(handle.cancel());
+        clock.advance(Duration::from_millis(100));
+        t.fire_due();
+        assert_eq!(3, *count.lock());
+        assert!(!handle.is_pending());
+        t.stop();
+    }
+
+    #[test]
+    fn rescheduled_timer_fires_at_new_time()
+    {
+        let flag = Shareable::new(false);
+        let clock = Arc::new(VirtualClock::new());
+        let t = Timer::with_clock(clock.clone());
+        let c = flag.clone();
+        let handle = t.schedule_once(Box::new(move || *c.lock() = true), 100);
+
+        clock.advance(Duration::from_millis(50));
+        assert!(handle.reschedule(100));
+        clock.advance(Duration::from_millis(50));
+        t.fire_due();
+        assert!(*flag.lock() == false);
+
+        clock.advance(Duration::from_millis(50));
+        t.fire_due();
+        assert!(*flag.lock() == true);
+        assert!(!handle.reschedule(100));
+        assert!(!handle.cancel());
+        t.stop();
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+use super::{TraceLevel, TraceMessage};
+
+/// Changes the trace filter at runtime, e.g.
+/// {"module": "ARM/MFRC522", "level": "Debug"}
+/// * module: None changes the level of all modules
+///   without a level of their own.
+/// * level: None removes the level of the module,
+///   i.e. it uses the default level again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceLevelSetting
+{
+    pub module: Option<String>,
+    pub level: Option<TraceLevel>
+}
+
+/// # Filters trace records by level
+/// A record passes if its level is at least the level configured
+/// for its source module. A level configured for a module also
+/// applies to its submodules, e.g. the level of "SIG" applies
+/// to "SIG/SigCtrl" as well, unless the submodule has a level of
+/// its own.
+pub struct TraceFilter
+{
+    default_level: TraceLevel,
+    module_levels: HashMap<String, TraceLevel>
+}
+
+impl TraceFilter
+{
+    pub fn new(default_level: TraceLevel) -> Self
+    {
+        TraceFilter
+        {
+            default_level,
+            module_levels: HashMap::new()
+        }
+    }
+
+    pub fn apply(&mut self, setting: TraceLevelSetting)
+    {
+        match (setting.module, setting.level)
+        {
+            (None, Some(level)) => self.default_level = level,
+            (None, None) => self.module_levels.clear(),
+            (Some(module), Some(level)) => { self.module_levels.insert(module, level); },
+            (Some(module), None) => { self.module_levels.remove(&module); }
+        }
+    }
+
+    pub fn level_for(&self, module: &str) -> TraceLevel
+    {
+        let mut name = module;
+        loop
+        {
+            if let Some(level) = self.module_levels.get(name)
+            {
+                return *level;
+            }
+            match name.rfind('/')
+            {
+                Some(index) => name = &name[..index],
+                None => return self.default_level
+            }
+        }
+    }
+
+    pub fn passes(&self, message: &TraceMessage) -> bool
+    {
+        message.level >= self.level_for(&message.module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(filter: &mut TraceFilter, module: Option<&str>, level: Option<TraceLevel>)
+    {
+        filter.apply(TraceLevelSetting { module: module.map(|x| x.to_string()), level });
+    }
+
+    #[test]
+    fn module_levels_override_default()
+    {
+        let mut filter = TraceFilter::new(TraceLevel::Info);
+        set(&mut filter, Some("ARM/MFRC522"), Some(TraceLevel::Debug));
+        set(&mut filter, Some("DCM"), Some(TraceLevel::Error));
+
+        assert_eq!(TraceLevel::Debug, filter.level_for("ARM/MFRC522"));
+        assert_eq!(TraceLevel::Info, filter.level_for("ARM/ConsoleInput"));
+        assert_eq!(TraceLevel::Error, filter.level_for("DCM/ADCM"));
+        assert_eq!(TraceLevel::Info, filter.level_for("DCMX"));
+    }
+
+    #[test]
+    fn levels_can_be_reset()
+    {
+        let mut filter = TraceFilter::new(TraceLevel::Info);
+        set(&mut filter, Some("ARM/MFRC522"), Some(TraceLevel::Debug));
+        set(&mut filter, None, Some(TraceLevel::Warning));
+        assert_eq!(TraceLevel::Debug, filter.level_for("ARM/MFRC522"));
+
+        set(&mut filter, Some("ARM/MFRC522"), None);
+        assert_eq!(TraceLevel::Warning, filter.level_for("ARM/MFRC522"));
+    }
+}
@@ -1,68 +1,195 @@
 
 use crate::core::channel_manager::*;
-use crate::core::event::DataEvent;
-use std::{io, thread, self};
-use std::{sync::Arc, io::Write};
+use crate::core::select::Selector;
+use crate::core::module_thread::spawn_module;
+use crate::core::rpc::{Request, Responder};
+use std::fmt;
+use chrono::{DateTime, Local};
+use serde::{Serialize, Deserialize};
 
 use crate::core::SystemMessage;
+use crate::core::bootstage_helper::handle_system_message;
+use crate::core::dependencies::Dependencies;
 
 
 pub mod trace_helper;
+pub mod filter;
+pub mod sinks;
+
+use filter::{TraceFilter, TraceLevelSetting};
+use sinks::{ConsoleSink, RingBufferSink, TraceSink};
 
 const MODULE_ID: u32 = 0x02000000;
 
-#[derive(Clone)]
-pub struct TraceMessage
+/// Number of records kept by the ring buffer of launch()
+pub const DEFAULT_BUFFER_SIZE: usize = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TraceLevel
 {
-    msg: String
+    Debug,
+    Info,
+    Warning,
+    Error
 }
 
-impl TraceMessage
+impl fmt::Display for TraceLevel
 {
-    pub fn new(msg: String) -> Self
-    {
-        TraceMessage {
-            msg: msg
-        }
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self
+        {
+            TraceLevel::Debug => "DEBUG",
+            TraceLevel::Info => "INFO",
+            TraceLevel::Warning => "WARN",
+            TraceLevel::Error => "ERROR"
+        };
+        // Pads the name, if requested
+        f.pad(name)
     }
+}
 
-    #[allow(dead_code)]
-    pub fn from_str(msg: &str) -> Self
-    {
-        TraceMessage {
-            msg: msg.to_string()
+/// # A single trace record
+/// Created by the TraceHelper of a module, see there.
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceMessage
+{
+    pub level: TraceLevel,
+    pub module: String,                 // Name of the source module, e.g. "ARM/MFRC522"
+    pub sud: u32,                       // Module ID of the source module, 0 if unknown
+    pub timestamp: DateTime<Local>,
+    pub msg: String,
+    pub fields: Vec<(String, String)>   // Key/value pairs, in the order they were added
+}
+
+impl fmt::Display for TraceMessage
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<5} {}: {}", self.timestamp.format("%H:%M:%S%.3f"), self.level, self.module, self.msg)?;
+        for (key, value) in self.fields.iter()
+        {
+            write!(f, " {}={}", key, value)?;
         }
+        Ok(())
     }
 }
 
+/// Requests the records kept by the sinks of SYS/Trace, e.g.
+/// the ring buffer. Answered with the records, oldest first:
+/// Requester::<TraceQuery, Vec<TraceMessage>>::new(chm)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceQuery;
+
+enum Work
+{
+    System(SystemMessage),
+    Level(TraceLevelSetting),
+    Query(Request<TraceQuery, Vec<TraceMessage>>),
+    Trace(TraceMessage)
+}
+
+/// Starts the trace module, which prints all trace records that
+/// pass the filter to stdout and keeps the last DEFAULT_BUFFER_SIZE
+/// of them in a ring buffer.
 pub fn launch(chm: &mut ChannelManager)
-{    
+{
+    let sinks: Vec<Box<dyn TraceSink>> = vec![Box::new(ConsoleSink), Box::new(RingBufferSink::new(DEFAULT_BUFFER_SIZE))];
+    launch_with_sinks(chm, sinks);
+}
+
+/// Starts the trace module, which passes all trace records that
+/// pass the filter to the given sinks. The filter passes records
+/// of level Info and above by default and can be changed at runtime
+/// by sending a TraceLevelSetting. As launch! may start modules more
+/// than once, the sinks are best created in a closure:
+/// ```ignore
+/// launch!(|chm: &mut ChannelManager| trace::launch_with_sinks(chm, vec![
+///             Box::new(ConsoleSink),
+///             Box::new(FileSink::new("trace.log".to_string(), 1024 * 1024, 4))]),
+///         ...);
+/// ```
+pub fn launch_with_sinks(chm: &mut ChannelManager, mut sinks: Vec<Box<dyn TraceSink>>)
+{
     let trace_rx = chm.get_receiver::<TraceMessage>();
+    let level_rx = chm.get_receiver::<TraceLevelSetting>();
+    let query_rx = Responder::<TraceQuery, Vec<TraceMessage>>::new(chm);
     let sys_rx= chm.get_receiver::<crate::core::SystemMessage>();
     let sys_tx= chm.get_sender::<crate::core::SystemMessage>();
+    sys_tx.send(SystemMessage::Register(MODULE_ID, "SYS/Trace".to_string(), Dependencies::none()));
     sys_tx.send(SystemMessage::StageComplete(crate::core::BootStage::Sync, MODULE_ID));
     println!("Trace active");
 
-    let _ = thread::Builder::new().name("Trace".to_string()).spawn(move || {
+    spawn_module(MODULE_ID, "Trace", chm, move || {
+        let mut selector = Selector::new("Trace");
+        let mut filter = TraceFilter::new(TraceLevel::Info);
         loop
         {
-            let queue = select_chan!(sys_rx, trace_rx);
-            if queue == 1
+            let work = selector.select()
+                               .recv(&sys_rx, Work::System)
+                               .recv(&level_rx, Work::Level)
+                               .recv(&trace_rx, Work::Trace)
+                               .recv(&query_rx, Work::Query)
+                               .wait();
+            let keep_running = match work
             {
-                let message = trace_rx.receive();
-                println!("{}", message.msg);
-                let _ = io::stdout().flush();
-            }
-            if queue == 0
-            {
-                let msg = sys_rx.receive();
-                if let SystemMessage::RunStage(x) = msg
+                Work::System(msg) => match msg
                 {
-                    
-                    println!("Ran bootstage {}", x as u32);
-                    sys_tx.send(SystemMessage::StageComplete(x, MODULE_ID));
+                    SystemMessage::RunStage(x) | SystemMessage::RunStageFor(x, MODULE_ID) => {
+                        println!("Ran bootstage {}", x as u32);
+                        sys_tx.send(SystemMessage::StageComplete(x, MODULE_ID));
+                        true
+                    },
+                    SystemMessage::RunStageFor(_, _) => true,
+                    _ => handle_system_message(MODULE_ID, msg, &sys_tx)
+                },
+                Work::Level(setting) => {
+                    filter.apply(setting);
+                    true
+                },
+                Work::Query(request) => {
+                    let records = sinks.iter().filter_map(|x| x.records()).flatten().collect();
+                    request.reply(records);
+                    true
+                },
+                Work::Trace(message) => {
+                    if filter.passes(&message)
+                    {
+                        for sink in sinks.iter_mut()
+                        {
+                            sink.write(&message);
+                        }
+                    }
+                    true
                 }
-            }        
+            };
+
+            if !keep_running
+            {
+                break;
+            }
         }
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::rpc::Requester;
+    use crate::trace::trace_helper::TraceHelper;
+    use std::thread;
+
+    #[test]
+    fn buffered_records_can_be_queried()
+    {
+        let mut chm = ChannelManager::new();
+        let requester = Requester::<TraceQuery, Vec<TraceMessage>>::new(&mut chm);
+        let tracer = TraceHelper::new("ARM/MFRC522".to_string(), &mut chm);
+        launch_with_sinks(&mut chm, vec![Box::new(RingBufferSink::new(10))]);
+
+        tracer.debug("Filtered".to_string());
+        tracer.trace_str("Kept");
+        // Records and queries are served round robin
+        thread::sleep(std::time::Duration::from_millis(50));
+        let records = requester.request(TraceQuery, 1000).unwrap();
+        assert_eq!(vec!["Kept"], records.iter().map(|x| x.msg.as_str()).collect::<Vec<_>>());
+    }
+}
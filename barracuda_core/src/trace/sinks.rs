@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::util::rotating_file::RotatingFile;
+use super::{TraceLevel, TraceMessage};
+
+/// # Destination of trace records
+/// SYS/Trace passes each record that passes its filter to
+/// all of its sinks, see launch_with_sinks. Sinks must not
+/// block for long, as they delay all following records.
+pub trait TraceSink: Send
+{
+    fn write(&mut self, message: &TraceMessage);
+
+    /// Yields the records kept by the sink, if it keeps any.
+    /// These are served to TraceQuery requests.
+    fn records(&self) -> Option<Vec<TraceMessage>>
+    {
+        None
+    }
+}
+
+/// Prints records to stdout.
+pub struct ConsoleSink;
+
+impl TraceSink for ConsoleSink
+{
+    fn write(&mut self, message: &TraceMessage)
+    {
+        println!("{}", message);
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Writes records to a size limited, rotating set of files,
+/// see RotatingFile. Records that cannot be written are dropped.
+pub struct FileSink
+{
+    file: RotatingFile
+}
+
+impl FileSink
+{
+    pub fn new(file_name: String, max_file_size: u64, max_files: u32) -> Self
+    {
+        FileSink
+        {
+            file: RotatingFile::new(file_name, max_file_size, max_files)
+        }
+    }
+}
+
+impl TraceSink for FileSink
+{
+    fn write(&mut self, message: &TraceMessage)
+    {
+        let _ = self.file.write_line(&format!("{}\n", message));
+    }
+}
+
+/// Keeps the last capacity records in memory.
+pub struct RingBufferSink
+{
+    capacity: usize,
+    records: VecDeque<TraceMessage>
+}
+
+impl RingBufferSink
+{
+    pub fn new(capacity: usize) -> Self
+    {
+        RingBufferSink
+        {
+            capacity,
+            records: VecDeque::with_capacity(capacity)
+        }
+    }
+}
+
+impl TraceSink for RingBufferSink
+{
+    fn write(&mut self, message: &TraceMessage)
+    {
+        if self.capacity == 0
+        {
+            return;
+        }
+        if self.records.len() == self.capacity
+        {
+            self.records.pop_front();
+        }
+        self.records.push_back(message.clone());
+    }
+
+    fn records(&self) -> Option<Vec<TraceMessage>>
+    {
+        Some(self.records.iter().cloned().collect())
+    }
+}
+
+#[cfg(unix)]
+pub use syslog::SyslogSink;
+
+#[cfg(unix)]
+mod syslog
+{
+    use std::os::unix::net::UnixDatagram;
+    use super::*;
+
+    pub const DEFAULT_SOCKET: &str = "/dev/log";
+    const FACILITY_DAEMON: u8 = 3;
+
+    /// # Forwards records to the local syslog daemon
+    /// Records are sent in the BSD syslog format (RFC 3164) with
+    /// facility daemon. If the syslog socket is not available
+    /// records are dropped, connecting is retried with each record.
+    pub struct SyslogSink
+    {
+        socket_path: String,
+        tag: String,
+        socket: Option<UnixDatagram>
+    }
+
+    impl SyslogSink
+    {
+        /// Creates a sink that sends to /dev/log, tag is the
+        /// name the records are logged with, e.g. "barracuda".
+        pub fn new(tag: &str) -> Self
+        {
+            SyslogSink::with_socket(DEFAULT_SOCKET, tag)
+        }
+
+        pub fn with_socket(socket_path: &str, tag: &str) -> Self
+        {
+            SyslogSink
+            {
+                socket_path: socket_path.to_string(),
+                tag: tag.to_string(),
+                socket: None
+            }
+        }
+
+        fn severity(level: TraceLevel) -> u8
+        {
+            match level
+            {
+                TraceLevel::Error => 3,
+                TraceLevel::Warning => 4,
+                TraceLevel::Info => 6,
+                TraceLevel::Debug => 7
+            }
+        }
+
+        fn to_datagram(&self, message: &TraceMessage) -> String
+        {
+            let mut datagram = format!("<{}>{} {}: {}: {}",
+                                       FACILITY_DAEMON * 8 + SyslogSink::severity(message.level),
+                                       message.timestamp.format("%b %e %H:%M:%S"),
+                                       self.tag,
+                                       message.module,
+                                       message.msg);
+            for (key, value) in message.fields.iter()
+            {
+                datagram.push_str(&format!(" {}={}", key, value));
+            }
+            datagram
+        }
+    }
+
+    impl TraceSink for SyslogSink
+    {
+        fn write(&mut self, message: &TraceMessage)
+        {
+            if self.socket.is_none()
+            {
+                self.socket = UnixDatagram::unbound().ok()
+                                                     .filter(|x| x.connect(&self.socket_path).is_ok());
+            }
+
+            let datagram = self.to_datagram(message);
+            if let Some(socket) = self.socket.as_ref()
+            {
+                if socket.send(datagram.as_bytes()).is_err()
+                {
+                    // The daemon might have been restarted
+                    self.socket = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use crate::util::temp_dir::TempDir;
+
+    fn message(level: TraceLevel, msg: &str) -> TraceMessage
+    {
+        TraceMessage
+        {
+            level,
+            module: "ARM/MFRC522".to_string(),
+            sud: 0x0B000000,
+            timestamp: Local::now(),
+            msg: msg.to_string(),
+            fields: vec![("uid".to_string(), "01020304".to_string())]
+        }
+    }
+
+    #[test]
+    fn ring_buffer_keeps_last_records()
+    {
+        let mut sink = RingBufferSink::new(2);
+        for msg in ["first", "second", "third"].iter()
+        {
+            sink.write(&message(TraceLevel::Info, msg));
+        }
+
+        let kept: Vec<String> = sink.records().unwrap().into_iter().map(|x| x.msg).collect();
+        assert_eq!(vec!["second", "third"], kept);
+    }
+
+    #[test]
+    fn file_sink_writes_one_line_per_record()
+    {
+        let dir = TempDir::new("trace_file");
+        let file_name = dir.file("trace.log");
+        let mut sink = FileSink::new(file_name.clone(), 1024, 1);
+        let record = message(TraceLevel::Warning, "Antenna not found");
+        sink.write(&record);
+
+        assert_eq!(format!("{}\n", record), std::fs::read_to_string(&file_name).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syslog_sink_sends_priority_and_tag()
+    {
+        let dir = TempDir::new("trace_syslog");
+        let socket_path = dir.file("log");
+        let daemon = std::os::unix::net::UnixDatagram::bind(&socket_path).unwrap();
+        let mut sink = SyslogSink::with_socket(&socket_path, "barracuda");
+        sink.write(&message(TraceLevel::Warning, "Antenna not found"));
+
+        let mut buffer = [0u8; 256];
+        let len = daemon.recv(&mut buffer).unwrap();
+        let datagram = String::from_utf8_lossy(&buffer[..len]).to_string();
+        assert!(datagram.starts_with("<28>"));
+        assert!(datagram.ends_with(" barracuda: ARM/MFRC522: Antenna not found uid=01020304"));
+    }
+}
@@ -1,9 +1,21 @@
+use std::sync::Arc;
+use crate::core::{clock::Clock, sud::Sud};
+use crate::trace::{TraceLevel, TraceMessage};
 
-use crate::trace::TraceMessage;
-
+/// # Creates the trace records of a module
+/// trace() and trace_str() create records of level Info,
+/// records of other levels and records with key/value
+/// fields are created using record():
+/// ```ignore
+/// tracer.record(TraceLevel::Debug, "Found new transponder")
+///       .field("uid", format!("{:?}", uid))
+///       .send();
+/// ```
 pub struct TraceHelper
 {
     source_mod: String,
+    sud: u32,
+    clock: Arc<dyn Clock>,
     trace_tx: crate::core::broadcast_channel::GenericSender<TraceMessage>
 }
 
@@ -14,14 +26,27 @@ impl TraceHelper
         TraceHelper
         {
             source_mod: module_name,
+            sud: 0,
+            clock: channel_manager.clock(),
             trace_tx: channel_manager.get_sender()
         }
     }
 
+    /// Sets the module ID stored in all records of this helper.
+    pub fn with_sud(mut self, sud: Sud) -> Self
+    {
+        self.sud = sud.into();
+        self
+    }
+
+    pub fn module_name(&self) -> &str
+    {
+        &self.source_mod
+    }
+
     pub fn trace(&self, message: String)
     {
-        let final_message = format!("{}: {}", self.source_mod, message);        
-        self.trace_tx.send(TraceMessage::new(final_message));
+        self.record(TraceLevel::Info, message).send();
     }
 
     pub fn trace_str(&self, message: &str)
@@ -29,4 +54,80 @@ impl TraceHelper
         let msgstring = String::from(message);
         self.trace(msgstring);
     }
-}
\ No newline at end of file
+
+    pub fn debug(&self, message: String)
+    {
+        self.record(TraceLevel::Debug, message).send();
+    }
+
+    pub fn warning(&self, message: String)
+    {
+        self.record(TraceLevel::Warning, message).send();
+    }
+
+    pub fn error(&self, message: String)
+    {
+        self.record(TraceLevel::Error, message).send();
+    }
+
+    pub fn record<S: Into<String>>(&self, level: TraceLevel, message: S) -> TraceRecord<'_>
+    {
+        TraceRecord
+        {
+            helper: self,
+            message: TraceMessage
+            {
+                level,
+                module: self.source_mod.clone(),
+                sud: self.sud,
+                timestamp: self.clock.local_time(),
+                msg: message.into(),
+                fields: Vec::new()
+            }
+        }
+    }
+}
+
+/// A trace record that is being built, see TraceHelper::record.
+pub struct TraceRecord<'a>
+{
+    helper: &'a TraceHelper,
+    message: TraceMessage
+}
+
+impl <'a> TraceRecord<'a>
+{
+    pub fn field<K: Into<String>, V: ToString>(mut self, key: K, value: V) -> Self
+    {
+        self.message.fields.push((key.into(), value.to_string()));
+        self
+    }
+
+    pub fn send(self)
+    {
+        self.helper.trace_tx.send(self.message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::channel_manager::ChannelManager;
+
+    #[test]
+    fn records_carry_source_and_fields()
+    {
+        let mut chm = ChannelManager::new();
+        let trace_rx = chm.get_receiver::<TraceMessage>();
+        let tracer = TraceHelper::new("ARM/MFRC522".to_string(), &mut chm).with_sud(Sud::from(0x0B000000));
+
+        tracer.record(TraceLevel::Debug, "Found new transponder").field("uid", "01020304").send();
+        tracer.trace_str("Starting");
+
+        let record = trace_rx.receive();
+        assert_eq!((TraceLevel::Debug, "ARM/MFRC522", 0x0B000000), (record.level, record.module.as_str(), record.sud));
+        assert_eq!(vec![("uid".to_string(), "01020304".to_string())], record.fields);
+        assert!(record.to_string().ends_with("DEBUG ARM/MFRC522: Found new transponder uid=01020304"));
+        assert_eq!(TraceLevel::Info, trace_rx.receive().level);
+    }
+}
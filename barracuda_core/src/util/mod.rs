@@ -4,6 +4,8 @@ use std::fs::File;
 use std::{slice::Iter};
 
 pub mod datetime;
+pub mod rotating_file;
+pub mod temp_dir;
 
 pub trait ObjectStorage<T>
 {
@@ -0,0 +1,92 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// # A size limited, rotating set of files
+/// Once the current file would exceed max_file_size it is
+/// renamed to <file_name>.1, the previous <file_name>.1
+/// becomes <file_name>.2 and so on. Only max_files rotated
+/// files are kept. A file that exists when the first line
+/// is written is rotated as well, so each file contains the
+/// output of a single run.
+pub struct RotatingFile
+{
+    file_name: String,
+    max_file_size: u64,
+    max_files: u32,
+    file: Option<File>,
+    size: u64
+}
+
+impl RotatingFile
+{
+    pub fn new(file_name: String, max_file_size: u64, max_files: u32) -> Self
+    {
+        RotatingFile
+        {
+            file_name,
+            max_file_size,
+            max_files,
+            file: None,
+            size: 0
+        }
+    }
+
+    /// Writes the line as is, i.e. the caller has to
+    /// terminate it. A line is never split across files.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()>
+    {
+        if self.file.is_none()
+        {
+            self.open()?;
+        }
+        else if self.size > 0 && self.size + line.len() as u64 > self.max_file_size
+        {
+            self.rotate()?;
+        }
+
+        if let Some(file) = self.file.as_mut()
+        {
+            file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> io::Result<()>
+    {
+        let existing_size = fs::metadata(&self.file_name).map(|x| x.len()).unwrap_or(0);
+        if existing_size > 0
+        {
+            return self.rotate();
+        }
+        self.file = Some(OpenOptions::new().create(true).append(true).open(&self.file_name)?);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()>
+    {
+        self.file = None;
+        if self.max_files > 0
+        {
+            for index in (1..self.max_files).rev()
+            {
+                let older = self.rotated_name(index);
+                if Path::new(&older).exists()
+                {
+                    fs::rename(&older, self.rotated_name(index + 1))?;
+                }
+            }
+            fs::rename(&self.file_name, self.rotated_name(1))?;
+        }
+        self.file = Some(File::create(&self.file_name)?);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_name(&self, index: u32) -> String
+    {
+        format!("{}.{}", self.file_name, index)
+    }
+}
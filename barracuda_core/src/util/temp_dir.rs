@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// # A directory that is removed when dropped
+/// Created below the system's temp directory, the name contains
+/// the process id and a counter, so tests running in parallel
+/// never share a directory. Used by tests that write files,
+/// e.g. logs, so no files are left behind.
+pub struct TempDir
+{
+    path: PathBuf
+}
+
+impl TempDir
+{
+    pub fn new(name: &str) -> Self
+    {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("barracuda_{}_{}_{}", name, std::process::id(), id));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    /// Yields the name of a file in this directory.
+    pub fn file(&self, name: &str) -> String
+    {
+        self.path.join(name).to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TempDir
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_is_removed_when_dropped()
+    {
+        let dir = TempDir::new("temp_dir");
+        fs::write(dir.file("test.txt"), "4711").unwrap();
+        let path = dir.path().to_path_buf();
+        assert!(path.join("test.txt").exists());
+
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
@@ -14,6 +14,11 @@ impl DoorHandle
 
 impl InputComponent for DoorHandle
 {
+    fn input_id(&self) -> u32
+    {
+        self.id
+    }
+
     fn on_input_change(&mut self, event: &InputEvent, generated_events: &mut Vec<DoorEvent>) {
 
         if event.input_id != self.id 
@@ -13,6 +13,11 @@ impl DoorOpenerKey
 
 impl InputComponent for DoorOpenerKey
 {
+    fn input_id(&self) -> u32
+    {
+        self.id
+    }
+
     fn on_input_change(&mut self, event: &InputEvent, generated_events: &mut Vec<DoorEvent>) {
 
         if event.input_id != self.id 
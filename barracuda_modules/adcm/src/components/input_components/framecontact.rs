@@ -14,6 +14,11 @@ pub struct FrameContact
 
 impl InputComponent for FrameContact
 {
+    fn input_id(&self) -> u32
+    {
+        self.id
+    }
+
     fn on_input_change(&mut self, event: &InputEvent, generated_events: &mut Vec<DoorEvent>) {
 
         if event.input_id != self.id 
@@ -13,6 +13,11 @@ impl ReleaseContact
 
 impl InputComponent for ReleaseContact
 {
+    fn input_id(&self) -> u32
+    {
+        self.id
+    }
+
     fn on_input_change(&mut self, event: &InputEvent, generated_events: &mut Vec<DoorEvent>) {
 
         if event.input_id != self.id 
@@ -10,6 +10,7 @@ pub mod serialization_types;
 
 pub trait InputComponent: Send
 {
+    fn input_id(&self) -> u32;
     fn on_input_change(&mut self, event: &InputEvent, generated_events: &mut Vec<DoorEvent>);    
 }
 
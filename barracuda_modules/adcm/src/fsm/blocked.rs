@@ -4,25 +4,123 @@ use crate::{DoorCommand, DoorEvent};
 use super::{DoorStateContainer, DoorStateImpl, emergency::Emergency, normal_operation::NormalOperation};
 
 
+/// The door stays blocked as long as one of the causes of the
+/// block persists, i.e. the blocking contact is engaged or a
+/// Block command was not revoked by a NormalOperation command.
 #[derive(Copy, Clone)]
-pub struct Blocked{}
+pub struct Blocked
+{
+    contact_engaged : bool,
+    requested       : bool
+}
+
+impl Blocked
+{
+    /// Yields the state entered by the given event, which is
+    /// either BlockingContactEngaged or BlockRequested.
+    pub fn caused_by(d: &DoorEvent) -> Self
+    {
+        Blocked
+        {
+            contact_engaged : *d == DoorEvent::BlockingContactEngaged,
+            requested       : *d == DoorEvent::BlockRequested
+        }
+    }
+}
 
 impl DoorStateImpl for Blocked
 {
-    fn dispatch_door_event(self, passageway_id: u32, d: DoorEvent, commands: &mut Vec<DoorCommand>) -> DoorStateContainer {
+    fn dispatch_door_event(mut self, passageway_id: u32, d: DoorEvent, commands: &mut Vec<DoorCommand>) -> DoorStateContainer {
          match d
          {
              DoorEvent::ValidDoorOpenRequestSeen(ap_id, token) => {
                  commands.push(DoorCommand::ShowSignal(ap_id, barracuda_base_modules::sig::SigType::AccessDenied));
                  commands.push(DoorCommand::TriggerEvent(LogEvent::AccessDeniedDoorBlocked(passageway_id, token, ap_id)));
                 }
-             DoorEvent::BlockingContactDisengaged => {
-                commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(passageway_id)));
-                 return DoorStateContainer::NormalOp(NormalOperation{}, passageway_id);
-                }
+             DoorEvent::BlockingContactEngaged => { self.contact_engaged = true; }
+             DoorEvent::BlockingContactDisengaged => { self.contact_engaged = false; }
+             DoorEvent::BlockRequested => { self.requested = true; }
+             DoorEvent::NormalOperationRequested => { self.requested = false; }
              DoorEvent::ReleaseSwitchEngaged => {return DoorStateContainer::Emergency(Emergency{}, passageway_id);}
              _ => {}
          }
+         if !self.contact_engaged && !self.requested
+         {
+             commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(passageway_id)));
+             return DoorStateContainer::NormalOp(NormalOperation{}, passageway_id);
+         }
          return DoorStateContainer::Blocked(self, passageway_id)
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod blocked_tests
+{
+    use super::*;
+
+    fn assert_states_are_equal(s1: DoorStateContainer, s2: DoorStateContainer)
+    {
+        assert_eq!(std::mem::discriminant(&s1), std::mem::discriminant(&s2));
+    }
+
+    fn dispatch(state: DoorStateContainer, d: DoorEvent) -> DoorStateContainer
+    {
+        let mut v = Vec::<DoorCommand>::new();
+        match state
+        {
+            DoorStateContainer::Blocked(op, id) => op.dispatch_door_event(id, d, &mut v),
+            other => other
+        }
+    }
+
+    fn blocked_by(d: DoorEvent) -> DoorStateContainer
+    {
+        DoorStateContainer::Blocked(Blocked::caused_by(&d), 0)
+    }
+
+    #[test]
+    pub fn normal_operation_request_lifts_block_by_command()
+    {
+        let mut v = Vec::<DoorCommand>::new();
+        let next = Blocked::caused_by(&DoorEvent::BlockRequested).dispatch_door_event(0, DoorEvent::NormalOperationRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0));
+        assert_eq!(v[0], DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(0)));
+    }
+
+    #[test]
+    pub fn normal_operation_request_does_not_lift_block_by_contact()
+    {
+        let next = dispatch(blocked_by(DoorEvent::BlockingContactEngaged), DoorEvent::NormalOperationRequested);
+        assert_states_are_equal(next, blocked_by(DoorEvent::BlockingContactEngaged));
+        let next = dispatch(next, DoorEvent::BlockingContactDisengaged);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0));
+    }
+
+    #[test]
+    pub fn disengaged_contact_does_not_lift_block_by_command()
+    {
+        let next = dispatch(blocked_by(DoorEvent::BlockRequested), DoorEvent::BlockingContactDisengaged);
+        assert_states_are_equal(next, blocked_by(DoorEvent::BlockRequested));
+    }
+
+    #[test]
+    pub fn block_lasts_while_any_cause_persists()
+    {
+        let next = dispatch(blocked_by(DoorEvent::BlockRequested), DoorEvent::BlockingContactEngaged);
+        let next = dispatch(next, DoorEvent::NormalOperationRequested);
+        assert_states_are_equal(next, blocked_by(DoorEvent::BlockRequested));
+        let next = dispatch(next, DoorEvent::BlockingContactDisengaged);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0));
+    }
+
+    #[test]
+    pub fn block_request_while_blocked_by_contact_outlasts_the_contact()
+    {
+        let next = dispatch(blocked_by(DoorEvent::BlockingContactEngaged), DoorEvent::BlockRequested);
+        let next = dispatch(next, DoorEvent::BlockingContactDisengaged);
+        assert_states_are_equal(next, blocked_by(DoorEvent::BlockRequested));
+        let next = dispatch(next, DoorEvent::NormalOperationRequested);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0));
+    }
+}
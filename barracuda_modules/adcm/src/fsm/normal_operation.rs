@@ -33,7 +33,7 @@ impl DoorStateImpl for NormalOperation
                                     commands.push(DoorCommand::ToggleAlarmRelay(OutputState::Low));
                                     commands.push(DoorCommand::TriggerEvent(LogEvent::DoorClosedAgain(passageway_id))); 
                                 }
-            DoorEvent::DoorOpenProfileActive => {
+            DoorEvent::DoorOpenProfileActive | DoorEvent::PermanentReleaseRequested => {
                                     commands.push(DoorCommand::ToggleElectricStrikeTimed(OutputState::High));
                                     commands.push(DoorCommand::ToggleAccessAllowed(OutputState::High));      
                                     commands.push(DoorCommand::TriggerEvent(LogEvent::DoorPermantlyReleased(passageway_id)));
@@ -51,10 +51,17 @@ impl DoorStateImpl for NormalOperation
                                 commands.push(DoorCommand::ToggleAccessAllowed(OutputState::High));
                                 return DoorStateContainer::ReleasedOnce(ReleasedOnce{}, passageway_id);
             }
+            DoorEvent::ReleaseOnceRequested => {
+                                commands.push(DoorCommand::ToggleElectricStrikeTimed(OutputState::High));
+                                commands.push(DoorCommand::ToggleAccessAllowed(OutputState::High));
+                                commands.push(DoorCommand::ArmAutoswitchToNormal);
+                                commands.push(DoorCommand::TriggerEvent(LogEvent::DoorReleasedOnce(passageway_id)));
+                                return DoorStateContainer::ReleasedOnce(ReleasedOnce{}, passageway_id);
+            }
 
-            DoorEvent::BlockingContactEngaged => { 
+            DoorEvent::BlockingContactEngaged | DoorEvent::BlockRequested => { 
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorBlocked(passageway_id)));
-                return DoorStateContainer::Blocked(Blocked{}, passageway_id); 
+                return DoorStateContainer::Blocked(Blocked::caused_by(&d), passageway_id); 
             }            
             DoorEvent::ReleaseSwitchEngaged => { 
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEmergencyReleased(passageway_id)));
@@ -66,6 +73,7 @@ impl DoorStateImpl for NormalOperation
             DoorEvent::DoorTimerExpired => {}
             DoorEvent::BlockingContactDisengaged => { }
             DoorEvent::ReleaseSwitchDisengaged => { }
+            DoorEvent::NormalOperationRequested => { }
         }
         return DoorStateContainer::NormalOp(self, passageway_id)
     }
@@ -93,62 +101,66 @@ mod normal_op_tests
     pub fn normal_op_generates_release_cmd_on_valid_booking()
     {
         let (op, mut v) = make_normal_op();
-        op.dispatch_door_event(DoorEvent::ValidDoorOpenRequestSeen(0), &mut v);
-        assert_eq!(v.len() , 4);
+        op.dispatch_door_event(0, DoorEvent::ValidDoorOpenRequestSeen(0, vec![]), &mut v);
+        assert_eq!(v.len() , 6);
         assert_eq!(v[0], DoorCommand::ToggleElectricStrikeTimed(OutputState::High));
         assert_eq!(v[1], DoorCommand::ToggleAccessAllowed(OutputState::High));
         assert_eq!(v[2], DoorCommand::ArmAutoswitchToNormal);
         assert_eq!(v[3], DoorCommand::ShowSignal(0, SigType::AccessGranted));
+        assert_eq!(v[4], DoorCommand::TriggerEvent(LogEvent::AccessGranted(0, vec![], 0)));
+        assert_eq!(v[5], DoorCommand::TriggerEvent(LogEvent::DoorReleasedOnce(0)));
     }
 
     #[test]
     pub fn normal_op_changes_to_released_once_on_valid_booking()
     {
         let (op, mut v) = make_normal_op();
-        let next = op.dispatch_door_event(DoorEvent::ValidDoorOpenRequestSeen(0), &mut v);
-        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}))
+        let next = op.dispatch_door_event(0, DoorEvent::ValidDoorOpenRequestSeen(0, vec![]), &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}, 0))
     }
 
     #[test]
     pub fn normal_op_generates_release_cmd_on_door_opener_key()
     {
         let (op, mut v) = make_normal_op();
-        op.dispatch_door_event(DoorEvent::DoorOpenerKeyTriggered, &mut v);
-        assert_eq!(v.len() , 2);
+        op.dispatch_door_event(0, DoorEvent::DoorOpenerKeyTriggered, &mut v);
+        assert_eq!(v.len() , 3);
         assert_eq!(v[0], DoorCommand::ToggleElectricStrikeTimed(OutputState::High));
         assert_eq!(v[1], DoorCommand::ToggleAccessAllowed(OutputState::High));
+        assert_eq!(v[2], DoorCommand::TriggerEvent(LogEvent::DoorReleasedOnce(0)));
     }
 
     #[test]
     pub fn normal_op_changes_to_released_once_on_door_opener_key()
     {
         let (op, mut v) = make_normal_op();
-        let next = op.dispatch_door_event(DoorEvent::DoorOpenerKeyTriggered, &mut v);
-        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}))
+        let next = op.dispatch_door_event(0, DoorEvent::DoorOpenerKeyTriggered, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}, 0))
     }
 
     #[test]
     pub fn normal_op_generates_access_allowed_on_door_handle()
     {
         let (op, mut v) = make_normal_op();
-        op.dispatch_door_event(DoorEvent::DoorHandleTriggered, &mut v);
-        assert_eq!(v.len() , 1);
-        assert_eq!(v[0], DoorCommand::ToggleAccessAllowed(OutputState::High));
+        op.dispatch_door_event(0, DoorEvent::DoorHandleTriggered, &mut v);
+        assert_eq!(v.len() , 2);
+        assert_eq!(v[0], DoorCommand::TriggerEvent(LogEvent::DoorReleasedOnce(0)));
+        assert_eq!(v[1], DoorCommand::ToggleAccessAllowed(OutputState::High));
     }
 
     #[test]
     pub fn normal_op_changes_to_released_once_on_door_handle()
     {
         let (op, mut v) = make_normal_op();
-        let next = op.dispatch_door_event(DoorEvent::DoorHandleTriggered, &mut v);
-        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}))
+        let next = op.dispatch_door_event(0, DoorEvent::DoorHandleTriggered, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}, 0))
     }
 
     #[test]
     pub fn normal_op_fires_alarm_on_door_open()
     {
         let (op, mut v) = make_normal_op();
-        op.dispatch_door_event(DoorEvent::Opened, &mut v);
+        op.dispatch_door_event(0, DoorEvent::Opened, &mut v);
         assert_eq!(v[0], DoorCommand::ToggleAlarmRelay(OutputState::High));
     }
 
@@ -156,8 +168,47 @@ mod normal_op_tests
     pub fn normal_op_disengages_alarm_on_door_open()
     {
         let (op, mut v) = make_normal_op();
-        op.dispatch_door_event(DoorEvent::Closed, &mut v);
+        op.dispatch_door_event(0, DoorEvent::Closed, &mut v);
         assert_eq!(v[0], DoorCommand::ToggleAlarmRelay(OutputState::Low));
     }
 
+    #[test]
+    pub fn normal_op_changes_to_released_once_on_release_once_request()
+    {
+        let (op, mut v) = make_normal_op();
+        let next = op.dispatch_door_event(0, DoorEvent::ReleaseOnceRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}, 0));
+        assert_eq!(v.len() , 4);
+        assert_eq!(v[0], DoorCommand::ToggleElectricStrikeTimed(OutputState::High));
+        assert_eq!(v[1], DoorCommand::ToggleAccessAllowed(OutputState::High));
+        assert_eq!(v[2], DoorCommand::ArmAutoswitchToNormal);
+        assert_eq!(v[3], DoorCommand::TriggerEvent(LogEvent::DoorReleasedOnce(0)));
+    }
+
+    #[test]
+    pub fn normal_op_changes_to_released_permanently_on_permanent_release_request()
+    {
+        let (op, mut v) = make_normal_op();
+        let next = op.dispatch_door_event(0, DoorEvent::PermanentReleaseRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasePerm(ReleasedPermanently{}, 0));
+        assert_eq!(v[2], DoorCommand::TriggerEvent(LogEvent::DoorPermantlyReleased(0)));
+    }
+
+    #[test]
+    pub fn normal_op_changes_to_blocked_on_block_request()
+    {
+        let (op, mut v) = make_normal_op();
+        let next = op.dispatch_door_event(0, DoorEvent::BlockRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::Blocked(Blocked::caused_by(&DoorEvent::BlockRequested), 0));
+        assert_eq!(v[0], DoorCommand::TriggerEvent(LogEvent::DoorBlocked(0)));
+    }
+
+    #[test]
+    pub fn normal_op_ignores_normal_operation_request()
+    {
+        let (op, mut v) = make_normal_op();
+        let next = op.dispatch_door_event(0, DoorEvent::NormalOperationRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0));
+        assert!(v.is_empty());
+    }
 }
\ No newline at end of file
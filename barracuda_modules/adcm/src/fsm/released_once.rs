@@ -30,7 +30,7 @@ impl DoorStateImpl for ReleasedOnce
                     commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(passageway_id)));
                     return DoorStateContainer::NormalOp(NormalOperation{}, passageway_id);
                 }
-            DoorEvent::DoorOpenProfileActive => { 
+            DoorEvent::DoorOpenProfileActive | DoorEvent::PermanentReleaseRequested => { 
                     commands.push(DoorCommand::ToggleElectricStrike(OutputState::High));
                     commands.push(DoorCommand::ToggleAccessAllowed(OutputState::High));
                     commands.push(DoorCommand::TriggerEvent(LogEvent::DoorPermantlyReleased(passageway_id)));
@@ -45,9 +45,9 @@ impl DoorStateImpl for ReleasedOnce
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(passageway_id)));
                 return DoorStateContainer::NormalOp(NormalOperation{}, passageway_id);
             }
-            DoorEvent::BlockingContactEngaged => {
+            DoorEvent::BlockingContactEngaged | DoorEvent::BlockRequested => {
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorBlocked(passageway_id)));
-                return DoorStateContainer::Blocked(Blocked{}, passageway_id);
+                return DoorStateContainer::Blocked(Blocked::caused_by(&d), passageway_id);
             }
             DoorEvent::ReleaseSwitchEngaged    => {
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEmergencyReleased(passageway_id)));
@@ -60,6 +60,8 @@ impl DoorStateImpl for ReleasedOnce
             DoorEvent::DoorOpenerKeyTriggered => { /* Ignore */ }
             DoorEvent::DoorHandleTriggered    => { /* Ignore */ }
             DoorEvent::DoorOpenTooLong        => { /* Ignore */ }
+            DoorEvent::ReleaseOnceRequested   => { /* Ignore */ }
+            DoorEvent::NormalOperationRequested => { /* Ignore */ }
         }
         return DoorStateContainer::ReleasedOnce(self,passageway_id)
     }
@@ -87,11 +89,12 @@ mod released_once_tests
     pub fn arms_door_open_too_long_and_switches_off_strike_on_door_open()
     {
         let (op, mut v)  = make_released_once();
-        op.dispatch_door_event(DoorEvent::Opened, &mut v);
-        assert_eq!(3, v.len());
+        op.dispatch_door_event(0, DoorEvent::Opened, &mut v);
+        assert_eq!(4, v.len());
         assert_eq!(v[0], DoorCommand::ArmDoorOpenTooLongAlarm);
         assert_eq!(v[1], DoorCommand::ToggleElectricStrike(OutputState::Low));
         assert_eq!(v[2], DoorCommand::DisarmAutoswitchToNormal);
+        assert_eq!(v[3], DoorCommand::TriggerEvent(LogEvent::DoorReleasedOnce(0)));
     }
 
     
@@ -99,17 +102,47 @@ mod released_once_tests
     pub fn disarms_door_open_too_long_and_switches_off_access_allowed_on_door_close()
     {
         let (op, mut v)  = make_released_once();
-        op.dispatch_door_event(DoorEvent::Closed, &mut v);
-        assert_eq!(2, v.len());
+        op.dispatch_door_event(0, DoorEvent::Closed, &mut v);
+        assert_eq!(4, v.len());
         assert_eq!(v[0], DoorCommand::DisarmDoorOpenTooLongAlarm);
         assert_eq!(v[1], DoorCommand::ToggleAccessAllowed(OutputState::Low));
+        assert_eq!(v[2], DoorCommand::TriggerEvent(LogEvent::DoorClosedAgain(0)));
+        assert_eq!(v[3], DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(0)));
     }
 
     #[test]
     pub fn returns_to_normal_op_on_door_close()
     {
         let (op, mut v)  = make_released_once();
-        let next = op.dispatch_door_event(DoorEvent::Closed, &mut v);
-        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}))
+        let next = op.dispatch_door_event(0, DoorEvent::Closed, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0))
+    }
+
+    #[test]
+    pub fn changes_to_blocked_on_block_request()
+    {
+        let (op, mut v)  = make_released_once();
+        let next = op.dispatch_door_event(0, DoorEvent::BlockRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::Blocked(Blocked::caused_by(&DoorEvent::BlockRequested), 0));
+        assert_eq!(v[0], DoorCommand::TriggerEvent(LogEvent::DoorBlocked(0)));
+    }
+
+    #[test]
+    pub fn changes_to_released_permanently_on_permanent_release_request()
+    {
+        let (op, mut v)  = make_released_once();
+        let next = op.dispatch_door_event(0, DoorEvent::PermanentReleaseRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasePerm(ReleasedPermanently{}, 0))
+    }
+
+    #[test]
+    pub fn ignores_release_once_and_normal_operation_requests()
+    {
+        let (op, mut v)  = make_released_once();
+        let next = op.dispatch_door_event(0, DoorEvent::ReleaseOnceRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}, 0));
+        let next = op.dispatch_door_event(0, DoorEvent::NormalOperationRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::ReleasedOnce(ReleasedOnce{}, 0));
+        assert!(v.is_empty());
     }
 }
@@ -13,13 +13,13 @@ impl DoorStateImpl for ReleasedPermanently
     fn dispatch_door_event(self,passageway_id: u32, d: DoorEvent, commands: &mut Vec<DoorCommand>) -> DoorStateContainer {
         match d
         {
-            DoorEvent::DoorOpenProfileInactive => {
+            DoorEvent::DoorOpenProfileInactive | DoorEvent::NormalOperationRequested => {
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(passageway_id)));
                 return DoorStateContainer::NormalOp(NormalOperation{}, passageway_id);                
             }
-            DoorEvent::BlockingContactEngaged => {
+            DoorEvent::BlockingContactEngaged | DoorEvent::BlockRequested => {
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorBlocked(passageway_id)));
-                return DoorStateContainer::Blocked(Blocked{}, passageway_id);
+                return DoorStateContainer::Blocked(Blocked::caused_by(&d), passageway_id);
             }
             DoorEvent::ReleaseSwitchEngaged => {
                 commands.push(DoorCommand::TriggerEvent(LogEvent::DoorEmergencyReleased(passageway_id)));
@@ -30,3 +30,33 @@ impl DoorStateImpl for ReleasedPermanently
         return DoorStateContainer::ReleasePerm(self, passageway_id)
     }
 }
+
+
+#[cfg(test)]
+mod released_permanently_tests
+{
+    use super::*;
+
+    fn assert_states_are_equal(s1: DoorStateContainer, s2: DoorStateContainer)
+    {
+        assert_eq!(std::mem::discriminant(&s1), std::mem::discriminant(&s2));
+    }
+
+    #[test]
+    pub fn returns_to_normal_op_on_normal_operation_request()
+    {
+        let mut v = Vec::<DoorCommand>::new();
+        let next = ReleasedPermanently{}.dispatch_door_event(0, DoorEvent::NormalOperationRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::NormalOp(NormalOperation{}, 0));
+        assert_eq!(v[0], DoorCommand::TriggerEvent(LogEvent::DoorEnteredNormalOperation(0)));
+    }
+
+    #[test]
+    pub fn changes_to_blocked_on_block_request()
+    {
+        let mut v = Vec::<DoorCommand>::new();
+        let next = ReleasedPermanently{}.dispatch_door_event(0, DoorEvent::BlockRequested, &mut v);
+        assert_states_are_equal(next, DoorStateContainer::Blocked(Blocked::caused_by(&DoorEvent::BlockRequested), 0));
+        assert_eq!(v[0], DoorCommand::TriggerEvent(LogEvent::DoorBlocked(0)));
+    }
+}
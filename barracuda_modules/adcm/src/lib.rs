@@ -1,13 +1,13 @@
-use barracuda_base_modules::{cfg::{self, cfgholder::FunctionType}, dcm::DoorOpenRequest, events::LogEvent, io::{InputEvent, OutputState}, modulebase::ModuleBase, profile::ProfileChangeEvent, sig::SigType};
+use std::{collections::HashSet, sync::Arc};
+
+use barracuda_base_modules::{cfg::{self, cfgholder::FunctionType}, dcm::{DoorOpenRequest, PassagewayCommand}, events::LogEvent, io::{InputEvent, OutputState}, modulebase::ModuleBase, profile::ProfileChangeEvent, sig::SigType};
 use barracuda_base_modules::Handler;
-use barracuda_core::core::{broadcast_channel::GenericReceiver, channel_manager::*, shareable::Shareable};
-use barracuda_core::core::{bootstage_helper::*, event::DataEvent};
+use barracuda_core::core::{broadcast_channel::GenericReceiver, channel_manager::*, shareable::Shareable, sud::Sud};
+use barracuda_core::core::{SystemMessage, bootstage_helper::*, dependencies::Dependencies, module_thread::spawn_module, select::Selector};
 
 use barracuda_core::trace::*;
-use barracuda_core::{select_chan, wait_for};
 use barracuda_core::util::JsonStorage;
 use barracuda_core::util::ObjectStorage;
-use std::{sync::Arc, thread};
 use crate::components::serialization_types::*;
 use passageway::Passageway;
 
@@ -40,7 +40,11 @@ pub enum DoorEvent
     DoorOpenerKeyTriggered,
     DoorHandleTriggered,
     DoorOpenTooLong,
-    DoorTimerExpired
+    DoorTimerExpired,
+    ReleaseOnceRequested,           // The following are caused by PassagewayCommands
+    BlockRequested,
+    PermanentReleaseRequested,
+    NormalOperationRequested
 }
 
 // Generated by the FSM, consumed by outputs. The inputs
@@ -62,9 +66,9 @@ pub enum DoorCommand
 
 pub fn launch(chm: &mut ChannelManager)
 {    
-    let tracer = trace_helper::TraceHelper::new("DCM/ADCM".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("DCM/ADCM".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut chmclone = chm.clone();
-    thread::spawn(move || {        
+    spawn_module(MODULE_ID, "DCM/ADCM", chm, move || {        
         let mut adcm = ADCM::new(tracer, &mut chmclone);
         adcm.init(); 
         loop 
@@ -79,40 +83,70 @@ pub fn launch(chm: &mut ChannelManager)
 }
 
 
+/// The inputs and access points used by the passageways. The
+/// receivers of the ADCM only get the events of these, so events
+/// of e.g. inputs not assigned to a door are not even queued.
+#[derive(Default)]
+struct Routing
+{
+    inputs          : HashSet<u32>,
+    access_points   : HashSet<u32>
+}
+
 struct ADCM
 {
     module_base         : ModuleBase,
     bin_prof_rx         : GenericReceiver<ProfileChangeEvent>,  
     input_rx            : GenericReceiver<InputEvent>, 
     door_req_rx         : GenericReceiver<DoorOpenRequest>,
+    pway_command_rx     : GenericReceiver<PassagewayCommand>,
     pway_change_rx      : GenericReceiver<PassagewayUpdate>,
     passageways         : Vec<Passageway>,
+    routing             : Shareable<Routing>,
     storage             : Shareable<JsonStorage<PassagewaySetting>>,
     trace               : trace_helper::TraceHelper,
-    channel_manager     : ChannelManager
+    channel_manager     : ChannelManager,
+    selector            : Selector
+}
+
+enum Work
+{
+    ProfileChange(ProfileChangeEvent),
+    Input(InputEvent),
+    DoorRequest(Arc<DoorOpenRequest>),
+    PassagewayCommand(PassagewayCommand),
+    PassagewayChange(PassagewayUpdate),
+    System(SystemMessage)
 }
 
 impl ADCM
 {
     pub fn new(tracer: trace_helper::TraceHelper, chm: &mut ChannelManager) -> Self
     {
+        let routing = Shareable::new(Routing::default());
+        let input_routing = routing.clone();
+        let door_req_routing = routing.clone();
         let mut result = Self
         {
-            module_base         : ModuleBase::new(MODULE_ID, tracer, chm),
+            module_base         : ModuleBase::new(MODULE_ID, Dependencies::new(&[], &[cfg::CFG_HOLDER]), tracer, chm),
             bin_prof_rx         : chm.get_receiver(),
-            input_rx            : chm.get_receiver(),
-            door_req_rx         : chm.get_receiver(),
+            input_rx            : chm.get_receiver_filtered(move |x: &InputEvent| input_routing.lock().inputs.contains(&x.input_id)),
+            door_req_rx         : chm.get_receiver_filtered(move |x: &DoorOpenRequest| door_req_routing.lock().access_points.contains(&x.access_point_id)),
+            pway_command_rx     : chm.get_receiver(),
             pway_change_rx      : chm.get_receiver(),
             passageways         : vec![],
+            routing,
             storage             : Shareable::new(JsonStorage::new("./passageways.txt".to_string())),
-            trace               : trace_helper::TraceHelper::new("DCM/ADCM".to_string(), chm),
-            channel_manager     : chm.clone()
+            trace               : trace_helper::TraceHelper::new("DCM/ADCM".to_string(), chm).with_sud(Sud::from(MODULE_ID)),
+            channel_manager     : chm.clone(),
+            selector            : Selector::new("DCM/ADCM")
         };
 
         for setting in result.storage.lock().iter()
         {
             result.passageways.push(Passageway::new(setting.clone(), chm));
         }
+        result.update_routing();
 
         return result;
     }
@@ -164,43 +198,57 @@ impl ADCM
 
     pub fn run(&mut self) -> bool
     {        
-        let queue_id = select_chan!(self.bin_prof_rx, self.input_rx, self.door_req_rx, self.pway_change_rx);
-        match queue_id
+        let work = self.selector.select()
+                                .recv(&self.bin_prof_rx, Work::ProfileChange)
+                                .recv(&self.input_rx, Work::Input)
+                                .recv_shared(&self.door_req_rx, Work::DoorRequest)
+                                .recv(&self.pway_command_rx, Work::PassagewayCommand)
+                                .recv(&self.pway_change_rx, Work::PassagewayChange)
+                                .recv(&self.module_base.system_events_rx, Work::System)
+                                .wait();
+        match work
         {
-            0 => self.do_bin_prof_event(),
-            1 => self.do_input_event(),
-            2 => self.do_door_request(),
-            3 => self.do_passageway_change_event(),
-            _ => return false
+            Work::ProfileChange(event) => self.do_bin_prof_event(event),
+            Work::Input(event) => self.do_input_event(event),
+            Work::DoorRequest(request) => self.do_door_request(&request),
+            Work::PassagewayCommand(command) => self.do_passageway_command(command),
+            Work::PassagewayChange(event) => self.do_passageway_change_event(event),
+            Work::System(msg) => return self.module_base.handle_system_event(msg)
         }
         true
     }
 
-    fn do_input_event(&mut self)
+    fn do_input_event(&mut self, input_event: InputEvent)
     {
-        let input_event = self.input_rx.receive();
         for passageway in self.passageways.iter_mut()
         {            
             passageway.on_input_change(&input_event);
         }
     }
 
-    fn do_bin_prof_event(&mut self)
+    fn do_bin_prof_event(&mut self, binprof_event: ProfileChangeEvent)
     {
-        let binprof_event = self.bin_prof_rx.receive();
         for passageway in self.passageways.iter_mut()
         {            
             passageway.on_profile_change(&binprof_event);
         }
     }
 
-    fn do_door_request(&mut self)
+    fn do_door_request(&mut self, door_request: &DoorOpenRequest)
     {
-        let door_request = self.door_req_rx.receive();
         self.trace.trace(format!("DoorRequest for accesspoint {}", door_request.access_point_id));
         for passageway in self.passageways.iter_mut()
         {            
-            passageway.on_door_open_request(&door_request);
+            passageway.on_door_open_request(door_request);
+        }
+    }
+
+    fn do_passageway_command(&mut self, command: PassagewayCommand)
+    {
+        self.trace.trace(format!("Passageway command {:?}", command));
+        for passageway in self.passageways.iter_mut()
+        {
+            passageway.on_passageway_command(&command);
         }
     }
 
@@ -222,14 +270,20 @@ impl ADCM
         
     }
 
-    fn do_passageway_change_event(&mut self)
+    fn do_passageway_change_event(&mut self, event: PassagewayUpdate)
     {
-        let event = self.pway_change_rx.receive();
-
         match event
         {
             PassagewayUpdate::PassagewayUpdate(id) => {self.update_passageway(id)},
             PassagewayUpdate::DeletePassageway(id) => {self.passageways.retain(|x| x.id != id)}
         }
+        self.update_routing();
+    }
+
+    fn update_routing(&mut self)
+    {
+        let mut routing = self.routing.lock();
+        routing.inputs = self.passageways.iter().flat_map(|x| x.input_ids()).collect();
+        routing.access_points = self.passageways.iter().flat_map(|x| x.access_points().iter().copied()).collect();
     }
 }
\ No newline at end of file
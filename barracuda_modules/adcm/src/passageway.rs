@@ -1,9 +1,10 @@
 use std::sync::{Arc};
 
-use barracuda_base_modules::{dcm::DoorOpenRequest, events::LogEvent, io::InputEvent, profile::{ProfileChangeEvent, ProfileState}, sig::{SigCommand, SigType}};
+use barracuda_base_modules::{dcm::{DoorOpenRequest, PassagewayCommand}, events::LogEvent, io::InputEvent, profile::{ProfileChangeEvent, ProfileState}, sig::{SigCommand, SigType}};
 use barracuda_core::{core::{
         broadcast_channel::GenericSender, 
         channel_manager::ChannelManager, 
+        sud::Sud,
         timer::Timer}, core::shareable::Shareable, trace::trace_helper::TraceHelper};
 use crate::{DoorCommand, DoorEvent, components::output_components::accessgranted::AccessGranted, components::{InputComponent, OutputComponent, VirtualComponent, output_components::{alarmrelay::AlarmRelay, electricstrike::ElectricStrike}, serialization_types::InputComponentSerialization, serialization_types::{OutputComponentSerialization, PassagewaySetting}}};
 
@@ -97,9 +98,9 @@ impl Passageway
             pending_events: vec![],
             sig_tx: chm.get_sender(),
             log_tx: chm.get_sender(),
-            trace: TraceHelper::new(format!("ADCM/PW{}", settings.id), chm),            
+            trace: TraceHelper::new(format!("ADCM/PW{}", settings.id), chm).with_sud(Sud::new(Sud::from(crate::MODULE_ID).module_type(), 0, settings.id as u16)),            
             door_fsm: Shareable::new(DoorStateContainer::NormalOp(NormalOperation{}, settings.id)),
-            auto_event_timer: Timer::new(),
+            auto_event_timer: chm.timer(),
             auto_switch_normal_timer: None,
             door_open_too_long_timer: None,
             alarm_time: settings.alarm_time,
@@ -131,6 +132,16 @@ impl Passageway
         }
     }
 
+    pub fn input_ids(&self) -> impl Iterator<Item = u32> + '_
+    {
+        self.input_components.iter().map(|x| x.input_id())
+    }
+
+    pub fn access_points(&self) -> &[u32]
+    {
+        &self.access_points
+    }
+
     pub fn on_profile_change(&mut self, event: &ProfileChangeEvent)
     {
         // if the profile is our door open profile, we have
@@ -262,6 +273,22 @@ impl Passageway
         self.handle_door_event(DoorEvent::ValidDoorOpenRequestSeen(request.access_point_id, request.identification_token.clone()));
     }
 
+    pub fn on_passageway_command(&mut self, command: &PassagewayCommand)
+    {
+        let (passageway_id, event) = match *command
+        {
+            PassagewayCommand::ReleaseOnce(id) => (id, DoorEvent::ReleaseOnceRequested),
+            PassagewayCommand::Block(id) => (id, DoorEvent::BlockRequested),
+            PassagewayCommand::ReleasePermanently(id) => (id, DoorEvent::PermanentReleaseRequested),
+            PassagewayCommand::NormalOperation(id) => (id, DoorEvent::NormalOperationRequested)
+        };
+
+        if passageway_id == self.id
+        {
+            self.handle_door_event(event);
+        }
+    }
+
     fn send_signal_command(&self, access_point_id: u32, sigtype: SigType, duration: u32)
     {
         let sig = SigCommand {
@@ -1,12 +1,13 @@
 use barracuda_core::core::broadcast_channel::*;
 use barracuda_core::core::channel_manager::*;
-use barracuda_core::core::{shareable::Shareable, bootstage_helper::*, SystemMessage};
-use barracuda_base_modules::{Handler, cfg::{ConfigMessage, cfgholder::*, self}};
+use barracuda_core::core::{shareable::Shareable, bootstage_helper::*, dependencies::Dependencies, module_thread::spawn_module, sud::Sud, SystemMessage};
+use barracuda_base_modules::{Handler, cfg::{ConfigMessage, CFG_HOLDER, cfgholder::*, self}};
 use barracuda_core::trace::*;
 use barracuda_base_modules::{sig::*, acm::*};
 use barracuda_base_modules::dcm::DoorOpenRequest;
-use barracuda_base_modules::modcaps::{ModCapAggregator, ModuleCapabilityAdvertisement, ModuleCapabilityType};
-use std::{thread};
+use barracuda_base_modules::modcaps::{ModCapAggregator, ModuleCapabilityAdvertisement, ModuleCapabilityType, ACCESS_POINTS, retain_advertisements};
+use barracuda_core::core::select::Selector;
+use std::sync::Arc;
 
 use profiles::{ProfileChecker, JsonProfileChecker, AccessProfile};
 
@@ -19,9 +20,9 @@ const MODULE_ID: u32 = 0x03000000;
 pub fn launch<T: 'static>(chm: &mut ChannelManager)
     where T: whitelist::WhitelistEntryProvider + std::marker::Send
 {    
-    let tracer = trace_helper::TraceHelper::new("ACM/Whitelist".to_string(), chm);
-    let mut wl = GenericWhitelist::new(tracer, chm, T::new(), JsonProfileChecker::new("profiles.txt".to_string()));
-    thread::spawn(move || {  
+    let tracer = trace_helper::TraceHelper::new("ACM/Whitelist".to_string(), chm).with_sud(Sud::from(MODULE_ID));
+    let mut wl = GenericWhitelist::new(tracer, chm, T::new(), JsonProfileChecker::new("profiles.txt".to_string(), chm.clock()));
+    spawn_module(MODULE_ID, "ACM/Whitelist", chm, move || {  
         wl.init();   
         loop 
         {
@@ -47,13 +48,21 @@ struct GenericWhitelist<WhitelistProvider: whitelist::WhitelistEntryProvider, Pr
     whitelist           : Shareable<WhitelistProvider>,
     profiles            : Shareable<ProfileStorage>,
     modcaps             : ModCapAggregator,
-    modcap_rx           : GenericReceiver<ModuleCapabilityAdvertisement>
+    modcap_rx           : GenericReceiver<ModuleCapabilityAdvertisement>,
+    selector            : Selector
+}
+
+enum Work
+{
+    AccessRequest(Arc<WhitelistAccessRequest>),
+    System(SystemMessage)
 }
 
 impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, ProfileStorage:ProfileChecker + Send +'static> GenericWhitelist<WhitelistProvider, ProfileStorage>
 {
     fn new(trace: trace_helper::TraceHelper, chm: &mut ChannelManager, whitelist: WhitelistProvider, profile_source: ProfileStorage) -> Self
     { 
+        retain_advertisements(chm);
         GenericWhitelist
         {
             tracer              : trace,
@@ -66,7 +75,8 @@ impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, Prof
             whitelist           : Shareable::new(whitelist),
             profiles            : Shareable::new(profile_source),
             modcaps             : ModCapAggregator::new(),
-            modcap_rx           : chm.get_receiver()
+            modcap_rx           : chm.get_receiver(),
+            selector            : Selector::new("ACM/Whitelist")
         }
     }
 
@@ -110,7 +120,7 @@ impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, Prof
             
         });
 
-        boot(MODULE_ID, Some(boot_noop), hli_cb, 
+        boot(MODULE_ID, Dependencies::new(&[], &[CFG_HOLDER, ACCESS_POINTS]), Some(boot_noop), hli_cb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
@@ -126,10 +136,27 @@ impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, Prof
     pub fn do_request(&mut self) -> bool
     {
         self.tracer.trace_str("Start serving requests.");
-        let req = self.access_request_rx.receive();
+        let work = self.selector.select()
+                                .recv_shared(&self.access_request_rx, Work::AccessRequest)
+                                .recv(&self.system_events_rx, Work::System)
+                                .wait();
+        let req = match work
+        {
+            Work::AccessRequest(req) => req,
+            Work::System(msg) => {
+                // The shutdown stage is acknowledged right after, so
+                // the storage has to be up to date before that.
+                if is_stop_request(MODULE_ID, &msg)
+                {
+                    self.whitelist.lock().flush();
+                    self.profiles.lock().flush();
+                }
+                return handle_system_message(MODULE_ID, msg, &self.system_events_tx)
+            }
+        };
         self.tracer.trace(format!("Received request with token {:?}", req.identity_token_number));
         // ToDo: This should be done from a threadpool.
-        self.process_access_request(req);
+        self.process_access_request(&req);
         true
     }
 
@@ -161,10 +188,10 @@ impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, Prof
         }
     }
 
-    fn process_access_request(&self, req: WhitelistAccessRequest)
+    fn process_access_request(&self, req: &WhitelistAccessRequest)
     {
         // Pull Whitelist Entry
-        let entry = self.whitelist.lock().get_entry(req.identity_token_number);
+        let entry = self.whitelist.lock().get_entry(req.identity_token_number.clone());
 
         // Found? If so, check access profile, otherwise emit AccessDenied Sig
         if let Some(entry) = entry 
@@ -187,7 +214,7 @@ impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, Prof
         else
         {
             self.tracer.trace_str("Access Denied; Unknown identification token.");
-            self.send_signal_command(req.access_point_id, SigType::AccessDenied, 1000);
+            self.send_signal_command(req.access_point_id.into(), SigType::AccessDenied, 1000);
         }
     }
 
@@ -229,7 +256,8 @@ impl<WhitelistProvider: whitelist::WhitelistEntryProvider + Send + 'static, Prof
 
 #[cfg(test)]
 mod tests {
-     use barracuda_core::{core::channel_manager::ChannelManager, trace::*};
+     use barracuda_core::{core::{channel_manager::ChannelManager, sud::Sud, SystemMessage, BootStage}, trace::*};
+     use std::cell::Cell;
      use crate::profiles::{AccessProfile, ProfileChecker, ProfileCheckResult};
      use crate::whitelist::WhitelistEntry;
      use crate::whitelist::WhitelistEntryProvider;
@@ -238,14 +266,15 @@ mod tests {
 
      struct DummyWhitelist
      {
-        pub entry: Option<WhitelistEntry>
+        pub entry: Option<WhitelistEntry>,
+        pub flushed: Cell<bool>
      }
 
      impl crate::whitelist::WhitelistEntryProvider for DummyWhitelist
      {         
         fn new() -> Self
         {
-            DummyWhitelist{entry: None}
+            DummyWhitelist{entry: None, flushed: Cell::new(false)}
         }
 
          fn get_entry(&self, _identity_token_id: Vec<u8>) -> Option<crate::whitelist::WhitelistEntry> 
@@ -259,6 +288,9 @@ mod tests {
          fn delete_entry(&mut self, _identity_token_id: Vec<u8>) { 
              self.entry = None;
          }
+         fn flush(&self) {
+             self.flushed.set(true);
+         }
 
      }  
 
@@ -277,6 +309,7 @@ mod tests {
         fn add_profile(&mut self, _profile: AccessProfile) {}
         fn get_profile(&self, _profile_id_: u32) -> Option<AccessProfile> {None}
         fn delete_profile(&mut self, _profile_id: u32) { }         
+        fn flush(&self) {}
      }
 
      fn make_whitelist(chm: &mut ChannelManager) -> crate::GenericWhitelist<DummyWhitelist, DummyProfileChecker>
@@ -287,7 +320,7 @@ mod tests {
         let mut md = crate::GenericWhitelist::new(tracer, chm, wl, prof);
 
         let ap_modcap_message = ModuleCapabilityAdvertisement {
-            module_id: 0x10000000,
+            module_id: Sud::new(0x10, 0, 0),
             caps: vec![ModuleCapability::AccessPoints(50)]
         };
         
@@ -298,6 +331,21 @@ mod tests {
      }
 
 
+     #[test]
+     fn flushes_whitelist_before_acknowledging_shutdown()
+     {
+         let mut chm = ChannelManager::new();
+         let mut wl = make_whitelist(&mut chm);
+         let sys_tx = chm.get_sender::<SystemMessage>();
+         let sys_rx = chm.get_receiver::<SystemMessage>();
+
+         sys_tx.send(SystemMessage::ShutdownModule(crate::MODULE_ID));
+         assert!(!wl.do_request());
+         assert!(wl.whitelist.lock().flushed.get());
+         assert!(sys_rx.receive_with_timeout(1) == Some(SystemMessage::ShutdownModule(crate::MODULE_ID)));
+         assert!(sys_rx.receive_with_timeout(1) == Some(SystemMessage::StageComplete(BootStage::Shutdown, crate::MODULE_ID)));
+     }
+
      #[test]
      fn will_throw_access_denied_if_no_whitelist_entry_exists()
      {
@@ -308,7 +356,7 @@ mod tests {
          let access_tx = chm.get_sender::<WhitelistAccessRequest>();
 
          let req = WhitelistAccessRequest {
-             access_point_id: 0,
+             access_point_id: Sud::default(),
              identity_token_number: vec![1,2,3,4],
          };
 
@@ -338,7 +386,7 @@ mod tests {
         let mut md = crate::GenericWhitelist::new(tracer, &mut chm, wl, DummyProfileChecker {check_result: Ok(())});
 
         let ap_modcap_message = ModuleCapabilityAdvertisement {
-            module_id: 0x10000000,
+            module_id: Sud::new(0x10, 0, 0),
             caps: vec![ModuleCapability::AccessPoints(50)]
         };
         
@@ -349,7 +397,7 @@ mod tests {
         let access_tx = chm.get_sender::<WhitelistAccessRequest>();
 
         let req = WhitelistAccessRequest {
-            access_point_id: 0x1000002F,
+            access_point_id: Sud::new(0x10, 0, 0x2F),
             identity_token_number: vec![1,2,3,4],
         };
 
@@ -379,7 +427,7 @@ mod tests {
         let mut md = crate::GenericWhitelist::new(tracer, &mut chm, wl,DummyProfileChecker {check_result: Ok(())});
         
         let ap_modcap_message = ModuleCapabilityAdvertisement {
-            module_id: 0x10000000,
+            module_id: Sud::new(0x10, 0, 0),
             caps: vec![ModuleCapability::AccessPoints(50)]
         };
         
@@ -392,7 +440,7 @@ mod tests {
         for _ in 0..20
         {
             let req = WhitelistAccessRequest {
-                access_point_id: 0x1000002F,
+                access_point_id: Sud::new(0x10, 0, 0x2F),
                 identity_token_number: vec![1,2,3,4],
             };
 
@@ -1,9 +1,11 @@
 use super::whitelist::WhitelistEntry;
 use barracuda_core::util::{JsonStorage, ObjectStorage};
 use barracuda_core::util::datetime::*;
+use barracuda_core::core::clock::Clock;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Datelike, Timelike, Local};
+use chrono::{DateTime, Datelike, Timelike};
 use strum_macros::*;
 
 #[derive(Clone, Copy, Debug, Display)]
@@ -34,17 +36,22 @@ pub trait ProfileChecker
     fn add_profile(&mut self, profile: AccessProfile);
     fn get_profile(&self, profile_id_: u32) -> Option<AccessProfile>;
     fn delete_profile(&mut self, profile_id: u32);
+    /// Writes any pending changes to the storage, called before
+    /// the module terminates.
+    fn flush(&self);
 }
 
 pub struct JsonProfileChecker{
-    profiles: JsonStorage<AccessProfile>
+    profiles: JsonStorage<AccessProfile>,
+    clock: Arc<dyn Clock>
 }
 
 impl JsonProfileChecker
 {
-    pub fn new(file: String) -> Self{
+    pub fn new(file: String, clock: Arc<dyn Clock>) -> Self{
         JsonProfileChecker{
-            profiles: JsonStorage::new(file)
+            profiles: JsonStorage::new(file),
+            clock
         }
     }
 }
@@ -58,7 +65,7 @@ impl ProfileChecker for JsonProfileChecker
             let profile = self.profiles.get_entry(|x| x.id == *profile_id);
             if let Some(the_profile) = profile
             {   
-                let datetime = Local::now();
+                let datetime = self.clock.local_time();
                 // ToDo: This code will effectively only check the
                 // first assigned profile and always return afterwards.
                 // We actually want to check all assigned profiles 
@@ -83,7 +90,11 @@ impl ProfileChecker for JsonProfileChecker
         self.profiles.delete_entry(|x| x.id == profile_id as u16);
         self.profiles.update_storage();
     }
-    
+
+    fn flush(&self) {
+        self.profiles.update_storage();
+    }
+
 }
 
 
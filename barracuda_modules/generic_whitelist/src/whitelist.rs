@@ -17,6 +17,9 @@ pub trait WhitelistEntryProvider
     fn get_entry(&self, identity_token_id: Vec<u8>) -> Option<WhitelistEntry>;
     fn put_entry(&mut self,entry: WhitelistEntry);
     fn delete_entry(&mut self, identity_token_id: Vec<u8>);
+    /// Writes any pending changes to the storage, called before
+    /// the module terminates.
+    fn flush(&self);
     fn new() -> Self;
 }
 
@@ -68,5 +71,10 @@ impl WhitelistEntryProvider for JsonEntryProvider
         self.entries.delete_entry(|x| x.identification_token_id.cmp(&identity_token_id) != Ordering::Equal);
         self.entries.update_storage();
     }
+
+    fn flush(&self)
+    {
+        self.entries.update_storage();
+    }
 }
 
@@ -57,6 +57,7 @@
 */
 
 use crate::{error::TxpError, rfchip::RFChip};
+use barracuda_core::trace::{trace_helper::TraceHelper, TraceLevel};
 use std::iter::FromIterator;
 use num_enum::TryFromPrimitive;
 use std::convert::TryFrom;
@@ -140,7 +141,8 @@ const INCREASE_CASCADE_MASK: u8 = 0b00000100;
 
 pub struct Iso14443A<'a,T> where T: RFChip
 {
-    rf_chip: &'a T
+    rf_chip: &'a T,
+    tracer: Option<&'a TraceHelper>
 }
 
 
@@ -149,7 +151,24 @@ impl<'a, T:RFChip> Iso14443A<'a, T>
     pub fn new(chip: &'a T) -> Self
     {
         Self{
-            rf_chip: chip
+            rf_chip: chip,
+            tracer: None
+        }
+    }
+
+    /// Emits debug traces for the single steps of the
+    /// card activation, including the raw UID bytes read.
+    pub fn with_tracer(mut self, tracer: &'a TraceHelper) -> Self
+    {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    fn trace_bytes(&self, message: &str, key: &str, data: &[u8])
+    {
+        if let Some(tracer) = self.tracer
+        {
+            tracer.record(TraceLevel::Debug, message).field(key, format!("{:02X?}", data)).send();
         }
     }
 
@@ -172,6 +191,7 @@ impl<'a, T:RFChip> Iso14443A<'a, T>
         {
             return Err(TxpError::GeneralError);
         }
+        self.trace_bytes("Transponder detected.", "atqa", &atqa);
 
         let uid = self.txp_anticoll()?;
         Ok(uid)
@@ -199,6 +219,7 @@ impl<'a, T:RFChip> Iso14443A<'a, T>
 
         // The 0x20 is actually the NVB!
         let mut res = self.do_picc_command( Iso14443aCommand::AnticollCasc1, Some(vec![0x20]))?;
+        self.trace_bytes("Read UID, cascade level 1.", "raw", &res);
 
         // The anti collision loop should go here... but alas:
         // Note, that we do not really support anti coll here, but we use the anticoll
@@ -225,6 +246,7 @@ impl<'a, T:RFChip> Iso14443A<'a, T>
         // already received are the prefix.
         select_data.append(&mut res.clone());
         let sak = self.do_picc_command(Iso14443aCommand::AnticollCasc1, Some(select_data))?[0];
+        self.trace_bytes("Selected transponder, cascade level 1.", "sak", &[sak]);
         if sak & INCREASE_CASCADE_MASK != INCREASE_CASCADE_MASK
         {
             // SAK states UID is incomplete (i.e. != 0x04)
@@ -234,12 +256,14 @@ impl<'a, T:RFChip> Iso14443A<'a, T>
         }
 
         let uid2 = self.do_picc_command( Iso14443aCommand::AnticollCasc2, Some(vec![0x20 as u8]))?;
+        self.trace_bytes("Read UID, cascade level 2.", "raw", &uid2);
         let _ = self.check_bcc(&uid2)?;
 
         // Get select ackknowledge
         let mut sak2selectdata = vec![0x70];
         sak2selectdata.extend_from_slice(&uid2[..]);            
         let sak2 = self.do_picc_command( Iso14443aCommand::AnticollCasc2, Some(sak2selectdata))?[0];
+        self.trace_bytes("Selected transponder, cascade level 2.", "sak", &[sak2]);
         
         if sak2 & INCREASE_CASCADE_MASK == INCREASE_CASCADE_MASK
         {
@@ -36,34 +36,42 @@ extern crate barracuda_core;
 extern crate barracuda_hal;
 extern crate num_enum;
 
-use barracuda_base_modules::{acm::WhitelistAccessRequest, modcaps::{ModuleCapability, ModuleCapabilityAdvertisement}};
+use barracuda_base_modules::{acm::WhitelistAccessRequest, modcaps::{ModuleCapability, ModuleCapabilityAdvertisement, ACCESS_POINTS}};
 use barracuda_core::{core::
-            {bootstage_helper::{boot_noop, boot}, 
+            {bootstage_helper::{boot_noop, boot, poll_system_messages}, 
+             dependencies::Dependencies,
+             module_thread::spawn_module,
+             sud::Sud,
              channel_manager::ChannelManager, 
              broadcast_channel::{GenericSender, GenericReceiver}, SystemMessage},              
-             trace::trace_helper,              
+             trace::{TraceLevel, trace_helper},              
             };
 
 use barracuda_hal::{spi::SpiInterface, interrupt::Interrupt};
 use std::{thread, time};
 use iso14443a::Iso14443aTransponder;
+use error::TxpError;
 
 mod mfrc522;
 mod rfchip;
 mod iso14443a;
 mod error;
 
-const MODULE_ID: u32 = 0x0B000000;
+pub const MODULE_ID: u32 = 0x0B000000;
 
 pub fn launch<Spi, Irq>(chm: &mut ChannelManager, spi_driver: Spi, tx_ready_irq: Irq)
     where Spi: SpiInterface+Send + 'static, Irq: Interrupt+Send+ 'static
 {    
-    let tracer = trace_helper::TraceHelper::new("ARM/MFRC522".to_string(), chm);
+    let tracer = trace_helper::TraceHelper::new("ARM/MFRC522".to_string(), chm).with_sud(Sud::from(MODULE_ID));
     let mut rm = ReaderModule::new(tracer, chm, spi_driver, tx_ready_irq);
-    thread::spawn(move || {  
+    spawn_module(MODULE_ID, "ARM/MFRC522", chm, move || {  
         rm.init();   
         loop 
         {
+            if !rm.poll_system_events()
+            {
+                break;
+            }
             rm.search_media();
         }   
         
@@ -104,17 +112,22 @@ impl<Spi: SpiInterface, Irq: Interrupt> ReaderModule<Spi, Irq>
         let hlicb= Some(move|| {
             let m = ModuleCapabilityAdvertisement {
                 caps: vec![ModuleCapability::AccessPoints(1)],
-                module_id: MODULE_ID
+                module_id: Sud::from(MODULE_ID)
             };
             modcaps_tx_clone.send(m);            
         });
 
-        boot(MODULE_ID, Some(boot_noop), hlicb, 
+        boot(MODULE_ID, Dependencies::new(&[ACCESS_POINTS], &[]), Some(boot_noop), hlicb, 
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
     }
 
+    pub fn poll_system_events(&self) -> bool
+    {
+        poll_system_messages(MODULE_ID, &self.system_events_tx, &self.system_events_rx)
+    }
+
     fn is_new_txp(&self, txp: &Iso14443aTransponder) -> bool
     {
         match self.last_txp
@@ -136,7 +149,7 @@ impl<Spi: SpiInterface, Irq: Interrupt> ReaderModule<Spi, Irq>
 
     pub fn search_media(&mut self)
     {
-        let iso_impl = iso14443a::Iso14443A::new(&self.rfchip);
+        let iso_impl = iso14443a::Iso14443A::new(&self.rfchip).with_tracer(&self.tracer);
 
         self.rfchip.toggle_antenna(true);        
         let txp = iso_impl.search_txp();
@@ -149,11 +162,11 @@ impl<Spi: SpiInterface, Irq: Interrupt> ReaderModule<Spi, Irq>
             {
                 return;
             }
-            self.tracer.trace_str("Found new transponder.");
+            self.tracer.record(TraceLevel::Info, "Found new transponder.").field("uid", format!("{:02X?}", uid.uid)).send();
 
             let req = WhitelistAccessRequest
             {
-                access_point_id: MODULE_ID,     // use AP 1, i.e. index 0
+                access_point_id: Sud::from(MODULE_ID),     // use AP 1, i.e. index 0
                 identity_token_number: uid.uid.clone()
             };
 
@@ -162,6 +175,11 @@ impl<Spi: SpiInterface, Irq: Interrupt> ReaderModule<Spi, Irq>
         }
         else
         {
+            // A timeout just means, that there is no transponder in the field.
+            if let Some(e) = txp.as_ref().err().filter(|e| !matches!(e, TxpError::Timeout))
+            {
+                self.tracer.record(TraceLevel::Debug, "Transponder search failed.").field("error", format!("{:?}", e)).send();
+            }
             self.last_txp = None;
         }
 
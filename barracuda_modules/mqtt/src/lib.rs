@@ -0,0 +1,611 @@
+use std::{collections::VecDeque, fs, io, sync::Arc, thread, time::Duration};
+
+use barracuda_base_modules::{dcm::PassagewayCommand, events::LogEvent, io::{InputEvent, InputState, OutputState, OutputSwitch}, modulebase::ModuleBase};
+use barracuda_core::core::{SystemMessage, broadcast_channel::{GenericReceiver, GenericSender}, channel_manager::ChannelManager, dependencies::Dependencies, module_thread::spawn_module, select::Selector, sud::Sud};
+use barracuda_core::trace::trace_helper::{self, TraceHelper};
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, QoS, Transport};
+use serde::{Serialize, Deserialize};
+
+pub mod topics;
+use topics::{id_from_topic, subscription_for, topic_for};
+
+const MODULE_ID: u32 = 0x11000000;
+
+/// Files used to connect to the broker by means of TLS, all
+/// files are PEM encoded.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MqttTlsSettings
+{
+    pub ca_file: Option<String>,            // CA the broker's certificate is checked against, None uses the system's root certificates
+    pub client_cert_file: Option<String>,   // Certificate the module authenticates with, needs client_key_file
+    pub client_key_file: Option<String>
+}
+
+/// Anyone who may publish to the command topics can release or
+/// block doors and switch outputs, so commands are only accepted
+/// if their topics are configured. These should only be used along
+/// with credentials and TLS, and a broker that restricts who may
+/// publish to them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MqttSettings
+{
+    pub broker_host: String,
+    pub broker_port: u16,
+    pub client_id: String,
+    pub username: Option<String>,                   // Credentials, only sent if set
+    pub password: Option<String>,
+    pub tls: Option<MqttTlsSettings>,               // None connects without TLS
+    pub event_topic: String,                        // All LogEvents, as JSON
+    pub passageway_state_topic: String,             // Template, see topics. Retained
+    pub input_topic: String,                        // Template. Retained
+    pub passageway_command_topic: Option<String>,   // Template, subscribed. None ignores passageway commands
+    pub output_command_topic: Option<String>,       // Template, subscribed. None ignores output commands
+    pub buffer_size: usize,                         // Publications kept while the broker is unreachable
+    pub reconnect_delay: u64                        // in ms!
+}
+
+impl Default for MqttSettings
+{
+    fn default() -> Self {
+        MqttSettings
+        {
+            broker_host: "localhost".to_string(),
+            broker_port: 1883,
+            client_id: "barracuda".to_string(),
+            username: None,
+            password: None,
+            tls: None,
+            event_topic: "barracuda/events".to_string(),
+            passageway_state_topic: "barracuda/passageway/{id}/state".to_string(),
+            input_topic: "barracuda/input/{id}".to_string(),
+            passageway_command_topic: None,
+            output_command_topic: None,
+            buffer_size: 1000,
+            reconnect_delay: 5000
+        }
+    }
+}
+
+/// The part of the MQTT client the module uses,
+/// allows running the module without a broker.
+pub trait Broker: Send
+{
+    /// Queues a publication, yields false if the
+    /// client cannot take any more publications.
+    fn publish(&self, topic: &str, payload: String, retain: bool) -> bool;
+    fn subscribe(&self, filter: &str);
+    fn disconnect(&self);
+}
+
+impl Broker for Client
+{
+    fn publish(&self, topic: &str, payload: String, retain: bool) -> bool
+    {
+        self.try_publish(topic, QoS::AtLeastOnce, retain, payload).is_ok()
+    }
+
+    fn subscribe(&self, filter: &str)
+    {
+        let _ = self.try_subscribe(filter, QoS::AtLeastOnce);
+    }
+
+    fn disconnect(&self)
+    {
+        let _ = self.try_disconnect();
+    }
+}
+
+/// Sent by the thread driving the connection to the module.
+#[derive(Clone, Debug, PartialEq)]
+enum BrokerEvent
+{
+    Connected,
+    Disconnected(String),       // Reason
+    Received(String, String)    // Topic and payload
+}
+
+/// Body of a message on the output command topic, e.g.
+/// {"target_state":"High","switch_time":5000}
+#[derive(Deserialize)]
+struct OutputCommand
+{
+    target_state: OutputState,
+    #[serde(default)]
+    switch_time: u64            // in ms! 0 switches permanently
+}
+
+pub fn launch(chm: &mut ChannelManager)
+{
+    launch_with_settings(chm, MqttSettings::default());
+}
+
+pub fn launch_with_settings(chm: &mut ChannelManager, settings: MqttSettings)
+{
+    let tracer = trace_helper::TraceHelper::new("GW/Mqtt".to_string(), chm).with_sud(Sud::from(MODULE_ID));
+    match mqtt_options(&settings)
+    {
+        Ok(options) =>
+        {
+            let (client, connection) = Client::new(options, settings.buffer_size.max(10));
+            drive_connection(chm, connection, &settings.client_id, settings.reconnect_delay);
+            launch_client(chm, tracer, client, settings);
+        },
+        Err(e) =>
+        {
+            // Falling back to an unencrypted connection would
+            // expose the credentials, so the module stays offline.
+            tracer.error(format!("Cannot read the TLS files: {}, not connecting to the broker", e));
+            let options = MqttOptions::new(settings.client_id.clone(), settings.broker_host.clone(), settings.broker_port);
+            let (client, _) = Client::new(options, settings.buffer_size.max(10));
+            launch_client(chm, tracer, client, settings);
+        }
+    }
+}
+
+/// Builds the options to connect with, the files
+/// needed for TLS are read right away.
+fn mqtt_options(settings: &MqttSettings) -> io::Result<MqttOptions>
+{
+    let mut options = MqttOptions::new(settings.client_id.clone(), settings.broker_host.clone(), settings.broker_port);
+    if let Some(username) = &settings.username
+    {
+        options.set_credentials(username.clone(), settings.password.clone().unwrap_or_default());
+    }
+    if let Some(tls) = &settings.tls
+    {
+        let client_auth = match (&tls.client_cert_file, &tls.client_key_file)
+        {
+            (Some(cert), Some(key)) => Some((fs::read(cert)?, fs::read(key)?)),
+            (None, None) => None,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "client_cert_file and client_key_file have to be set both"))
+        };
+        let transport = match &tls.ca_file
+        {
+            Some(ca_file) => Transport::tls(fs::read(ca_file)?, client_auth, None),
+            None if client_auth.is_none() => Transport::tls_with_default_config(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "a client certificate needs the ca_file"))
+        };
+        options.set_transport(transport);
+    }
+    Ok(options)
+}
+
+fn launch_client(chm: &mut ChannelManager, tracer: TraceHelper, client: Client, settings: MqttSettings)
+{
+    let mut mqtt = Mqtt::new(tracer, chm, client, settings);
+    spawn_module(MODULE_ID, "GW/Mqtt", chm, move || {
+        mqtt.init();
+        loop
+        {
+            if !mqtt.run()
+            {
+                break;
+            }
+        }
+    });
+}
+
+/// Keeps the connection to the broker alive, i.e. reconnects
+/// after reconnect_delay if the connection was lost. Stops once
+/// the client was dropped. Runs as part of the module, a panic
+/// is reported to the supervisor.
+fn drive_connection(chm: &mut ChannelManager, mut connection: Connection, client_id: &str, reconnect_delay: u64)
+{
+    let events_tx: GenericSender<BrokerEvent> = chm.get_topic_sender(client_id);
+    spawn_module(MODULE_ID, "GW/Mqtt/Connection", chm, move || {
+        let mut connected = false;
+        for notification in connection.iter()
+        {
+            match notification
+            {
+                Ok(Event::Incoming(Packet::ConnAck(_))) =>
+                {
+                    connected = true;
+                    events_tx.send(BrokerEvent::Connected);
+                },
+                Ok(Event::Incoming(Packet::Publish(publish))) =>
+                {
+                    events_tx.send(BrokerEvent::Received(publish.topic, String::from_utf8_lossy(&publish.payload).to_string()));
+                },
+                Ok(_) => {},
+                Err(e) =>
+                {
+                    if connected
+                    {
+                        connected = false;
+                        events_tx.send(BrokerEvent::Disconnected(e.to_string()));
+                    }
+                    thread::sleep(Duration::from_millis(reconnect_delay));
+                }
+            }
+        }
+    });
+}
+
+struct Publication
+{
+    topic: String,
+    payload: String,
+    retain: bool
+}
+
+enum Work
+{
+    Log(Arc<LogEvent>),
+    Input(InputEvent),
+    Broker(BrokerEvent),
+    System(SystemMessage)
+}
+
+struct Mqtt<B: Broker>
+{
+    module          : ModuleBase,
+    broker          : B,
+    settings        : MqttSettings,
+    log_rx          : GenericReceiver<LogEvent>,
+    input_rx        : GenericReceiver<InputEvent>,
+    broker_rx       : GenericReceiver<BrokerEvent>,
+    pway_command_tx : GenericSender<PassagewayCommand>,
+    output_tx       : GenericSender<OutputSwitch>,
+    connected       : bool,
+    buffer          : VecDeque<Publication>,
+    dropped         : u64,                  // Publications lost because the buffer was full
+    selector        : Selector
+}
+
+impl <B: Broker> Mqtt<B>
+{
+    fn new(tracer: TraceHelper, chm: &mut ChannelManager, broker: B, settings: MqttSettings) -> Self
+    {
+        // The client ID is used as topic, so multiple instances
+        // don't see each other's connections.
+        Mqtt
+        {
+            module          : ModuleBase::new(MODULE_ID, Dependencies::new(&[], &[]), tracer, chm),
+            broker,
+            log_rx          : chm.get_receiver(),
+            input_rx        : chm.get_receiver(),
+            broker_rx       : chm.get_topic_receiver(&settings.client_id),
+            pway_command_tx : chm.get_sender(),
+            output_tx       : chm.get_sender(),
+            connected       : false,
+            buffer          : VecDeque::new(),
+            dropped         : 0,
+            selector        : Selector::new("GW/Mqtt"),
+            settings
+        }
+    }
+
+    fn init(&mut self)
+    {
+        let accepts_commands = self.settings.passageway_command_topic.is_some() || self.settings.output_command_topic.is_some();
+        if accepts_commands && (self.settings.username.is_none() || self.settings.tls.is_none())
+        {
+            self.module.tracer().warning("Accepting commands without credentials or TLS, anyone who can reach the broker may operate doors".to_string());
+        }
+        self.module.plain_boot();
+    }
+
+    fn run(&mut self) -> bool
+    {
+        let work = self.selector.select()
+                                .recv(&self.broker_rx, Work::Broker)
+                                .recv_shared(&self.log_rx, Work::Log)
+                                .recv(&self.input_rx, Work::Input)
+                                .recv(&self.module.system_events_rx, Work::System)
+                                .wait();
+        match work
+        {
+            Work::Log(event) => self.do_log_event(&event),
+            Work::Input(event) => self.do_input_event(event),
+            Work::Broker(event) => self.do_broker_event(event),
+            Work::System(msg) =>
+            {
+                if self.module.is_stop_request(&msg)
+                {
+                    self.broker.disconnect();
+                }
+                return self.module.handle_system_event(msg);
+            }
+        }
+        true
+    }
+
+    fn do_log_event(&mut self, event: &LogEvent)
+    {
+        let state = match *event
+        {
+            LogEvent::DoorEmergencyReleased(id) => Some((id, "EmergencyReleased")),
+            LogEvent::DoorEnteredNormalOperation(id) => Some((id, "NormalOperation")),
+            LogEvent::DoorPermantlyReleased(id) => Some((id, "PermanentlyReleased")),
+            LogEvent::DoorReleasedOnce(id) => Some((id, "ReleasedOnce")),
+            LogEvent::DoorBlocked(id) => Some((id, "Blocked")),
+            _ => None
+        };
+
+        let topic = self.settings.event_topic.clone();
+        self.publish(topic, serde_json::to_string(event).unwrap(), false);
+        if let Some((id, state)) = state
+        {
+            let topic = topic_for(&self.settings.passageway_state_topic, id);
+            self.publish(topic, state.to_string(), true);
+        }
+    }
+
+    fn do_input_event(&mut self, event: InputEvent)
+    {
+        let state = match event.state
+        {
+            InputState::Low => "Low",
+            InputState::High => "High",
+            InputState::_Short => "Short",
+            InputState::_Cutout => "Cutout",
+            InputState::_Unknown => "Unknown"
+        };
+        let topic = topic_for(&self.settings.input_topic, event.input_id);
+        self.publish(topic, state.to_string(), true);
+    }
+
+    fn do_broker_event(&mut self, event: BrokerEvent)
+    {
+        match event
+        {
+            BrokerEvent::Connected =>
+            {
+                self.module.tracer().trace_str("Connected to broker");
+                self.connected = true;
+                // Subscriptions do not survive a lost connection.
+                for template in [&self.settings.passageway_command_topic, &self.settings.output_command_topic].iter().filter_map(|x| x.as_ref())
+                {
+                    self.broker.subscribe(&subscription_for(template));
+                }
+                self.flush();
+            },
+            BrokerEvent::Disconnected(reason) =>
+            {
+                self.module.tracer().trace(format!("Connection to broker lost: {}", reason));
+                self.connected = false;
+            },
+            BrokerEvent::Received(topic, payload) => self.do_command(&topic, payload.trim())
+        }
+    }
+
+    fn do_command(&mut self, topic: &str, payload: &str)
+    {
+        let passageway_id = self.settings.passageway_command_topic.as_ref().and_then(|x| id_from_topic(x, topic));
+        let output_id = self.settings.output_command_topic.as_ref().and_then(|x| id_from_topic(x, topic));
+        if let Some(id) = passageway_id
+        {
+            let command = match payload
+            {
+                "ReleaseOnce" => PassagewayCommand::ReleaseOnce(id),
+                "Block" => PassagewayCommand::Block(id),
+                "ReleasePermanently" => PassagewayCommand::ReleasePermanently(id),
+                "NormalOperation" => PassagewayCommand::NormalOperation(id),
+                _ => return self.module.tracer().trace(format!("Unknown passageway command {} on {}", payload, topic))
+            };
+            self.pway_command_tx.send(command);
+        }
+        else if let Some(id) = output_id
+        {
+            match serde_json::from_str::<OutputCommand>(payload)
+            {
+                Ok(command) => self.output_tx.send(OutputSwitch { output_id: id, target_state: command.target_state, switch_time: command.switch_time }),
+                Err(e) => self.module.tracer().trace(format!("Invalid output command on {}: {}", topic, e))
+            }
+        }
+    }
+
+    /// Publishes right away if the broker is connected, otherwise the
+    /// publication is buffered. The oldest publications are dropped
+    /// once the buffer is full.
+    fn publish(&mut self, topic: String, payload: String, retain: bool)
+    {
+        self.buffer.push_back(Publication { topic, payload, retain });
+        if self.buffer.len() > self.settings.buffer_size
+        {
+            self.buffer.pop_front();
+            self.dropped += 1;
+            if self.dropped == 1
+            {
+                self.module.tracer().trace_str("Buffer full, dropping the oldest publications");
+            }
+        }
+        self.flush();
+    }
+
+    fn flush(&mut self)
+    {
+        if !self.connected
+        {
+            return;
+        }
+        while let Some(publication) = self.buffer.front()
+        {
+            if !self.broker.publish(&publication.topic, publication.payload.clone(), publication.retain)
+            {
+                break;
+            }
+            self.buffer.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use barracuda_core::core::shareable::Shareable;
+    use barracuda_core::util::temp_dir::TempDir;
+
+    #[derive(Clone)]
+    struct FakeBroker
+    {
+        publications: Shareable<Vec<(String, String, bool)>>,
+        subscriptions: Shareable<Vec<String>>
+    }
+
+    impl Broker for FakeBroker
+    {
+        fn publish(&self, topic: &str, payload: String, retain: bool) -> bool
+        {
+            self.publications.lock().push((topic.to_string(), payload, retain));
+            true
+        }
+
+        fn subscribe(&self, filter: &str)
+        {
+            self.subscriptions.lock().push(filter.to_string());
+        }
+
+        fn disconnect(&self) {}
+    }
+
+    fn make_mod(settings: MqttSettings) -> (Mqtt<FakeBroker>, FakeBroker, ChannelManager)
+    {
+        let mut chm = ChannelManager::new();
+        let broker = FakeBroker { publications: Shareable::new(Vec::new()), subscriptions: Shareable::new(Vec::new()) };
+        let mqtt = Mqtt::new(TraceHelper::new("Test".to_string(), &mut chm), &mut chm, broker.clone(), settings);
+        (mqtt, broker, chm)
+    }
+
+    fn connect(md: &mut Mqtt<FakeBroker>, chm: &mut ChannelManager)
+    {
+        chm.get_topic_sender::<BrokerEvent>("barracuda").send(BrokerEvent::Connected);
+        md.run();
+    }
+
+    #[test]
+    fn publishes_events_and_passageway_state()
+    {
+        let (mut md, broker, mut chm) = make_mod(MqttSettings::default());
+        connect(&mut md, &mut chm);
+
+        chm.get_sender::<LogEvent>().send(LogEvent::DoorBlocked(3));
+        md.run();
+
+        let publications = broker.publications.lock().clone();
+        assert_eq!(vec![("barracuda/events".to_string(), "{\"DoorBlocked\":3}".to_string(), false),
+                        ("barracuda/passageway/3/state".to_string(), "Blocked".to_string(), true)], publications);
+        assert!(broker.subscriptions.lock().is_empty());
+    }
+
+    fn with_commands() -> MqttSettings
+    {
+        MqttSettings
+        {
+            passageway_command_topic: Some("barracuda/passageway/{id}/command".to_string()),
+            output_command_topic: Some("barracuda/output/{id}/set".to_string()),
+            ..MqttSettings::default()
+        }
+    }
+
+    #[test]
+    fn subscribes_to_configured_command_topics()
+    {
+        let (mut md, broker, mut chm) = make_mod(with_commands());
+        connect(&mut md, &mut chm);
+        assert_eq!(vec!["barracuda/passageway/+/command".to_string(), "barracuda/output/+/set".to_string()], broker.subscriptions.lock().clone());
+    }
+
+    #[test]
+    fn buffers_publications_while_offline()
+    {
+        let (mut md, broker, mut chm) = make_mod(MqttSettings { buffer_size: 2, ..MqttSettings::default() });
+        let input_tx = chm.get_sender::<InputEvent>();
+        for input_id in 1..4
+        {
+            input_tx.send(InputEvent { input_id, state: InputState::High });
+            md.run();
+        }
+        assert!(broker.publications.lock().is_empty());
+
+        connect(&mut md, &mut chm);
+        let topics: Vec<String> = broker.publications.lock().iter().map(|x| x.0.clone()).collect();
+        assert_eq!(vec!["barracuda/input/2".to_string(), "barracuda/input/3".to_string()], topics);
+        assert_eq!(1, md.dropped);
+    }
+
+    #[test]
+    fn commands_are_sent_on_the_bus()
+    {
+        let (mut md, _broker, mut chm) = make_mod(with_commands());
+        let pway_command_rx = chm.get_receiver::<PassagewayCommand>();
+        let output_rx = chm.get_receiver::<OutputSwitch>();
+        let broker_tx = chm.get_topic_sender::<BrokerEvent>("barracuda");
+
+        broker_tx.send(BrokerEvent::Received("barracuda/passageway/4/command".to_string(), "Block".to_string()));
+        broker_tx.send(BrokerEvent::Received("barracuda/passageway/4/command".to_string(), "Open sesame".to_string()));
+        broker_tx.send(BrokerEvent::Received("barracuda/output/2/set".to_string(), "{\"target_state\":\"High\",\"switch_time\":500}".to_string()));
+        for _ in 0..3
+        {
+            md.run();
+        }
+
+        assert_eq!(Some(PassagewayCommand::Block(4)), pway_command_rx.receive_with_timeout(0));
+        assert!(!pway_command_rx.has_data());
+        let switch = output_rx.receive_with_timeout(0).unwrap();
+        assert_eq!((2, OutputState::High, 500), (switch.output_id, switch.target_state, switch.switch_time));
+    }
+
+    #[test]
+    fn commands_are_ignored_by_default()
+    {
+        let (mut md, _broker, mut chm) = make_mod(MqttSettings::default());
+        let pway_command_rx = chm.get_receiver::<PassagewayCommand>();
+        let output_rx = chm.get_receiver::<OutputSwitch>();
+        let broker_tx = chm.get_topic_sender::<BrokerEvent>("barracuda");
+
+        broker_tx.send(BrokerEvent::Received("barracuda/passageway/4/command".to_string(), "Block".to_string()));
+        broker_tx.send(BrokerEvent::Received("barracuda/output/2/set".to_string(), "{\"target_state\":\"High\"}".to_string()));
+        for _ in 0..2
+        {
+            md.run();
+        }
+
+        assert!(!pway_command_rx.has_data());
+        assert!(!output_rx.has_data());
+    }
+
+    #[test]
+    fn tls_files_are_required()
+    {
+        let tls = |ca_file: Option<&str>, client_cert_file: Option<&str>, client_key_file: Option<&str>| MqttSettings
+        {
+            tls: Some(MqttTlsSettings { ca_file: ca_file.map(String::from), client_cert_file: client_cert_file.map(String::from), client_key_file: client_key_file.map(String::from) }),
+            ..MqttSettings::default()
+        };
+        let dir = TempDir::new("mqtt_tls");
+        fs::write(dir.file("ca.pem"), "").unwrap();
+
+        assert!(mqtt_options(&tls(None, None, None)).is_ok());
+        assert!(mqtt_options(&tls(Some(&dir.file("ca.pem")), None, None)).is_ok());
+        assert!(mqtt_options(&tls(Some(&dir.file("missing.pem")), None, None)).is_err());
+        assert!(mqtt_options(&tls(Some(&dir.file("ca.pem")), Some(&dir.file("ca.pem")), None)).is_err());
+        assert!(mqtt_options(&tls(None, Some(&dir.file("ca.pem")), Some(&dir.file("ca.pem")))).is_err());
+    }
+
+    #[test]
+    fn credentials_are_sent_if_set()
+    {
+        assert_eq!(None, mqtt_options(&MqttSettings::default()).unwrap().credentials());
+        let settings = MqttSettings { username: Some("gate".to_string()), password: Some("4711".to_string()), ..MqttSettings::default() };
+        assert_eq!(Some(("gate".to_string(), "4711".to_string())), mqtt_options(&settings).unwrap().credentials());
+    }
+
+    /// Needs a broker, e.g. mosquitto, listening on localhost:1883.
+    #[test]
+    #[ignore]
+    fn connects_to_local_broker()
+    {
+        let mut chm = ChannelManager::new();
+        let settings = MqttSettings { client_id: "barracuda-test".to_string(), ..MqttSettings::default() };
+        let broker_rx = chm.get_topic_receiver::<BrokerEvent>(&settings.client_id);
+        let (client, connection) = Client::new(mqtt_options(&settings).unwrap(), 10);
+        drive_connection(&mut chm, connection, &settings.client_id, settings.reconnect_delay);
+
+        assert_eq!(Some(BrokerEvent::Connected), broker_rx.receive_with_timeout(2000));
+        client.subscribe("barracuda/test", QoS::AtLeastOnce).unwrap();
+        Broker::publish(&client, "barracuda/test", "4711".to_string(), false);
+        assert_eq!(Some(BrokerEvent::Received("barracuda/test".to_string(), "4711".to_string())), broker_rx.receive_with_timeout(2000));
+    }
+}
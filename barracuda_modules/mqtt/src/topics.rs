@@ -0,0 +1,67 @@
+//! # Topic templates
+//! The topics used by the MQTT module are configured as templates,
+//! where "{id}" is replaced by the ID of the passageway, input or
+//! output the topic refers to, e.g. "barracuda/input/{id}".
+
+const ID_PLACEHOLDER: &str = "{id}";
+
+/// Yields the topic for the given ID.
+pub fn topic_for(template: &str, id: u32) -> String
+{
+    template.replace(ID_PLACEHOLDER, &id.to_string())
+}
+
+/// Yields the filter used to subscribe to the topics of all IDs.
+pub fn subscription_for(template: &str) -> String
+{
+    template.replace(ID_PLACEHOLDER, "+")
+}
+
+/// Yields the ID the topic refers to, None if the
+/// topic does not match the template.
+pub fn id_from_topic(template: &str, topic: &str) -> Option<u32>
+{
+    let template_levels: Vec<&str> = template.split('/').collect();
+    let topic_levels: Vec<&str> = topic.split('/').collect();
+    if template_levels.len() != topic_levels.len()
+    {
+        return None;
+    }
+
+    let mut id = None;
+    for (expected, actual) in template_levels.iter().zip(topic_levels.iter())
+    {
+        if *expected == ID_PLACEHOLDER
+        {
+            id = Some(actual.parse::<u32>().ok()?);
+        }
+        else if expected != actual
+        {
+            return None;
+        }
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_are_generated_from_template()
+    {
+        assert_eq!("barracuda/input/7", topic_for("barracuda/input/{id}", 7));
+        assert_eq!("barracuda/input/+", subscription_for("barracuda/input/{id}"));
+    }
+
+    #[test]
+    fn id_is_extracted_from_matching_topics_only()
+    {
+        let template = "barracuda/passageway/{id}/command";
+        assert_eq!(Some(4), id_from_topic(template, "barracuda/passageway/4/command"));
+        assert_eq!(None, id_from_topic(template, "barracuda/passageway/4/state"));
+        assert_eq!(None, id_from_topic(template, "barracuda/passageway/door/command"));
+        assert_eq!(None, id_from_topic(template, "barracuda/passageway/4"));
+        assert_eq!(None, id_from_topic("barracuda/events", "barracuda/events"));
+    }
+}
@@ -13,14 +13,21 @@ mod drivers;
 use drivers::*;
 
 fn main() {
-    // Note: Launch never returns!
-    launch!(barracuda_core::trace::launch,
+    // The device keeps working without the RFID reader (e.g. using the console),
+    // so a reader that fails to initialize must not stop the boot.
+    let mut boot_settings = core::supervisor::BootSettings::default();
+    boot_settings.optional_modules.push(mfrc522::MODULE_ID);
+
+    // Note: Launch returns only after the system was shut down,
+    // modules are stopped in reverse order.
+    launch!(settings: boot_settings, core::supervisor::HeartbeatSettings::default();
+            barracuda_core::trace::launch,
+            barracuda_core::io::launch,
             barracuda_core::cfg::rest::launch,
+            barracuda_core::profile::launch,
             generic_whitelist::launch::<generic_whitelist::whitelist::JsonEntryProvider>,
-            barracuda_core::arm::console_input::launch,
-            barracuda_core::io::launch,
             adcm::launch,            
-            |chm| mfrc522::launch(chm, RfidSpi::new(), RfidIrq::new()),
-            barracuda_core::profile::launch
+            barracuda_core::arm::console_input::launch,
+            |chm| mfrc522::launch(chm, RfidSpi::new(), RfidIrq::new())
             );    
 }
\ No newline at end of file
@@ -1,10 +1,10 @@
-use barracuda_base_modules::modcaps::{ModuleCapability, ModuleCapabilityAdvertisement};
+use barracuda_base_modules::modcaps::{ModuleCapability, ModuleCapabilityAdvertisement, INPUTS, OUTPUTS};
 use barracuda_core::{trace::trace_helper, 
     core::{
         broadcast_channel::{GenericReceiver, GenericSender}, 
-        channel_manager::ChannelManager, bootstage_helper::{boot, boot_noop}
+        channel_manager::ChannelManager, bootstage_helper::{boot, boot_noop, handle_system_message},
+        dependencies::Dependencies, module_thread::spawn_module, sud::Sud
         }};
-use std::{thread};
 
 const MODULE_ID: u32 = 0x09000000;
 
@@ -12,8 +12,15 @@ pub fn launch(chm: &mut ChannelManager)
 {    
     let tracer = trace_helper::TraceHelper::new("Plattform/Win32Io".to_string(), chm);
     let ioman = W32Io::new(tracer, chm);
-    thread::spawn(move || {  
+    spawn_module(MODULE_ID, "Plattform/Win32Io", chm, move || {  
         ioman.init();           
+        loop
+        {
+            if !ioman.do_system_event()
+            {
+                break;
+            }
+        }
     });
 }
 
@@ -47,14 +54,20 @@ impl W32Io
             */
             let m = ModuleCapabilityAdvertisement {
                 caps: vec![ModuleCapability::Outputs(3), ModuleCapability::Inputs(3)],
-                module_id: MODULE_ID
+                module_id: Sud::from(MODULE_ID)
             };
             modcaps_tx_clone.send(m);            
         });
 
-        boot(MODULE_ID, llicb, Some(boot_noop),
+        boot(MODULE_ID, Dependencies::new(&[INPUTS, OUTPUTS], &[]), llicb, Some(boot_noop),
             &self.system_events_tx, 
             &self.system_events_rx, 
             &self.tracer);
     }
-}
\ No newline at end of file
+
+    pub fn do_system_event(&self) -> bool
+    {
+        let msg = self.system_events_rx.receive();
+        handle_system_message(MODULE_ID, msg, &self.system_events_tx)
+    }
+}
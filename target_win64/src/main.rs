@@ -5,15 +5,22 @@ use barracuda_core::launch_impl;
 mod io;
 
 fn main() {
-    // Note: Launch never returns!
-    launch!(barracuda_core::trace::launch,
-            barracuda_base_modules::cfg::rest::launch,            
-            barracuda_base_modules::arm::console_input::launch,
+    // Note: Launch returns only after the system was shut down,
+    // modules are stopped in reverse order.
+    let result = launch!(barracuda_core::trace::launch,
+            crate::io::launch,
             barracuda_base_modules::io::launch,            
+            barracuda_base_modules::cfg::rest::launch,            
             barracuda_base_modules::profile::launch,
             barracuda_base_modules::events::launch,
+            barracuda_base_modules::recorder::launch,
             generic_whitelist::launch::<generic_whitelist::whitelist::JsonEntryProvider>,
             adcm::launch,
-            crate::io::launch
+            barracuda_base_modules::arm::console_input::launch
             );    
+    if let Err(e) = result
+    {
+        eprintln!("Failed to boot. {}", e);
+        std::process::exit(1);
+    }
 }
\ No newline at end of file